@@ -0,0 +1,5 @@
+use avr_emulator::instruction_set::*;
+
+fn main() {
+    print!("{}", instruction_table());
+}
@@ -13,7 +13,7 @@ fn screenshot<T: fmt::Display>(avr: &T) {
 fn main() {
     let hex = fs::read_to_string(SAMPLE_FILE_NAME).unwrap();
     let mut avr = ATmega328P::new(Package::PDIP28);
-    avr.program(hex);
+    avr.program(hex).unwrap();
     avr.initialize();
     screenshot(&avr);
 
@@ -0,0 +1,513 @@
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// ADMUX
+const ADLAR: u8 = 5;
+
+// ADCSRA
+const ADEN: u8 = 7;
+const ADSC: u8 = 6;
+const ADATE: u8 = 5;
+const ADIF: u8 = 4;
+
+// MUX3:0
+const TEMPERATURE_CHANNEL: u8 = 0b1000;
+const BANDGAP_CHANNEL: u8 = 0b1110;
+const GND_CHANNEL: u8 = 0b1111;
+
+pub(crate) const BANDGAP_VOLTAGE: f64 = 1.1;
+
+// Temperature sensor output voltage in volts at -45, 25 and 85 °C
+const TEMPERATURE_SENSOR: [(f64, f64); 3] = [(-45.0, 0.242), (25.0, 0.314), (85.0, 0.380)];
+
+// Analog voltages supplied by the host
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalogInputs {
+    pub avcc: f64,
+    pub aref: f64,
+    // ADC0 ~ ADC7
+    pub channels: [f64; 8],
+    // AIN0 and AIN1 of the analog comparator
+    pub ain: [f64; 2],
+    // °C
+    pub temperature: f64,
+}
+
+impl AnalogInputs {
+    fn temperature_voltage(&self) -> f64 {
+        let t = self.temperature;
+        let ((t0, v0), (t1, v1)) = if t < TEMPERATURE_SENSOR[1].0 {
+            (TEMPERATURE_SENSOR[0], TEMPERATURE_SENSOR[1])
+        } else {
+            (TEMPERATURE_SENSOR[1], TEMPERATURE_SENSOR[2])
+        };
+        v0 + (t - t0) * (v1 - v0) / (t1 - t0)
+    }
+}
+
+impl Default for AnalogInputs {
+    fn default() -> AnalogInputs {
+        AnalogInputs {
+            avcc: 5.0,
+            aref: 5.0,
+            channels: [0.0; 8],
+            ain: [0.0; 2],
+            temperature: 25.0,
+        }
+    }
+}
+
+struct Conversion {
+    // The input is sampled by the sample-and-hold circuit at `sample`.
+    sample: u64,
+    done: u64,
+    result: Option<u16>,
+}
+
+pub struct Adc {
+    sram: Rc<RefCell<SRAM>>,
+    last_cycle: u64,
+    pub inputs: AnalogInputs,
+    conversion: Option<Conversion>,
+    // The first conversion after ADEN is set takes 25 ADC clock cycles.
+    is_first: bool,
+    adif: bool,
+    // The data register is not updated after ADCL is read until ADCH is read.
+    is_locked: bool,
+    last_trigger: bool,
+
+    admux: RegisterAddr,
+    adcsra: RegisterAddr,
+    adcsrb: RegisterAddr,
+    adc: RegisterWordAddr,
+    // Flags of the auto trigger sources selected by ADTS2:0
+    triggers: [RegisterBitAddr; 8],
+}
+
+impl Adc {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        admux: RegisterAddr,
+        adcsra: RegisterAddr,
+        adcsrb: RegisterAddr,
+        adc: RegisterWordAddr,
+        triggers: [RegisterBitAddr; 8],
+    ) -> Adc {
+        Adc {
+            sram: sram,
+            last_cycle: 0,
+            inputs: AnalogInputs::default(),
+            conversion: None,
+            is_first: true,
+            adif: false,
+            is_locked: false,
+            last_trigger: false,
+            admux: admux,
+            adcsra: adcsra,
+            adcsrb: adcsrb,
+            adc: adc,
+            triggers: triggers,
+        }
+    }
+
+    // Analog inputs are kept.
+    pub fn initialize(&mut self) {
+        self.conversion = None;
+        self.is_first = true;
+        self.adif = false;
+        self.is_locked = false;
+        self.last_trigger = false;
+        self.last_cycle = 0;
+    }
+
+    fn admux(&self) -> u8 {
+        self.sram.borrow().get(self.admux)
+    }
+
+    fn adcsra(&self) -> u8 {
+        self.sram.borrow().get(self.adcsra)
+    }
+
+    fn is_enabled(&self) -> bool {
+        bit(self.adcsra(), ADEN)
+    }
+
+    // ADC clock = clk_io / 2 ~ 128
+    fn prescaler(&self) -> u64 {
+        match self.adcsra() & 0b111 {
+            0 => 2,
+            n => 1 << n,
+        }
+    }
+
+    fn reference(&self) -> f64 {
+        match self.admux() >> 6 {
+            0b01 => self.inputs.avcc,
+            0b11 => BANDGAP_VOLTAGE,
+            _ => self.inputs.aref,
+        }
+    }
+
+    fn input(&self) -> f64 {
+        match self.admux() & 0b1111 {
+            n @ 0..=7 => self.inputs.channels[n as usize],
+            TEMPERATURE_CHANNEL => self.inputs.temperature_voltage(),
+            BANDGAP_CHANNEL => BANDGAP_VOLTAGE,
+            GND_CHANNEL => 0.0,
+            _ => 0.0,
+        }
+    }
+
+    // ADC = Vin * 1024 / Vref
+    fn convert(&self) -> u16 {
+        let result = self.input() * 1024.0 / self.reference();
+        result.max(0.0).min(1023.0) as u16
+    }
+
+    // Start a conversion unless one is in progress. A normal conversion
+    // takes 13 ADC clock cycles, 25 for the first one and 13.5 when it is
+    // auto triggered.
+    pub fn start(&mut self, cycle: u64, is_auto_triggered: bool) {
+        if self.conversion.is_some() || !self.is_enabled() {
+            return;
+        }
+        let prescaler = self.prescaler();
+        let (sample, done) = if self.is_first {
+            (27 * prescaler / 2, 25 * prescaler)
+        } else if is_auto_triggered {
+            (2 * prescaler, 27 * prescaler / 2)
+        } else {
+            (3 * prescaler / 2, 13 * prescaler)
+        };
+        self.is_first = false;
+        self.conversion = Some(Conversion {
+            sample: cycle + sample,
+            done: cycle + done,
+            result: None,
+        });
+    }
+
+    fn access_adcsra(&mut self, cycle: u64) {
+        let (is_written, adcsra) = {
+            let sram = self.sram.borrow();
+            (sram.is_written(self.adcsra), sram.get(self.adcsra))
+        };
+        if !bit(adcsra, ADEN) {
+            // Disabling the ADC terminates an ongoing conversion.
+            self.conversion = None;
+            self.is_first = true;
+        }
+        if is_written {
+            // ADIF is cleared by writing one to it.
+            self.adif &= !bit(adcsra, ADIF);
+            if bit(adcsra, ADSC) {
+                self.start(cycle, false);
+            }
+        } else {
+            // ADIF is cleared by executing the interrupt.
+            self.adif = bit(adcsra, ADIF);
+        }
+    }
+
+    fn access_adc(&mut self) {
+        let sram = self.sram.borrow();
+        if sram.is_read(self.adc.1) {
+            self.is_locked = true;
+        }
+        if sram.is_read(self.adc.0) {
+            self.is_locked = false;
+        }
+    }
+
+    // A conversion is started on a rising edge of the selected trigger
+    // source. The ADC interrupt flag as a source makes the ADC free running,
+    // a new conversion starts as soon as the ongoing one is completed.
+    fn auto_trigger(&mut self, cycle: u64, is_completed: bool) {
+        let (adts, trigger) = {
+            let sram = self.sram.borrow();
+            let adts = sram.get(self.adcsrb) & 0b111;
+            (adts, sram.get_bit(self.triggers[adts as usize]))
+        };
+        let is_rising = trigger && !self.last_trigger;
+        self.last_trigger = trigger;
+        if !bit(self.adcsra(), ADATE) {
+            return;
+        }
+        if (adts == 0 && is_completed) || (adts != 0 && is_rising) {
+            self.start(cycle, true);
+        }
+    }
+
+    // Returns true if a conversion is completed.
+    fn next_conversion(&mut self, cycle: u64) -> bool {
+        let (sample, done) = match &self.conversion {
+            Some(c) => (c.sample, c.done),
+            None => return false,
+        };
+        if cycle >= sample {
+            let result = self.convert();
+            let conversion = self.conversion.as_mut().unwrap();
+            conversion.result.get_or_insert(result);
+        }
+        if cycle >= done {
+            let result = self.conversion.take().unwrap().result.unwrap();
+            self.adif = true;
+            if !self.is_locked {
+                let adc = if bit(self.admux(), ADLAR) {
+                    result << 6
+                } else {
+                    result
+                };
+                self.sram.borrow_mut().set_word(self.adc, adc);
+            }
+            return true;
+        }
+        false
+    }
+
+    // ADSC reads as one while a conversion is in progress.
+    fn update_registers(&mut self) {
+        let mut sram = self.sram.borrow_mut();
+        let adcsra = (sram.get(self.adcsra) & 0b1010_1111)
+            | (self.conversion.is_some() as u8) << ADSC
+            | (self.adif as u8) << ADIF;
+        sram.set(self.adcsra, adcsra);
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_adcsra(cycle);
+        self.access_adc();
+        let is_completed = self.next_conversion(cycle);
+        self.auto_trigger(cycle, is_completed);
+        self.update_registers();
+        self.last_cycle = cycle;
+    }
+
+    // The ADC clock is stopped by a sleep mode or PRR, an ongoing
+    // conversion is delayed.
+    pub fn pause(&mut self, cycle: u64) {
+        let diff = cycle - self.last_cycle;
+        if let Some(conversion) = self.conversion.as_mut() {
+            conversion.sample += diff;
+            conversion.done += diff;
+        }
+        self.last_cycle = cycle;
+    }
+}
+
+impl fmt::Display for Adc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "adc =====
+    admux: {:08b},    adcsra: {:08b},    adc: {:04x},    converting: {}",
+            self.admux(),
+            self.adcsra(),
+            self.sram.borrow().get_word(self.adc),
+            self.conversion.is_some(),
+        )
+    }
+}
+
+#[cfg(test)]
+use super::arch::atmega328p::{
+    ADC_TRIGGERS, REGISTER_BIT_MAP, REGISTER_MAP, REGISTER_WORD_MAP, SRAM_SIZE,
+};
+
+#[cfg(test)]
+fn new_adc() -> (Rc<RefCell<SRAM>>, Adc) {
+    let sram = Rc::new(RefCell::new(SRAM::new(
+        SRAM_SIZE,
+        &REGISTER_MAP,
+        &REGISTER_WORD_MAP,
+        &REGISTER_BIT_MAP,
+    )));
+    let adc = Adc::new(
+        Rc::clone(&sram),
+        REGISTER_MAP.admux,
+        REGISTER_MAP.adcsra,
+        REGISTER_MAP.adcsrb,
+        REGISTER_WORD_MAP.adc,
+        ADC_TRIGGERS,
+    );
+    (sram, adc)
+}
+
+// Access a register by an instruction at `cycle` and run the ADC.
+#[cfg(test)]
+fn write(sram: &Rc<RefCell<SRAM>>, adc: &mut Adc, cycle: u64, a: RegisterAddr, v: u8) {
+    sram.borrow_mut().start_tracing();
+    sram.borrow_mut().set(a, v);
+    sram.borrow_mut().stop_tracing();
+    adc.next(cycle);
+}
+
+#[cfg(test)]
+fn read(sram: &Rc<RefCell<SRAM>>, adc: &mut Adc, cycle: u64, a: RegisterAddr) -> u8 {
+    sram.borrow_mut().start_tracing();
+    let v = sram.borrow().get(a);
+    sram.borrow_mut().stop_tracing();
+    adc.next(cycle);
+    v
+}
+
+// Run the ADC from `cycle` until ADIF is set and return the cycle.
+#[cfg(test)]
+fn wait_adif(sram: &Rc<RefCell<SRAM>>, adc: &mut Adc, cycle: u64) -> u64 {
+    sram.borrow_mut().start_tracing();
+    sram.borrow_mut().stop_tracing();
+    (cycle + 1..cycle + 10_000)
+        .find(|c| {
+            adc.next(*c);
+            sram.borrow().get_bit(REGISTER_BIT_MAP.adif)
+        })
+        .unwrap()
+}
+
+// ADC clock = clk_io / 4
+#[cfg(test)]
+const ADEN_ADPS4: u8 = 1 << ADEN | 0b010;
+
+#[test]
+fn test_conversion_time() {
+    let (sram, mut adc) = new_adc();
+    let adcsra = REGISTER_MAP.adcsra;
+
+    // The first conversion takes 25 ADC clock cycles.
+    write(&sram, &mut adc, 0, adcsra, ADEN_ADPS4 | 1 << ADSC);
+    assert!(bit(sram.borrow().get(adcsra), ADSC));
+    assert_eq!(wait_adif(&sram, &mut adc, 0), 100);
+    assert!(!bit(sram.borrow().get(adcsra), ADSC));
+
+    // ADIF is cleared by writing one to it, then a normal conversion takes
+    // 13 ADC clock cycles.
+    write(&sram, &mut adc, 200, adcsra, ADEN_ADPS4 | 1 << ADIF);
+    assert!(!bit(sram.borrow().get(adcsra), ADIF));
+    write(&sram, &mut adc, 300, adcsra, ADEN_ADPS4 | 1 << ADSC);
+    assert_eq!(wait_adif(&sram, &mut adc, 300), 352);
+
+    // Writing zero to ADIF keeps it.
+    write(&sram, &mut adc, 400, adcsra, ADEN_ADPS4);
+    assert!(bit(sram.borrow().get(adcsra), ADIF));
+
+    // An auto triggered conversion takes 13.5 ADC clock cycles from the
+    // rising edge of TOV0.
+    write(&sram, &mut adc, 500, REGISTER_MAP.adcsrb, 0b100);
+    write(
+        &sram,
+        &mut adc,
+        501,
+        adcsra,
+        ADEN_ADPS4 | 1 << ADATE | 1 << ADIF,
+    );
+    write(&sram, &mut adc, 600, REGISTER_MAP.tifr0, 0b1);
+    assert_eq!(wait_adif(&sram, &mut adc, 600), 654);
+}
+
+#[test]
+fn test_free_running() {
+    let (sram, mut adc) = new_adc();
+    let adcsra = REGISTER_MAP.adcsra;
+    adc.inputs.channels[0] = 1.0;
+
+    // ADTS2:0 = 0, the first conversion is started by ADSC.
+    write(
+        &sram,
+        &mut adc,
+        0,
+        adcsra,
+        ADEN_ADPS4 | 1 << ADATE | 1 << ADSC,
+    );
+    assert_eq!(wait_adif(&sram, &mut adc, 0), 100);
+    assert_eq!(sram.borrow().get_word(REGISTER_WORD_MAP.adc), 204);
+
+    // The next conversions follow without ADSC being written.
+    adc.inputs.channels[0] = 2.0;
+    write(
+        &sram,
+        &mut adc,
+        101,
+        adcsra,
+        ADEN_ADPS4 | 1 << ADATE | 1 << ADIF,
+    );
+    assert!(bit(sram.borrow().get(adcsra), ADSC));
+    assert_eq!(wait_adif(&sram, &mut adc, 101), 154);
+    assert_eq!(sram.borrow().get_word(REGISTER_WORD_MAP.adc), 409);
+}
+
+#[test]
+fn test_channels_and_references() {
+    let (sram, mut adc) = new_adc();
+    adc.inputs.channels[3] = 2.0;
+    adc.inputs.aref = 2.5;
+
+    // (ADMUX, ADC)
+    let cases = [
+        (0b0000_0011, 819),      // ADC3 / AREF
+        (0b0100_0011, 409),      // ADC3 / AVCC
+        (0b1100_0011, 1023),     // ADC3 / 1.1 V, clipped
+        (0b1100_1000, 292),      // Temperature sensor (25 °C, 0.314 V) / 1.1 V
+        (0b0100_1110, 225),      // Bandgap (1.1 V) / AVCC
+        (0b0100_1111, 0),        // GND / AVCC
+        (0b0110_0011, 409 << 6), // Left adjusted
+    ];
+    let mut cycle = 0;
+    for (admux, result) in cases.iter() {
+        write(&sram, &mut adc, cycle, REGISTER_MAP.admux, *admux);
+        write(
+            &sram,
+            &mut adc,
+            cycle + 1,
+            REGISTER_MAP.adcsra,
+            ADEN_ADPS4 | 1 << ADSC,
+        );
+        cycle = wait_adif(&sram, &mut adc, cycle + 1) + 1;
+        assert_eq!(sram.borrow().get_word(REGISTER_WORD_MAP.adc), *result);
+        write(
+            &sram,
+            &mut adc,
+            cycle,
+            REGISTER_MAP.adcsra,
+            ADEN_ADPS4 | 1 << ADIF,
+        );
+        cycle += 1;
+    }
+}
+
+#[test]
+fn test_data_register_lock() {
+    let (sram, mut adc) = new_adc();
+    let (adch, adcl) = REGISTER_WORD_MAP.adc;
+    let adcsra = REGISTER_MAP.adcsra;
+    adc.inputs.channels[0] = 1.0;
+    write(&sram, &mut adc, 0, adcsra, ADEN_ADPS4 | 1 << ADSC);
+    wait_adif(&sram, &mut adc, 0);
+
+    // After ADCL is read, the result of the next conversion is lost.
+    assert_eq!(read(&sram, &mut adc, 200, adcl), 204);
+    adc.inputs.channels[0] = 2.0;
+    write(
+        &sram,
+        &mut adc,
+        201,
+        adcsra,
+        ADEN_ADPS4 | 1 << ADSC | 1 << ADIF,
+    );
+    wait_adif(&sram, &mut adc, 201);
+    assert_eq!(sram.borrow().get_word(REGISTER_WORD_MAP.adc), 204);
+
+    // Reading ADCH unlocks the data register.
+    assert_eq!(read(&sram, &mut adc, 300, adch), 0);
+    write(
+        &sram,
+        &mut adc,
+        301,
+        adcsra,
+        ADEN_ADPS4 | 1 << ADSC | 1 << ADIF,
+    );
+    wait_adif(&sram, &mut adc, 301);
+    assert_eq!(sram.borrow().get_word(REGISTER_WORD_MAP.adc), 409);
+}
@@ -0,0 +1,130 @@
+use super::adc::*;
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// ACSR
+const ACD: u8 = 7;
+const ACBG: u8 = 6;
+const ACO: u8 = 5;
+const ACI: u8 = 4;
+
+// ADCSRA
+const ADEN: u8 = 7;
+
+// ADCSRB
+const ACME: u8 = 6;
+
+pub struct AnalogComparator {
+    sram: Rc<RefCell<SRAM>>,
+    aco: bool,
+    aci: bool,
+
+    acsr: RegisterAddr,
+    admux: RegisterAddr,
+    adcsra: RegisterAddr,
+    adcsrb: RegisterAddr,
+}
+
+impl AnalogComparator {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        acsr: RegisterAddr,
+        admux: RegisterAddr,
+        adcsra: RegisterAddr,
+        adcsrb: RegisterAddr,
+    ) -> AnalogComparator {
+        AnalogComparator {
+            sram: sram,
+            aco: false,
+            aci: false,
+            acsr: acsr,
+            admux: admux,
+            adcsra: adcsra,
+            adcsrb: adcsrb,
+        }
+    }
+
+    pub fn initialize(&mut self) {
+        self.aco = false;
+        self.aci = false;
+    }
+
+    fn acsr(&self) -> u8 {
+        self.sram.borrow().get(self.acsr)
+    }
+
+    // The bandgap reference replaces AIN0 when ACBG is set.
+    fn positive_input(&self, inputs: &AnalogInputs) -> f64 {
+        if bit(self.acsr(), ACBG) {
+            BANDGAP_VOLTAGE
+        } else {
+            inputs.ain[0]
+        }
+    }
+
+    // ADC0 ~ ADC7 selected by MUX2:0 replace AIN1 when ACME is set and the
+    // ADC is switched off.
+    fn negative_input(&self, inputs: &AnalogInputs) -> f64 {
+        let sram = self.sram.borrow();
+        if bit(sram.get(self.adcsrb), ACME) && !bit(sram.get(self.adcsra), ADEN) {
+            inputs.channels[(sram.get(self.admux) & 0b111) as usize]
+        } else {
+            inputs.ain[1]
+        }
+    }
+
+    // ACI is cleared by writing one to it or by executing the interrupt.
+    fn access_acsr(&mut self) {
+        let sram = self.sram.borrow();
+        if sram.is_written(self.acsr) {
+            self.aci &= !sram.get_bit((self.acsr, ACI));
+        } else {
+            self.aci = sram.get_bit((self.acsr, ACI));
+        }
+    }
+
+    // ACIS1:0 selects the output toggle, the falling edge or the rising
+    // edge for the interrupt.
+    fn is_interrupt_edge(&self, aco: bool) -> bool {
+        match self.acsr() & 0b11 {
+            0b00 => aco != self.aco,
+            0b10 => self.aco && !aco,
+            0b11 => !self.aco && aco,
+            _ => false,
+        }
+    }
+
+    // The comparator is not clocked, so that it keeps running in all sleep
+    // modes unless ACD is set.
+    pub fn next(&mut self, inputs: &AnalogInputs) {
+        self.access_acsr();
+        if !bit(self.acsr(), ACD) {
+            let aco = self.positive_input(inputs) > self.negative_input(inputs);
+            if self.is_interrupt_edge(aco) {
+                self.aci = true;
+            }
+            self.aco = aco;
+        }
+
+        let mut sram = self.sram.borrow_mut();
+        let acsr =
+            (sram.get(self.acsr) & 0b1100_1111) | (self.aco as u8) << ACO | (self.aci as u8) << ACI;
+        sram.set(self.acsr, acsr);
+    }
+}
+
+impl fmt::Display for AnalogComparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "analog comparator =====
+    acsr: {:08b},    aco: {},    aci: {}",
+            self.acsr(),
+            self.aco,
+            self.aci,
+        )
+    }
+}
@@ -1,23 +1,98 @@
+use super::super::adc::*;
+use super::super::analog_comparator::*;
 use super::super::avrmcu::*;
+use super::super::clock::*;
+use super::super::eeprom::*;
+use super::super::eeprom_controller::*;
+use super::super::external_interrupt::*;
 use super::super::flash_memory::*;
 use super::super::instruction::*;
+use super::super::interrupt::*;
 use super::super::io_port::*;
 use super::super::opcode_tree::*;
+use super::super::self_programming::*;
+use super::super::spi::*;
 use super::super::sram::*;
 use super::super::timer16bit::*;
 use super::super::timer8bit::*;
+use super::super::twi::*;
+use super::super::usart::*;
 use super::super::util::bit::*;
+use super::super::watchdog::*;
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt;
 use std::rc::Rc;
+use std::time::Duration;
 
-const FLASH_MEMORY_SIZE: usize = 0x8000;
-const SRAM_SIZE: usize = 0x900;
+// 32 KB (16K words)
+const FLASH_MEMORY_SIZE: usize = 0x4000;
+pub(crate) const SRAM_SIZE: usize = 0x900;
+// 1 KB
+const EEPROM_SIZE: usize = 0x400;
 
-const REGISTER_MAP: RegisterMap = RegisterMap {
+// SS, MOSI, MISO and SCK on PORT B
+const SPI_PINS: [u8; 4] = [2, 3, 4, 5];
+
+// DDR bits of the output compare pins OCnA and OCnB
+const OC0_PINS: [RegisterBitAddr; 2] = [(REGISTER_MAP.ddrd, 6), (REGISTER_MAP.ddrd, 5)];
+const OC1_PINS: [RegisterBitAddr; 2] = [(REGISTER_MAP.ddrb, 1), (REGISTER_MAP.ddrb, 2)];
+const OC2_PINS: [RegisterBitAddr; 2] = [(REGISTER_MAP.ddrb, 3), (REGISTER_MAP.ddrd, 3)];
+
+// INT0 (PD2) and INT1 (PD3)
+const INT_PINS: [RegisterBitAddr; 2] = [(REGISTER_MAP.pind, 2), (REGISTER_MAP.pind, 3)];
+
+// PCINT0 ~ 7 on PORT B, PCINT8 ~ 14 on PORT C and PCINT16 ~ 23 on PORT D
+const PCINT_PORTS: [RegisterAddr; 3] = [REGISTER_MAP.pinb, REGISTER_MAP.pinc, REGISTER_MAP.pind];
+
+// Package pins (0-origin) of PORT B, C and D on PDIP28. PORT C has no PC7.
+const PDIP28_PORTB: [usize; 8] = [13, 14, 15, 16, 17, 18, 8, 9];
+const PDIP28_PORTC: [usize; 7] = [22, 23, 24, 25, 26, 27, 0];
+const PDIP28_PORTD: [usize; 8] = [1, 2, 3, 4, 5, 10, 11, 12];
+
+// Pin events not taken by the host are dropped from the oldest.
+const MAX_PIN_EVENTS: usize = 1024;
+
+// Input capture pin of Timer1 (PB0)
+const ICP1_PIN: RegisterBitAddr = (REGISTER_MAP.pinb, 0);
+
+// ADC auto trigger sources selected by ADTS2:0
+pub(crate) const ADC_TRIGGERS: [RegisterBitAddr; 8] = [
+    REGISTER_BIT_MAP.adif,  // Free running mode
+    REGISTER_BIT_MAP.aci,   // Analog comparator
+    REGISTER_BIT_MAP.intf0, // External interrupt request 0
+    REGISTER_BIT_MAP.ocf0a, // Timer/Counter0 compare match A
+    REGISTER_BIT_MAP.tov0,  // Timer/Counter0 overflow
+    REGISTER_BIT_MAP.ocf1b, // Timer/Counter1 compare match B
+    REGISTER_BIT_MAP.tov1,  // Timer/Counter1 overflow
+    REGISTER_BIT_MAP.icf1,  // Timer/Counter1 capture event
+];
+
+// Self-programming
+const SPM_PAGE_SIZE: usize = 64; // words
+const NRWW_START: usize = 0x3800;
+
+// Arduino Uno fuses, except that BOOTRST is unprogrammed so that
+// sketches without a bootloader start from 0x0000.
+const DEFAULT_FUSES: Fuses = Fuses {
+    low: 0xff,
+    high: 0xdf,
+    extended: 0xfd,
+};
+
+pub(crate) const REGISTER_MAP: RegisterMap = RegisterMap {
     sreg: 0x5f,
     sph: 0x5e,
     spl: 0x5d,
+    eind: 0x5c,  // reserved, ATmega328P has no EIND
+    rampz: 0x5b, // reserved, ATmega328P has no RAMPZ
+    spmcsr: 0x57,
+
+    // Power management
+    smcr: 0x53,
+    mcucr: 0x55,
+    clkpr: 0x61,
+    prr: 0x64,
 
     // Timer 0 (8-bit)
     tcnt0: 0x46,
@@ -67,9 +142,48 @@ const REGISTER_MAP: RegisterMap = RegisterMap {
     ucsr0a: 0xc0,
     ucsr0b: 0xc1,
     ucsr0c: 0xc2,
+    udr0: 0xc6,
+
+    // SPI
+    spcr: 0x4c,
+    spsr: 0x4d,
+    spdr: 0x4e,
+
+    // TWI
+    twbr: 0xb8,
+    twcr: 0xbc,
+    twamr: 0xbd,
+
+    // ADC
+    admux: 0x7c,
+    adcsra: 0x7a,
+    adcsrb: 0x7b,
+
+    // Analog comparator
+    acsr: 0x50,
+
+    // External interrupts
+    eicra: 0x69,
+    eimsk: 0x3d,
+    eifr: 0x3c,
+    pcicr: 0x68,
+    pcifr: 0x3b,
+    pcmsk0: 0x6b,
+    pcmsk1: 0x6c,
+    pcmsk2: 0x6d,
+
+    // EEPROM
+    eecr: 0x3f,
+    eedr: 0x40,
+
+    // Watchdog
+    wdtcsr: 0x60,
+
+    // Timer 2 asynchronous status
+    assr: 0xb6,
 };
 
-const REGISTER_BIT_MAP: RegisterBitMap = RegisterBitMap {
+pub(crate) const REGISTER_BIT_MAP: RegisterBitMap = RegisterBitMap {
     c: (REGISTER_MAP.sreg, 0),
     z: (REGISTER_MAP.sreg, 1),
     n: (REGISTER_MAP.sreg, 2),
@@ -79,23 +193,99 @@ const REGISTER_BIT_MAP: RegisterBitMap = RegisterBitMap {
     t: (REGISTER_MAP.sreg, 6),
     i: (REGISTER_MAP.sreg, 7),
 
+    // Self-programming
+    spmie: (REGISTER_MAP.spmcsr, 7),
+    rwwsb: (REGISTER_MAP.spmcsr, 6),
+    selfprgen: (REGISTER_MAP.spmcsr, 0),
+
+    // Power management
+    se: (REGISTER_MAP.smcr, 0),
+    prtim0: (REGISTER_MAP.prr, 5),
+    prtim1: (REGISTER_MAP.prr, 3),
+    prtim2: (REGISTER_MAP.prr, 6),
+    prusart0: (REGISTER_MAP.prr, 1),
+    prspi: (REGISTER_MAP.prr, 2),
+    prtwi: (REGISTER_MAP.prr, 7),
+    pradc: (REGISTER_MAP.prr, 0),
+
+    // I/O ports
+    pud: (REGISTER_MAP.mcucr, 4),
+
+    // USART 0
+    rxc0: (REGISTER_MAP.ucsr0a, 7),
+    txc0: (REGISTER_MAP.ucsr0a, 6),
+    udre0: (REGISTER_MAP.ucsr0a, 5),
+    rxcie0: (REGISTER_MAP.ucsr0b, 7),
+    txcie0: (REGISTER_MAP.ucsr0b, 6),
+    udrie0: (REGISTER_MAP.ucsr0b, 5),
+
+    // SPI
+    spif: (REGISTER_MAP.spsr, 7),
+    spie: (REGISTER_MAP.spcr, 7),
+
+    // TWI
+    twint: (REGISTER_MAP.twcr, 7),
+    twie: (REGISTER_MAP.twcr, 0),
+
+    // ADC
+    adif: (REGISTER_MAP.adcsra, 4),
+    adie: (REGISTER_MAP.adcsra, 3),
+
+    // Analog comparator
+    aci: (REGISTER_MAP.acsr, 4),
+    aco: (REGISTER_MAP.acsr, 5),
+    acie: (REGISTER_MAP.acsr, 3),
+    acic: (REGISTER_MAP.acsr, 2),
+
+    // External interrupts
+    int0: (REGISTER_MAP.eimsk, 0),
+    int1: (REGISTER_MAP.eimsk, 1),
+    intf0: (REGISTER_MAP.eifr, 0),
+    intf1: (REGISTER_MAP.eifr, 1),
+    pcie0: (REGISTER_MAP.pcicr, 0),
+    pcie1: (REGISTER_MAP.pcicr, 1),
+    pcie2: (REGISTER_MAP.pcicr, 2),
+    pcif0: (REGISTER_MAP.pcifr, 0),
+    pcif1: (REGISTER_MAP.pcifr, 1),
+    pcif2: (REGISTER_MAP.pcifr, 2),
+
+    // EEPROM
+    eepe: (REGISTER_MAP.eecr, 1),
+    eerie: (REGISTER_MAP.eecr, 3),
+
+    // Watchdog
+    wdif: (REGISTER_MAP.wdtcsr, 7),
+    wdie: (REGISTER_MAP.wdtcsr, 6),
+    wdrf: (REGISTER_MAP.mcusr, 3),
+
     // Timer 0
     tov0: (REGISTER_MAP.tifr0, 0),
     ocf0a: (REGISTER_MAP.tifr0, 1),
     ocf0b: (REGISTER_MAP.tifr0, 2),
+    toie0: (REGISTER_MAP.timsk0, 0),
+    ocie0a: (REGISTER_MAP.timsk0, 1),
+    ocie0b: (REGISTER_MAP.timsk0, 2),
 
     // Timer 1
     tov1: (REGISTER_MAP.tifr1, 0),
     ocf1a: (REGISTER_MAP.tifr1, 1),
     ocf1b: (REGISTER_MAP.tifr1, 2),
+    icf1: (REGISTER_MAP.tifr1, 5),
+    toie1: (REGISTER_MAP.timsk1, 0),
+    ocie1a: (REGISTER_MAP.timsk1, 1),
+    ocie1b: (REGISTER_MAP.timsk1, 2),
+    icie1: (REGISTER_MAP.timsk1, 5),
 
     // Timer 2
     tov2: (REGISTER_MAP.tifr2, 0),
     ocf2a: (REGISTER_MAP.tifr2, 1),
     ocf2b: (REGISTER_MAP.tifr2, 2),
+    toie2: (REGISTER_MAP.timsk2, 0),
+    ocie2a: (REGISTER_MAP.timsk2, 1),
+    ocie2b: (REGISTER_MAP.timsk2, 2),
 };
 
-const REGISTER_WORD_MAP: RegisterWordMap = RegisterWordMap {
+pub(crate) const REGISTER_WORD_MAP: RegisterWordMap = RegisterWordMap {
     sp: (REGISTER_MAP.sph, REGISTER_MAP.spl),
     x: (27, 26),
     y: (29, 28),
@@ -106,8 +296,203 @@ const REGISTER_WORD_MAP: RegisterWordMap = RegisterWordMap {
     ocr1a: (0x89, 0x88),
     ocr1b: (0x8b, 0x8a),
     icr1: (0x87, 0x86),
+
+    // USART 0
+    ubrr0: (0xc5, 0xc4),
+
+    // ADC
+    adc: (0x79, 0x78),
+
+    // EEPROM
+    eear: (0x42, 0x41),
 };
 
+// Interrupt vectors in order of priority.
+// 0x0000 (RESET) is handled by initialize() and is not listed here.
+const INTERRUPT_TABLE: [Interrupt; 25] = [
+    // INT0
+    Interrupt {
+        addr: 0x0002,
+        enable: REGISTER_BIT_MAP.int0,
+        flag: REGISTER_BIT_MAP.intf0,
+        trigger: Trigger::FlagOrLowLevel {
+            pin: INT_PINS[0],
+            isc: (REGISTER_MAP.eicra, 0),
+        },
+    },
+    // INT1
+    Interrupt {
+        addr: 0x0004,
+        enable: REGISTER_BIT_MAP.int1,
+        flag: REGISTER_BIT_MAP.intf1,
+        trigger: Trigger::FlagOrLowLevel {
+            pin: INT_PINS[1],
+            isc: (REGISTER_MAP.eicra, 2),
+        },
+    },
+    // PCINT0
+    Interrupt {
+        addr: 0x0006,
+        enable: REGISTER_BIT_MAP.pcie0,
+        flag: REGISTER_BIT_MAP.pcif0,
+        trigger: Trigger::Flag,
+    },
+    // PCINT1
+    Interrupt {
+        addr: 0x0008,
+        enable: REGISTER_BIT_MAP.pcie1,
+        flag: REGISTER_BIT_MAP.pcif1,
+        trigger: Trigger::Flag,
+    },
+    // PCINT2
+    Interrupt {
+        addr: 0x000a,
+        enable: REGISTER_BIT_MAP.pcie2,
+        flag: REGISTER_BIT_MAP.pcif2,
+        trigger: Trigger::Flag,
+    },
+    // WDT
+    Interrupt {
+        addr: 0x000c,
+        enable: REGISTER_BIT_MAP.wdie,
+        flag: REGISTER_BIT_MAP.wdif,
+        trigger: Trigger::Flag,
+    },
+    // TIMER2 COMPA
+    Interrupt {
+        addr: 0x000e,
+        enable: REGISTER_BIT_MAP.ocie2a,
+        flag: REGISTER_BIT_MAP.ocf2a,
+        trigger: Trigger::Flag,
+    },
+    // TIMER2 COMPB
+    Interrupt {
+        addr: 0x0010,
+        enable: REGISTER_BIT_MAP.ocie2b,
+        flag: REGISTER_BIT_MAP.ocf2b,
+        trigger: Trigger::Flag,
+    },
+    // TIMER2 OVF
+    Interrupt {
+        addr: 0x0012,
+        enable: REGISTER_BIT_MAP.toie2,
+        flag: REGISTER_BIT_MAP.tov2,
+        trigger: Trigger::Flag,
+    },
+    // TIMER1 CAPT
+    Interrupt {
+        addr: 0x0014,
+        enable: REGISTER_BIT_MAP.icie1,
+        flag: REGISTER_BIT_MAP.icf1,
+        trigger: Trigger::Flag,
+    },
+    // TIMER1 COMPA
+    Interrupt {
+        addr: 0x0016,
+        enable: REGISTER_BIT_MAP.ocie1a,
+        flag: REGISTER_BIT_MAP.ocf1a,
+        trigger: Trigger::Flag,
+    },
+    // TIMER1 COMPB
+    Interrupt {
+        addr: 0x0018,
+        enable: REGISTER_BIT_MAP.ocie1b,
+        flag: REGISTER_BIT_MAP.ocf1b,
+        trigger: Trigger::Flag,
+    },
+    // TIMER1 OVF
+    Interrupt {
+        addr: 0x001a,
+        enable: REGISTER_BIT_MAP.toie1,
+        flag: REGISTER_BIT_MAP.tov1,
+        trigger: Trigger::Flag,
+    },
+    // TIMER0 COMPA
+    Interrupt {
+        addr: 0x001c,
+        enable: REGISTER_BIT_MAP.ocie0a,
+        flag: REGISTER_BIT_MAP.ocf0a,
+        trigger: Trigger::Flag,
+    },
+    // TIMER0 COMPB
+    Interrupt {
+        addr: 0x001e,
+        enable: REGISTER_BIT_MAP.ocie0b,
+        flag: REGISTER_BIT_MAP.ocf0b,
+        trigger: Trigger::Flag,
+    },
+    // TIMER0 OVF
+    Interrupt {
+        addr: 0x0020,
+        enable: REGISTER_BIT_MAP.toie0,
+        flag: REGISTER_BIT_MAP.tov0,
+        trigger: Trigger::Flag,
+    },
+    // SPI STC
+    Interrupt {
+        addr: 0x0022,
+        enable: REGISTER_BIT_MAP.spie,
+        flag: REGISTER_BIT_MAP.spif,
+        trigger: Trigger::Flag,
+    },
+    // USART RX
+    Interrupt {
+        addr: 0x0024,
+        enable: REGISTER_BIT_MAP.rxcie0,
+        flag: REGISTER_BIT_MAP.rxc0,
+        trigger: Trigger::Level,
+    },
+    // USART UDRE
+    Interrupt {
+        addr: 0x0026,
+        enable: REGISTER_BIT_MAP.udrie0,
+        flag: REGISTER_BIT_MAP.udre0,
+        trigger: Trigger::Level,
+    },
+    // USART TX
+    Interrupt {
+        addr: 0x0028,
+        enable: REGISTER_BIT_MAP.txcie0,
+        flag: REGISTER_BIT_MAP.txc0,
+        trigger: Trigger::Flag,
+    },
+    // ADC
+    Interrupt {
+        addr: 0x002a,
+        enable: REGISTER_BIT_MAP.adie,
+        flag: REGISTER_BIT_MAP.adif,
+        trigger: Trigger::Flag,
+    },
+    // EE READY
+    Interrupt {
+        addr: 0x002c,
+        enable: REGISTER_BIT_MAP.eerie,
+        flag: REGISTER_BIT_MAP.eepe,
+        trigger: Trigger::LevelLow,
+    },
+    // ANALOG COMP
+    Interrupt {
+        addr: 0x002e,
+        enable: REGISTER_BIT_MAP.acie,
+        flag: REGISTER_BIT_MAP.aci,
+        trigger: Trigger::Flag,
+    },
+    // TWI
+    Interrupt {
+        addr: 0x0030,
+        enable: REGISTER_BIT_MAP.twie,
+        flag: REGISTER_BIT_MAP.twint,
+        trigger: Trigger::Level,
+    },
+    // SPM READY
+    Interrupt {
+        addr: 0x0032,
+        enable: REGISTER_BIT_MAP.spmie,
+        flag: REGISTER_BIT_MAP.selfprgen,
+        trigger: Trigger::LevelLow,
+    },
+];
+
 pub enum Package {
     PDIP28,
 }
@@ -119,12 +504,27 @@ pub struct ATmega328P {
     instr_func: Option<InstrFunc>,
     sram: Rc<RefCell<SRAM>>,
     flash_memory: Rc<RefCell<FlashMemory>>,
+    eeprom: Rc<RefCell<EEPROM>>,
+    clock: Rc<RefCell<Clock>>,
     timer0: Timer8bit,
     timer1: Timer16bit,
     timer2: Timer8bit,
     portb: IOPort,
     portc: IOPort,
     portd: IOPort,
+    usart0: Usart,
+    spi: Spi,
+    twi: Twi,
+    adc: Adc,
+    analog_comparator: AnalogComparator,
+    eeprom_controller: EEPROMController,
+    watchdog: Watchdog,
+    external_interrupt: ExternalInterrupt,
+    interrupt: InterruptController,
+    self_programming: SelfProgramming,
+    fuses: Fuses,
+    sleep_mode: Option<SleepMode>,
+    pin_events: VecDeque<PinEvent>,
     package: Package,
 }
 
@@ -139,9 +539,15 @@ impl ATmega328P {
 
         let flash_memory = Rc::new(RefCell::new(FlashMemory::new(FLASH_MEMORY_SIZE)));
 
+        let clock = Rc::new(RefCell::new(Clock::new(
+            Rc::clone(&sram),
+            sram.borrow().map.clkpr,
+        )));
+
         let timer0 = Timer8bit::new(
             Timer8bitType::A,
             Rc::clone(&sram),
+            Rc::clone(&clock),
             sram.borrow().map.tcnt0,
             sram.borrow().map.tccr0a,
             sram.borrow().map.tccr0b,
@@ -150,6 +556,8 @@ impl ATmega328P {
             sram.borrow().bit_map.tov0,
             sram.borrow().bit_map.ocf0a,
             sram.borrow().bit_map.ocf0b,
+            OC0_PINS,
+            None,
         );
 
         let timer1 = Timer16bit::new(
@@ -164,11 +572,17 @@ impl ATmega328P {
             sram.borrow().bit_map.tov1,
             sram.borrow().bit_map.ocf1a,
             sram.borrow().bit_map.ocf1b,
+            sram.borrow().bit_map.icf1,
+            OC1_PINS,
+            ICP1_PIN,
+            sram.borrow().bit_map.acic,
+            sram.borrow().bit_map.aco,
         );
 
         let timer2 = Timer8bit::new(
             Timer8bitType::B,
             Rc::clone(&sram),
+            Rc::clone(&clock),
             sram.borrow().map.tcnt2,
             sram.borrow().map.tccr2a,
             sram.borrow().map.tccr2b,
@@ -177,6 +591,8 @@ impl ATmega328P {
             sram.borrow().bit_map.tov2,
             sram.borrow().bit_map.ocf2a,
             sram.borrow().bit_map.ocf2b,
+            OC2_PINS,
+            Some(sram.borrow().map.assr),
         );
 
         let portb = IOPort::new(
@@ -184,6 +600,7 @@ impl ATmega328P {
             sram.borrow().map.portb,
             sram.borrow().map.ddrb,
             sram.borrow().map.pinb,
+            sram.borrow().bit_map.pud,
         );
 
         let portc = IOPort::new(
@@ -191,6 +608,7 @@ impl ATmega328P {
             sram.borrow().map.portc,
             sram.borrow().map.ddrc,
             sram.borrow().map.pinc,
+            sram.borrow().bit_map.pud,
         );
 
         let portd = IOPort::new(
@@ -198,6 +616,102 @@ impl ATmega328P {
             sram.borrow().map.portd,
             sram.borrow().map.ddrd,
             sram.borrow().map.pind,
+            sram.borrow().bit_map.pud,
+        );
+
+        let usart0 = Usart::new(
+            Rc::clone(&sram),
+            sram.borrow().map.udr0,
+            sram.borrow().word_map.ubrr0,
+            sram.borrow().map.ucsr0a,
+            sram.borrow().map.ucsr0b,
+            sram.borrow().map.ucsr0c,
+        );
+
+        let spi = Spi::new(
+            Rc::clone(&sram),
+            sram.borrow().map.spcr,
+            sram.borrow().map.spsr,
+            sram.borrow().map.spdr,
+            sram.borrow().map.ddrb,
+            sram.borrow().map.pinb,
+            SPI_PINS,
+        );
+
+        let twi = Twi::new(
+            Rc::clone(&sram),
+            sram.borrow().map.twbr,
+            sram.borrow().map.twcr,
+            sram.borrow().map.twsr,
+            sram.borrow().map.twdr,
+            sram.borrow().map.twar,
+            sram.borrow().map.twamr,
+        );
+
+        let adc = Adc::new(
+            Rc::clone(&sram),
+            sram.borrow().map.admux,
+            sram.borrow().map.adcsra,
+            sram.borrow().map.adcsrb,
+            sram.borrow().word_map.adc,
+            ADC_TRIGGERS,
+        );
+
+        let analog_comparator = AnalogComparator::new(
+            Rc::clone(&sram),
+            sram.borrow().map.acsr,
+            sram.borrow().map.admux,
+            sram.borrow().map.adcsra,
+            sram.borrow().map.adcsrb,
+        );
+
+        let eeprom = Rc::new(RefCell::new(EEPROM::new(EEPROM_SIZE)));
+        let eeprom_controller = EEPROMController::new(
+            Rc::clone(&sram),
+            Rc::clone(&eeprom),
+            Rc::clone(&clock),
+            sram.borrow().map.eecr,
+            sram.borrow().map.eedr,
+            sram.borrow().word_map.eear,
+        );
+
+        let watchdog = Watchdog::new(
+            Rc::clone(&sram),
+            Rc::clone(&clock),
+            sram.borrow().map.wdtcsr,
+            sram.borrow().map.mcusr,
+        );
+
+        let external_interrupt = ExternalInterrupt::new(
+            Rc::clone(&sram),
+            sram.borrow().map.eicra,
+            sram.borrow().map.eifr,
+            sram.borrow().map.pcifr,
+            [
+                sram.borrow().map.pcmsk0,
+                sram.borrow().map.pcmsk1,
+                sram.borrow().map.pcmsk2,
+            ],
+            INT_PINS,
+            PCINT_PORTS,
+        );
+
+        let interrupt = InterruptController::new(
+            Rc::clone(&sram),
+            &INTERRUPT_TABLE,
+            flash_memory.borrow().pc_size(),
+        );
+
+        let self_programming = SelfProgramming::new(
+            Rc::clone(&sram),
+            Rc::clone(&flash_memory),
+            Rc::clone(&clock),
+            SPM_PAGE_SIZE,
+            NRWW_START,
+            sram.borrow().map.spmcsr,
+            sram.borrow().map.rampz,
+            sram.borrow().bit_map.rwwsb,
+            sram.borrow().bit_map.selfprgen,
         );
 
         ATmega328P {
@@ -207,60 +721,322 @@ impl ATmega328P {
             instr_func: None,
             sram: sram,
             flash_memory: flash_memory,
+            eeprom: eeprom,
+            clock: clock,
             timer0: timer0,
             timer1: timer1,
             timer2: timer2,
             portb: portb,
             portc: portc,
             portd: portd,
+            usart0: usart0,
+            spi: spi,
+            twi: twi,
+            adc: adc,
+            analog_comparator: analog_comparator,
+            eeprom_controller: eeprom_controller,
+            watchdog: watchdog,
+            external_interrupt: external_interrupt,
+            interrupt: interrupt,
+            self_programming: self_programming,
+            fuses: DEFAULT_FUSES,
+            sleep_mode: None,
+            pin_events: VecDeque::new(),
             package: package,
         }
     }
 
-    fn pdip28(&self) -> [bool; 28] {
-        [
-            // 1 ~ 14
-            bit(self.portc.pinx(), 6),
-            bit(self.portd.pinx(), 0),
-            bit(self.portd.pinx(), 1),
-            bit(self.portd.pinx(), 2),
-            bit(self.portd.pinx(), 3),
-            bit(self.portd.pinx(), 4),
-            true,  // vcc
-            false, // gnd
-            bit(self.portb.pinx(), 6),
-            bit(self.portb.pinx(), 7),
-            bit(self.portd.pinx(), 5),
-            bit(self.portd.pinx(), 6),
-            bit(self.portd.pinx(), 7),
-            bit(self.portb.pinx(), 0),
-            // 15 ~ 28
-            bit(self.portb.pinx(), 1),
-            bit(self.portb.pinx(), 2),
-            bit(self.portb.pinx(), 3),
-            bit(self.portb.pinx(), 4),
-            bit(self.portb.pinx(), 5),
-            true,  // avcc
-            true,  // aref
-            false, // gnd
-            bit(self.portc.pinx(), 0),
-            bit(self.portc.pinx(), 1),
-            bit(self.portc.pinx(), 2),
-            bit(self.portc.pinx(), 3),
-            bit(self.portc.pinx(), 4),
-            bit(self.portc.pinx(), 5),
-        ]
+    // Fuses take effect on the next initialize().
+    pub fn set_fuses(&mut self, fuses: Fuses) {
+        self.fuses = fuses;
     }
-}
 
-impl AVRMCU for ATmega328P {
-    fn program(&self, hex: String) {
-        self.flash_memory.borrow_mut().load_hex_from_string(hex);
+    pub fn fuses(&self) -> Fuses {
+        self.fuses
     }
 
-    fn initialize(&mut self) {
+    // Frequency in Hz of the crystal or the external clock, selected by
+    // CKSEL3:0. It takes effect on the next initialize() like the fuses.
+    pub fn set_crystal_frequency(&mut self, frequency: u64) {
+        self.clock.borrow_mut().set_crystal_frequency(frequency);
+    }
+
+    // Frequency of the system clock in Hz, divided by CLKPR
+    pub fn frequency(&self) -> u64 {
+        self.clock.borrow().frequency()
+    }
+
+    // Simulated time since the power-on
+    pub fn time(&self) -> Duration {
+        Duration::from_nanos(self.clock.borrow().nanoseconds(self.cycle))
+    }
+
+    // Start address of the boot loader section selected by BOOTSZ1:0.
+    fn boot_start(&self) -> usize {
+        match (self.fuses.high >> 1) & 0b11 {
+            0b11 => 0x3f00,
+            0b10 => 0x3e00,
+            0b01 => 0x3c00,
+            _ => 0x3800,
+        }
+    }
+
+    // The reset vector is moved to the boot loader section if BOOTRST is programmed.
+    fn reset_vector(&self) -> usize {
+        if bit(self.fuses.high, 0) {
+            0
+        } else {
+            self.boot_start()
+        }
+    }
+
+    // Bytes sent by the host to RXD0 are received at the baud rate of USART0.
+    pub fn push_usart_rx(&mut self, data: &[u8]) {
+        self.usart0.push_rx(data);
+    }
+
+    // 9-bit frames of which bit 8 is read from RXB80.
+    pub fn push_usart_rx_9bit(&mut self, data: &[u16]) {
+        self.usart0.push_rx_9bit(data);
+    }
+
+    // Baud rate of USART0 set by UBRR0 at the current system clock
+    pub fn usart_baud_rate(&self) -> f64 {
+        self.frequency() as f64 / self.usart0.bit_cycles() as f64
+    }
+
+    // Take bytes transmitted by USART0 since the last call.
+    pub fn drain_usart_tx(&mut self) -> Vec<u8> {
+        self.usart0.drain_tx()
+    }
+
+    // 9-bit frames of which bit 8 is TXB80.
+    pub fn drain_usart_tx_9bit(&mut self) -> Vec<u16> {
+        self.usart0.drain_tx_9bit()
+    }
+
+    // Attach an emulated device to the SPI bus. It is selected by SS (PB2)
+    // in master mode and drives the transfers in slave mode.
+    pub fn attach_spi_device(&mut self, device: Box<dyn SpiDevice>) {
+        self.spi.attach(device);
+    }
+
+    // Attach an emulated slave device to the TWI bus.
+    pub fn attach_twi_device(&mut self, device: Box<dyn TwiDevice>) {
+        self.twi.attach(device);
+    }
+
+    // An external master on the TWI bus addresses the AVR as a slave.
+    pub fn push_twi_transaction(&mut self, transaction: TwiTransaction) {
+        self.twi.push_transaction(transaction);
+    }
+
+    // Take bytes read from the AVR by external masters since the last call.
+    pub fn drain_twi_read(&mut self) -> Vec<u8> {
+        self.twi.drain_read()
+    }
+
+    // Analog voltages of AVCC, AREF, ADC0 ~ ADC7, AIN0 (PD6), AIN1 (PD7) and
+    // the temperature of the internal sensor. ADC6 and ADC7 are not
+    // available in PDIP28.
+    pub fn analog_inputs(&self) -> AnalogInputs {
+        self.adc.inputs
+    }
+
+    pub fn set_analog_inputs(&mut self, inputs: AnalogInputs) {
+        self.adc.inputs = inputs;
+    }
+
+    // Panics unless `channel` is 0 ~ 7.
+    pub fn set_adc_voltage(&mut self, channel: usize, voltage: f64) {
+        assert!(channel < 8, "ADC channel must be 0 ~ 7, got {}", channel);
+        self.adc.inputs.channels[channel] = voltage;
+    }
+
+    // Panics unless `n` is 0 (AIN0) or 1 (AIN1).
+    pub fn set_ain_voltage(&mut self, n: usize, voltage: f64) {
+        assert!(n < 2, "AIN must be 0 or 1, got {}", n);
+        self.adc.inputs.ain[n] = voltage;
+    }
+
+    // Load an .eep Intel HEX image. EEPROM keeps its content across resets.
+    pub fn program_eeprom(&self, hex: String) -> Result<(), HexError> {
+        self.eeprom.borrow_mut().load_hex_from_string(hex)
+    }
+
+    // The whole EEPROM as an .eep Intel HEX image.
+    pub fn eeprom_hex(&self) -> String {
+        self.eeprom.borrow().to_hex_string()
+    }
+
+    pub fn state(&self) -> State {
+        match self.sleep_mode {
+            Some(mode) => State::Sleeping(mode),
+            None => State::Running,
+        }
+    }
+
+    // Sleep mode selected by SM2:0. None if SE is cleared or the mode is reserved.
+    fn selected_sleep_mode(&self) -> Option<SleepMode> {
+        let sram = self.sram.borrow();
+        if !sram.get_bit(sram.bit_map.se) {
+            return None;
+        }
+        match (sram.get(sram.map.smcr) >> 1) & 0b111 {
+            0b000 => Some(SleepMode::Idle),
+            0b001 => Some(SleepMode::ADCNoiseReduction),
+            0b010 => Some(SleepMode::PowerDown),
+            0b011 => Some(SleepMode::PowerSave),
+            0b110 => Some(SleepMode::Standby),
+            0b111 => Some(SleepMode::ExtendedStandby),
+            _ => None,
+        }
+    }
+
+    // Start-up time from Power-down and Power-save selected by CKSEL3:0 and SUT1:0.
+    fn startup_cycles(&self) -> u64 {
+        let cksel = self.fuses.low & 0b1111;
+        let sut = (self.fuses.low >> 4) & 0b11;
+        match cksel {
+            // External clock, calibrated internal RC oscillator and
+            // internal 128 kHz RC oscillator
+            0b0000 | 0b0010 | 0b0011 => 6,
+            // Low frequency crystal oscillator
+            0b0100 | 0b0101 => 1024,
+            // Full swing and low power crystal oscillator
+            _ => match (cksel & 1, sut) {
+                (0, 0b00) | (0, 0b01) => 258,
+                (0, _) | (1, 0b00) => 1024,
+                _ => 16384,
+            },
+        }
+    }
+
+    // Waking up from any sleep mode adds 4 cycles to the interrupt response
+    // time, followed by the start-up time. The oscillator is kept running in
+    // Standby and Extended Standby. The start-up time is counted in cycles of
+    // the clock source, which is divided by the system clock prescaler.
+    fn wake_up_cycles(&self, mode: SleepMode) -> u64 {
+        let source_cycles = match mode {
+            SleepMode::Idle | SleepMode::ADCNoiseReduction => 0,
+            SleepMode::Standby | SleepMode::ExtendedStandby => 6,
+            SleepMode::PowerDown | SleepMode::PowerSave => self.startup_cycles(),
+        };
+        let division = self.clock.borrow().division();
+        4 + source_cycles.div_ceil(division)
+    }
+
+    // Run the peripherals whose clock is not stopped by the sleep mode or PRR.
+    // Returns true if the watchdog requests a system reset.
+    fn next_peripherals(&mut self, cycle: u64) -> bool {
+        self.clock.borrow_mut().next(cycle);
+        self.self_programming.next(cycle);
+        self.eeprom_controller.next(cycle);
+
+        // clkI/O is only running in Idle mode while sleeping.
+        let clk_io = matches!(self.sleep_mode, None | Some(SleepMode::Idle));
+        // Timer2 also keeps running in ADC Noise Reduction, Power-save and
+        // Extended Standby when it is clocked asynchronously.
+        let clk_asy = match self.sleep_mode {
+            Some(SleepMode::PowerDown) | Some(SleepMode::Standby) => false,
+            _ => self.timer2.is_async(),
+        };
+        let (prtim0, prtim1, prtim2, prusart0, prspi, prtwi, pradc) = {
+            let sram = self.sram.borrow();
+            (
+                sram.get_bit(sram.bit_map.prtim0),
+                sram.get_bit(sram.bit_map.prtim1),
+                sram.get_bit(sram.bit_map.prtim2),
+                sram.get_bit(sram.bit_map.prusart0),
+                sram.get_bit(sram.bit_map.prspi),
+                sram.get_bit(sram.bit_map.prtwi),
+                sram.get_bit(sram.bit_map.pradc),
+            )
+        };
+        // ACO is updated before Timer1 captures it.
+        self.analog_comparator.next(&self.adc.inputs);
+        // T0 (PD4) and T1 (PD5) clock the timers externally.
+        let (t0, t1) = (bit(self.portd.levels(), 4), bit(self.portd.levels(), 5));
+        self.timer0.set_clock_pin(t0);
+        self.timer1.set_clock_pin(t1);
+        if clk_io && !prtim0 {
+            self.timer0.next(cycle);
+        } else {
+            self.timer0.pause(cycle);
+        }
+        if clk_io && !prtim1 {
+            self.timer1.next(cycle);
+        } else {
+            self.timer1.pause(cycle);
+        }
+        if (clk_io || clk_asy) && !prtim2 {
+            self.timer2.next(cycle);
+        } else {
+            self.timer2.pause(cycle);
+        }
+        if clk_io && !prusart0 {
+            self.usart0.next(cycle);
+        } else {
+            self.usart0.pause(cycle);
+        }
+        if clk_io && !prspi {
+            self.spi.next(cycle);
+        } else {
+            self.spi.pause(cycle);
+        }
+        if clk_io && !prtwi {
+            self.twi.next(cycle);
+        } else {
+            self.twi.pause(cycle);
+        }
+        // clkADC also keeps running in ADC Noise Reduction mode.
+        let clk_adc = clk_io || self.sleep_mode == Some(SleepMode::ADCNoiseReduction);
+        if clk_adc && !pradc {
+            self.adc.next(cycle);
+        } else {
+            self.adc.pause(cycle);
+        }
+        let (overrides, levels) = self.portb_overrides();
+        self.portb.set_overrides(overrides, levels);
+        let (overrides, levels) = self.portd_overrides();
+        self.portd.set_overrides(overrides, levels);
+        self.portb.next(cycle);
+        self.portc.next(cycle);
+        self.portd.next(cycle);
+        self.record_pin_events(cycle);
+        self.external_interrupt.next(clk_io);
+        // The watchdog oscillator keeps running in all sleep modes.
+        self.watchdog.next(cycle)
+    }
+
+    // No instruction is executed while sleeping. An interrupt wakes the MCU
+    // up and its vector is executed after the start-up time.
+    fn next_sleeping(&mut self, mode: SleepMode) {
+        let next_cycle = self.cycle + 1;
+        if self.next_peripherals(next_cycle) {
+            self.cycle = next_cycle;
+            self.watchdog_reset();
+            return;
+        }
+        match self.interrupt.next(self.pc, next_cycle) {
+            Some((pc, cycle)) => {
+                self.sleep_mode = None;
+                self.pc = pc;
+                self.cycle = cycle + self.wake_up_cycles(mode);
+                self.fetch();
+            }
+            None => self.cycle = next_cycle,
+        }
+    }
+
+    // I/O registers are set to their initial values while the data memory
+    // and the time are kept. `mcusr` holds the reset flags.
+    fn reset(&mut self, mcusr: u8) {
         // setup initial sram
         let mut sram = self.sram.borrow_mut();
+        for addr in 0x20..0x100 {
+            sram.set(addr, 0x00);
+        }
         sram.set_word(REGISTER_WORD_MAP.sp, REGISTER_MAP.ramend as u16);
         sram.set(0x12, 0x01);
         sram.set(0x16, 0x01);
@@ -270,57 +1046,278 @@ impl AVRMCU for ATmega328P {
         sram.set(0x1c, 0xff);
         sram.set(0x1d, 0x08);
         sram.set(0x1e, 0x7a);
-        sram.set(REGISTER_MAP.mcusr, 0x01);
+        sram.set(REGISTER_MAP.mcusr, mcusr);
         sram.set(REGISTER_MAP.twsr, 0xf8);
         sram.set(REGISTER_MAP.twar, 0xfe);
         sram.set(REGISTER_MAP.twdr, 0xff);
         sram.set(REGISTER_MAP.ucsr0a, 0x20);
         sram.set(REGISTER_MAP.ucsr0c, 0x06);
+        drop(sram);
+
+        self.clock
+            .borrow_mut()
+            .initialize(self.cycle, self.fuses.low);
+        self.self_programming.initialize();
+        self.usart0.initialize();
+        self.spi.initialize();
+        self.twi.initialize();
+        self.adc.initialize();
+        self.analog_comparator.initialize();
+        self.portb.initialize();
+        self.portc.initialize();
+        self.portd.initialize();
+        self.eeprom_controller.initialize();
+        self.timer0.initialize();
+        self.timer1.initialize();
+        self.timer2.initialize();
+        self.external_interrupt.initialize();
+        // WDTON (programmed = 0) forces the watchdog system reset mode.
+        self.watchdog
+            .initialize(self.cycle, !bit(self.fuses.high, 4));
 
         // prepare for start
-        self.pc = 0;
-        self.cycle = 0;
+        self.pc = self.reset_vector();
+        self.sleep_mode = None;
+        self.fetch();
+    }
+
+    // The watchdog system reset sets WDRF and keeps the other reset flags.
+    fn watchdog_reset(&mut self) {
+        let mcusr = self.sram.borrow().get(REGISTER_MAP.mcusr);
+        self.reset(mcusr | 1 << REGISTER_BIT_MAP.wdrf.1);
+    }
+
+    fn fetch(&mut self) {
         let word = self.flash_memory.borrow().get(self.pc as usize);
         let (instr, instr_func) = OPCODE_TREE.with(|tree| tree.find(word));
         self.instr = Some(instr);
         self.instr_func = Some(instr_func);
     }
 
+    // Peripherals override the PORT B pins.
+    fn portb_overrides(&self) -> (u8, u8) {
+        (0..8).fold((0, 0), |(overrides, levels), n| {
+            let oc = match n {
+                1 => self.timer1.output_a(),
+                2 => self.timer1.output_b(),
+                3 => self.timer2.output_a(),
+                _ => None,
+            };
+            match self.spi.output(n).or(oc) {
+                Some(level) => (overrides | 1 << n, levels | (level as u8) << n),
+                None => (overrides, levels),
+            }
+        })
+    }
+
+    // Output compare pins override the PORT D pins.
+    fn portd_overrides(&self) -> (u8, u8) {
+        (0..8).fold((0, 0), |(overrides, levels), n| {
+            let oc = match n {
+                3 => self.timer2.output_b(),
+                5 => self.timer0.output_b(),
+                6 => self.timer0.output_a(),
+                _ => None,
+            };
+            match oc {
+                Some(level) => (overrides | 1 << n, levels | (level as u8) << n),
+                None => (overrides, levels),
+            }
+        })
+    }
+
+    // Package pins of PORT B, C and D
+    fn port_pins(&self) -> [(&'static [usize], &IOPort); 3] {
+        match &self.package {
+            Package::PDIP28 => [
+                (&PDIP28_PORTB, &self.portb),
+                (&PDIP28_PORTC, &self.portc),
+                (&PDIP28_PORTD, &self.portd),
+            ],
+        }
+    }
+
+    fn record_pin_events(&mut self, cycle: u64) {
+        let mut events = vec![];
+        for (pins, port) in self.port_pins().iter() {
+            for (n, &p) in pins.iter().enumerate() {
+                if bit(port.edges(), n as u8) {
+                    events.push(PinEvent {
+                        cycle: cycle,
+                        pin: p,
+                        level: bit(port.levels(), n as u8),
+                    });
+                }
+            }
+        }
+        for event in events {
+            if self.pin_events.len() == MAX_PIN_EVENTS {
+                self.pin_events.pop_front();
+            }
+            self.pin_events.push_back(event);
+        }
+    }
+
+    // Level changes of the pins in order of time since the last call
+    pub fn take_pin_events(&mut self) -> Vec<PinEvent> {
+        self.pin_events.drain(..).collect()
+    }
+
+    fn set_pdip28(&mut self, states: &[Option<bool>]) {
+        // (levels, driven) of a port
+        let inputs = |map: &[usize]| {
+            map.iter()
+                .enumerate()
+                .fold((0, 0), |(levels, driven), (n, &p)| match states[p] {
+                    Some(level) => (levels | (level as u8) << n, driven | 1 << n),
+                    None => (levels, driven),
+                })
+        };
+        let (levels, driven) = inputs(&PDIP28_PORTB);
+        self.portb.set_inputs(levels, driven);
+        let (levels, driven) = inputs(&PDIP28_PORTC);
+        self.portc.set_inputs(levels, driven);
+        let (levels, driven) = inputs(&PDIP28_PORTD);
+        self.portd.set_inputs(levels, driven);
+    }
+
+    // Levels driven by the host, where `None` releases the pin. A released
+    // input pin is pulled up if PORTxn is set and PUD is cleared, otherwise
+    // it floats and keeps its last level.
+    pub fn set_pin_states(&mut self, states: Vec<Option<bool>>) {
+        match &self.package {
+            Package::PDIP28 => self.set_pdip28(&states),
+        }
+    }
+
+    // Output pins driven to the other level by the host
+    pub fn get_contentions(&self) -> Vec<bool> {
+        let mut contentions = vec![false; self.get_pins().len()];
+        for (pins, port) in self.port_pins().iter() {
+            for (n, &p) in pins.iter().enumerate() {
+                contentions[p] = bit(port.contention(), n as u8);
+            }
+        }
+        contentions
+    }
+
+    fn pdip28(&self) -> [bool; 28] {
+        [
+            // 1 ~ 14
+            bit(self.portc.levels(), 6),
+            bit(self.portd.levels(), 0),
+            bit(self.portd.levels(), 1),
+            bit(self.portd.levels(), 2),
+            bit(self.portd.levels(), 3),
+            bit(self.portd.levels(), 4),
+            true,  // vcc
+            false, // gnd
+            bit(self.portb.levels(), 6),
+            bit(self.portb.levels(), 7),
+            bit(self.portd.levels(), 5),
+            bit(self.portd.levels(), 6),
+            bit(self.portd.levels(), 7),
+            bit(self.portb.levels(), 0),
+            // 15 ~ 28
+            bit(self.portb.levels(), 1),
+            bit(self.portb.levels(), 2),
+            bit(self.portb.levels(), 3),
+            bit(self.portb.levels(), 4),
+            bit(self.portb.levels(), 5),
+            true,  // avcc
+            true,  // aref
+            false, // gnd
+            bit(self.portc.levels(), 0),
+            bit(self.portc.levels(), 1),
+            bit(self.portc.levels(), 2),
+            bit(self.portc.levels(), 3),
+            bit(self.portc.levels(), 4),
+            bit(self.portc.levels(), 5),
+        ]
+    }
+}
+
+impl AVRMCU for ATmega328P {
+    fn program(&self, hex: String) -> Result<(), HexError> {
+        self.flash_memory.borrow_mut().load_hex_from_string(hex)
+    }
+
+    fn initialize(&mut self) {
+        self.cycle = 0;
+        self.clock.borrow_mut().power_on();
+        // Power-on reset
+        self.reset(0x01);
+    }
+
     fn get_pins(&self) -> Vec<bool> {
         match &self.package {
-            PDIP28 => self.pdip28().to_vec(),
+            Package::PDIP28 => self.pdip28().to_vec(),
         }
     }
 
-    fn set_pins(&self, pins: Vec<bool>) {}
+    // The host drives all the pins. Levels of VCC and GND are ignored.
+    fn set_pins(&mut self, pins: Vec<bool>) {
+        self.set_pin_states(pins.into_iter().map(Some).collect());
+    }
 }
 
 impl Iterator for ATmega328P {
-    type Item = ();
-    fn next(&mut self) -> Option<()> {
+    type Item = State;
+    fn next(&mut self) -> Option<State> {
+        if let Some(mode) = self.sleep_mode {
+            self.next_sleeping(mode);
+            return Some(self.state());
+        }
+
         // execute
-        let (next_pc, next_cycle) = self.instr_func.unwrap()(
+        self.sram.borrow_mut().start_tracing();
+        let (next_pc, mut next_cycle) = self.instr_func.unwrap()(
             &mut self.sram.borrow_mut(),
             &self.flash_memory.borrow(),
             self.pc,
             self.cycle,
         );
-        self.timer0.next(next_cycle);
-        self.timer1.next(next_cycle);
-        self.timer2.next(next_cycle);
-        self.portb.next();
-        self.portc.next();
-        self.portd.next();
+        self.sram.borrow_mut().stop_tracing();
+        if self.instr == Some(Instr::SPM) {
+            next_cycle += self
+                .self_programming
+                .spm(self.pc, self.cycle, self.boot_start());
+        }
+        next_cycle += self.eeprom_controller.access(next_cycle);
+        if self.instr == Some(Instr::WDR) {
+            self.watchdog.reset_timer(next_cycle);
+        }
+        if self.next_peripherals(next_cycle) {
+            self.cycle = next_cycle;
+            self.watchdog_reset();
+            return Some(self.state());
+        }
+        if self.instr == Some(Instr::SLEEP) {
+            self.sleep_mode = self.selected_sleep_mode();
+            // Entering ADC Noise Reduction mode starts a conversion.
+            if self.sleep_mode == Some(SleepMode::ADCNoiseReduction) {
+                self.adc.start(next_cycle, false);
+            }
+        }
+
+        // The instruction following SEI or RETI is always executed
+        // before any pending interrupt is served. A pending interrupt
+        // wakes the MCU up on the next step if it has just gone to sleep.
+        let (next_pc, next_cycle) = match self.instr {
+            Some(Instr::SEI) | Some(Instr::RETI) => (next_pc, next_cycle),
+            _ if self.sleep_mode.is_some() => (next_pc, next_cycle),
+            _ => self
+                .interrupt
+                .next(next_pc, next_cycle)
+                .unwrap_or((next_pc, next_cycle)),
+        };
 
         // prepare for next
         self.pc = next_pc;
         self.cycle = next_cycle;
-        let word = self.flash_memory.borrow().get(self.pc as usize);
-        let (instr, instr_func) = OPCODE_TREE.with(|tree| tree.find(word));
-        self.instr = Some(instr);
-        self.instr_func = Some(instr_func);
+        self.fetch();
 
-        Some(())
+        Some(self.state())
     }
 }
 
@@ -347,7 +1344,8 @@ X Register:       {:#04x}
 Y Register:       {:#04x}
 Z Register:       {:#04x}
 Status Register:  {:08b}
-Cycle Counter:    {}"#,
+Cycle Counter:    {}
+State:            {:?}"#,
                 self.pc,
                 self.pc * 2,
                 self.instr,
@@ -357,8 +1355,13 @@ Cycle Counter:    {}"#,
                 self.sram.borrow().get_word(z_addr),
                 self.sram.borrow().get(sreg_addr),
                 self.cycle,
+                self.state(),
             );
             let sram = format!(">>>>>>>>>>>>> SRAM >>>>>>>>>>>>>>{}", self.sram.borrow());
+            let clock = format!(
+                ">>>>>>>>>>>>> CLOCK >>>>>>>>>>>>>>\n{}",
+                self.clock.borrow()
+            );
             let timer = format!(
                 ">>>>>>>>>>>>> TIMER >>>>>>>>>>>>>>\n{}\n{}\n{}",
                 self.timer0, self.timer1, self.timer2,
@@ -367,9 +1370,49 @@ Cycle Counter:    {}"#,
                 ">>>>>>>>>>>>> IO PORT >>>>>>>>>>>>>>\n{}\n{}\n{}",
                 self.portb, self.portc, self.portd,
             );
+            let usart = format!(">>>>>>>>>>>>> USART >>>>>>>>>>>>>>\n{}", self.usart0);
+            let spi = format!(">>>>>>>>>>>>> SPI >>>>>>>>>>>>>>\n{}", self.spi);
+            let twi = format!(">>>>>>>>>>>>> TWI >>>>>>>>>>>>>>\n{}", self.twi);
+            let adc = format!(">>>>>>>>>>>>> ADC >>>>>>>>>>>>>>\n{}", self.adc);
+            let analog_comparator = format!(
+                ">>>>>>>>>>>>> ANALOG COMPARATOR >>>>>>>>>>>>>>\n{}",
+                self.analog_comparator
+            );
+            let eeprom = format!(
+                ">>>>>>>>>>>>> EEPROM >>>>>>>>>>>>>>\n{}",
+                self.eeprom_controller
+            );
+            let external_interrupt = format!(
+                ">>>>>>>>>>>>> EXTERNAL INTERRUPT >>>>>>>>>>>>>>\n{}",
+                self.external_interrupt
+            );
+            let watchdog = format!(">>>>>>>>>>>>> WATCHDOG >>>>>>>>>>>>>>\n{}", self.watchdog);
+            let interrupt = format!(">>>>>>>>>>>>> INTERRUPT >>>>>>>>>>>>>>\n{}", self.interrupt);
+            let self_programming = format!(
+                ">>>>>>>>>>>>> SELF PROGRAMMING >>>>>>>>>>>>>>\n{}",
+                self.self_programming
+            );
             let pins = format!(">>>>>>>>>>>>> PINS >>>>>>>>>>>>>>\n{:?}", self.get_pins(),);
 
-            format!("{}\n{}\n{}\n{}\n{}", core, sram, timer, port, pins)
+            format!(
+                "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+                core,
+                sram,
+                clock,
+                timer,
+                port,
+                usart,
+                spi,
+                twi,
+                adc,
+                analog_comparator,
+                eeprom,
+                watchdog,
+                external_interrupt,
+                interrupt,
+                self_programming,
+                pins
+            )
         };
         write!(f, "{}", log)
     }
@@ -1,6 +1,43 @@
+pub use super::util::hex::HexError;
+
 pub trait AVRMCU {
-    fn program(&self, hex: String);
+    // Load an Intel HEX image into the flash memory.
+    fn program(&self, hex: String) -> Result<(), HexError>;
     fn initialize(&mut self);
     fn get_pins(&self) -> Vec<bool>;
-    fn set_pins(&self, pins: Vec<bool>);
+    fn set_pins(&mut self, pins: Vec<bool>);
+}
+
+// Fuse bytes of the device. A programmed fuse bit reads as 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fuses {
+    pub low: u8,
+    pub high: u8,
+    pub extended: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepMode {
+    Idle,
+    ADCNoiseReduction,
+    PowerDown,
+    PowerSave,
+    Standby,
+    ExtendedStandby,
+}
+
+// Reported by each step of the MCU. While sleeping no instruction is
+// executed and only the peripherals clocked in the sleep mode are running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Sleeping(SleepMode),
+}
+
+// A level change of a package pin (0-origin) at the cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinEvent {
+    pub cycle: u64,
+    pub pin: usize,
+    pub level: bool,
 }
@@ -0,0 +1,182 @@
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// CLKPR
+const CLKPCE: u8 = 7;
+
+// CLKPS3:0 can be changed within 4 cycles after CLKPCE is written to one.
+const CHANGE_TIMEOUT: u64 = 4;
+
+const INTERNAL_RC_FREQUENCY: u64 = 8_000_000;
+const INTERNAL_128KHZ_RC_FREQUENCY: u64 = 128_000;
+// Arduino Uno
+const DEFAULT_CRYSTAL_FREQUENCY: u64 = 16_000_000;
+
+const PICOSECONDS_PER_SECOND: u128 = 1_000_000_000_000;
+const PICOSECONDS_PER_NANOSECOND: u128 = 1_000;
+
+// The system clock is the clock source selected by CKSEL3:0 divided by the
+// system clock prescaler. Cycles counted by the MCU are cycles of the
+// system clock, and the simulated time is kept across the changes of the
+// division factor.
+pub struct Clock {
+    sram: Rc<RefCell<SRAM>>,
+    // Frequency of the external crystal or clock in Hz
+    crystal_frequency: u64,
+    // Frequency of the clock source selected by CKSEL3:0 in Hz
+    source_frequency: u64,
+    // 1 ~ 256 selected by CLKPS3:0
+    division: u64,
+    changeable_cycle: Option<u64>,
+    // Time in picoseconds at `base_cycle`, when the frequency was changed
+    base_cycle: u64,
+    base_time: u128,
+
+    clkpr: RegisterAddr,
+}
+
+impl Clock {
+    pub fn new(sram: Rc<RefCell<SRAM>>, clkpr: RegisterAddr) -> Clock {
+        Clock {
+            sram: sram,
+            crystal_frequency: DEFAULT_CRYSTAL_FREQUENCY,
+            source_frequency: DEFAULT_CRYSTAL_FREQUENCY,
+            division: 1,
+            changeable_cycle: None,
+            base_cycle: 0,
+            base_time: 0,
+            clkpr: clkpr,
+        }
+    }
+
+    // The time restarts from 0.
+    pub fn power_on(&mut self) {
+        self.base_cycle = 0;
+        self.base_time = 0;
+    }
+
+    // The clock source is selected by CKSEL3:0 and the division factor is
+    // 8 if CKDIV8 is programmed, otherwise 1.
+    pub fn initialize(&mut self, cycle: u64, low_fuse: u8) {
+        self.rebase(cycle);
+        self.source_frequency = match low_fuse & 0b1111 {
+            0b0010 => INTERNAL_RC_FREQUENCY,
+            0b0011 => INTERNAL_128KHZ_RC_FREQUENCY,
+            // External clock and crystal oscillators
+            _ => self.crystal_frequency,
+        };
+        let clkps = if bit(low_fuse, 7) { 0 } else { 0b0011 };
+        self.division = 1 << clkps;
+        self.changeable_cycle = None;
+        self.sram.borrow_mut().set(self.clkpr, clkps);
+    }
+
+    pub fn set_crystal_frequency(&mut self, frequency: u64) {
+        self.crystal_frequency = frequency;
+    }
+
+    // Frequency of the system clock in Hz
+    pub fn frequency(&self) -> u64 {
+        self.source_frequency / self.division
+    }
+
+    // The division factor of the system clock prescaler
+    pub fn division(&self) -> u64 {
+        self.division
+    }
+
+    // A cycle before `base_cycle` (e.g. the last cycle of a peripheral) is
+    // approximated with the current frequency.
+    fn picoseconds(&self, cycle: u64) -> u128 {
+        let frequency = self.frequency() as u128;
+        if cycle >= self.base_cycle {
+            let diff = (cycle - self.base_cycle) as u128;
+            self.base_time + diff * PICOSECONDS_PER_SECOND / frequency
+        } else {
+            let diff = (self.base_cycle - cycle) as u128;
+            self.base_time
+                .saturating_sub(diff * PICOSECONDS_PER_SECOND / frequency)
+        }
+    }
+
+    // Simulated time in nanoseconds at `cycle`
+    pub fn nanoseconds(&self, cycle: u64) -> u64 {
+        (self.picoseconds(cycle) / PICOSECONDS_PER_NANOSECOND) as u64
+    }
+
+    // Cycles of an oscillator of `frequency` Hz (e.g. the watchdog
+    // oscillator) from the power-on to `cycle`
+    pub fn ticks(&self, cycle: u64, frequency: u64) -> u64 {
+        (self.picoseconds(cycle) * frequency as u128 / PICOSECONDS_PER_SECOND) as u64
+    }
+
+    // System clock cycles taking `nanoseconds` at the current frequency,
+    // rounded up
+    pub fn cycles(&self, nanoseconds: u64) -> u64 {
+        let picoseconds = nanoseconds as u128 * PICOSECONDS_PER_NANOSECOND;
+        let frequency = self.frequency() as u128;
+        (picoseconds * frequency).div_ceil(PICOSECONDS_PER_SECOND) as u64
+    }
+
+    fn rebase(&mut self, cycle: u64) {
+        self.base_time = self.picoseconds(cycle);
+        self.base_cycle = cycle;
+    }
+
+    // CLKPS3:0 is changed by writing CLKPCE to one with the other bits
+    // zero, then writing CLKPS3:0 with CLKPCE zero within 4 cycles.
+    fn access_clkpr(&mut self, cycle: u64) {
+        let (is_written, clkpr) = {
+            let sram = self.sram.borrow();
+            (sram.is_written(self.clkpr), sram.get(self.clkpr))
+        };
+        if !is_written {
+            return;
+        }
+        let is_changeable = match self.changeable_cycle {
+            Some(c) => cycle - c <= CHANGE_TIMEOUT,
+            None => false,
+        };
+        let clkps = clkpr & 0b1111;
+        if clkpr == 1 << CLKPCE {
+            self.changeable_cycle = Some(cycle);
+        } else if is_changeable && !bit(clkpr, CLKPCE) {
+            self.changeable_cycle = None;
+            // CLKPS3:0 over 0b1000 are reserved.
+            if clkps <= 0b1000 {
+                self.rebase(cycle);
+                self.division = 1 << clkps;
+            }
+        } else {
+            self.changeable_cycle = None;
+        }
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_clkpr(cycle);
+        if let Some(c) = self.changeable_cycle {
+            if cycle - c > CHANGE_TIMEOUT {
+                self.changeable_cycle = None;
+            }
+        }
+        let clkpr = (self.changeable_cycle.is_some() as u8) << CLKPCE
+            | self.division.trailing_zeros() as u8;
+        self.sram.borrow_mut().set(self.clkpr, clkpr);
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "clock =====
+    clkpr: {:08b},    source: {} Hz,    frequency: {} Hz",
+            self.sram.borrow().get(self.clkpr),
+            self.source_frequency,
+            self.frequency(),
+        )
+    }
+}
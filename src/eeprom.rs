@@ -0,0 +1,59 @@
+use super::util::hex::*;
+use std::fmt;
+
+// Non-volatile data memory. Its content survives reset.
+pub struct EEPROM {
+    data: Vec<u8>,
+}
+
+impl EEPROM {
+    // An erased byte reads 0xff.
+    pub fn new(size: usize) -> EEPROM {
+        EEPROM {
+            data: vec![0xff; size],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn get(&self, a: usize) -> u8 {
+        self.data[a]
+    }
+
+    pub fn set(&mut self, a: usize, v: u8) {
+        self.data[a] = v;
+    }
+
+    // Load an .eep image. Nothing is loaded if the image is invalid or
+    // has a byte out of range.
+    pub fn load_hex_from_string(&mut self, hex: String) -> Result<(), HexError> {
+        let data = parse_intel_hex(&hex)?;
+        if let Some((addr, _)) = data.iter().find(|(addr, _)| *addr >= self.size()) {
+            return Err(HexError::AddressOutOfRange { addr: *addr });
+        }
+        for (addr, b) in data {
+            self.set(addr, b);
+        }
+        Ok(())
+    }
+
+    pub fn to_hex_string(&self) -> String {
+        to_intel_hex(&self.data)
+    }
+}
+
+impl fmt::Display for EEPROM {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sum = String::from("");
+        for i in 0..4 {
+            let i = i * 16;
+            let bytes: Vec<String> = (i..i + 16)
+                .map(|a| format!("{:02x}", self.get(a)))
+                .collect();
+            sum = format!("{}\n{:#06x} | {}", sum, i, bytes.join(" "));
+        }
+        write!(f, "{}", sum)
+    }
+}
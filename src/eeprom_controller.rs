@@ -0,0 +1,169 @@
+use super::clock::*;
+use super::eeprom::*;
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// EECR
+const EEMPE: u8 = 2;
+const EEPE: u8 = 1;
+const EERE: u8 = 0;
+
+// Erase and write in one operation takes 3.4 ms, erase only and write only
+// take 1.8 ms.
+const ERASE_AND_WRITE_NANOSECONDS: u64 = 3_400_000;
+const ERASE_OR_WRITE_NANOSECONDS: u64 = 1_800_000;
+
+// EEPE must be written within 4 cycles after EEMPE is written.
+const EEMPE_TIMEOUT: u64 = 4;
+
+// The CPU is halted for 4 cycles by EERE and 2 cycles by EEPE.
+const READ_HALT_CYCLES: u64 = 4;
+const WRITE_HALT_CYCLES: u64 = 2;
+
+pub struct EEPROMController {
+    sram: Rc<RefCell<SRAM>>,
+    eeprom: Rc<RefCell<EEPROM>>,
+    clock: Rc<RefCell<Clock>>,
+    enabled_cycle: Option<u64>,
+    // The address and the data to be programmed, and the cycle it completes
+    programming: Option<(usize, u8, u64)>,
+
+    eecr: RegisterAddr,
+    eedr: RegisterAddr,
+    eear: RegisterWordAddr,
+}
+
+impl EEPROMController {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        eeprom: Rc<RefCell<EEPROM>>,
+        clock: Rc<RefCell<Clock>>,
+        eecr: RegisterAddr,
+        eedr: RegisterAddr,
+        eear: RegisterWordAddr,
+    ) -> EEPROMController {
+        EEPROMController {
+            sram: sram,
+            eeprom: eeprom,
+            clock: clock,
+            enabled_cycle: None,
+            programming: None,
+            eecr: eecr,
+            eedr: eedr,
+            eear: eear,
+        }
+    }
+
+    pub fn initialize(&mut self) {
+        self.enabled_cycle = None;
+        self.programming = None;
+    }
+
+    fn eecr(&self) -> u8 {
+        self.sram.borrow().get(self.eecr)
+    }
+
+    fn set_eecr(&mut self, eecr: u8) {
+        self.sram.borrow_mut().set(self.eecr, eecr);
+    }
+
+    fn eear(&self) -> usize {
+        self.sram.borrow().get_word(self.eear) as usize % self.eeprom.borrow().size()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.programming.is_some()
+    }
+
+    // Handle the strobes written to EECR by the last instruction. Returns
+    // the number of cycles the CPU is halted.
+    pub fn access(&mut self, cycle: u64) -> u64 {
+        if !self.sram.borrow().is_written(self.eecr) {
+            return 0;
+        }
+        let mut eecr = self.eecr();
+        let mut halt = 0;
+
+        // Reading is not possible while programming.
+        if bit(eecr, EERE) {
+            if !self.is_busy() {
+                let data = self.eeprom.borrow().get(self.eear());
+                self.sram.borrow_mut().set(self.eedr, data);
+                halt += READ_HALT_CYCLES;
+            }
+            eecr &= !(1 << EERE);
+        }
+
+        let is_enabled = match self.enabled_cycle {
+            Some(c) => cycle - c <= EEMPE_TIMEOUT,
+            None => false,
+        };
+        if bit(eecr, EEPE) && !self.is_busy() {
+            if is_enabled {
+                let addr = self.eear();
+                let old = self.eeprom.borrow().get(addr);
+                let eedr = self.sram.borrow().get(self.eedr);
+                // EEPM1:0
+                let (data, nanoseconds) = match (eecr >> 4) & 0b11 {
+                    0b00 => (eedr, ERASE_AND_WRITE_NANOSECONDS),
+                    0b01 => (0xff, ERASE_OR_WRITE_NANOSECONDS),
+                    // Programming can only clear bits.
+                    0b10 => (old & eedr, ERASE_OR_WRITE_NANOSECONDS),
+                    _ => (old, ERASE_OR_WRITE_NANOSECONDS),
+                };
+                let cycles = self.clock.borrow().cycles(nanoseconds);
+                self.programming = Some((addr, data, cycle + cycles));
+                self.enabled_cycle = None;
+                eecr &= !(1 << EEMPE);
+                halt += WRITE_HALT_CYCLES;
+            } else {
+                eecr &= !(1 << EEPE);
+            }
+        }
+
+        if !bit(eecr, EEMPE) {
+            self.enabled_cycle = None;
+        } else if self.enabled_cycle.is_none() {
+            self.enabled_cycle = Some(cycle);
+        }
+        self.set_eecr(eecr);
+        halt
+    }
+
+    // EEMPE is cleared by hardware after 4 cycles, and EEPE is cleared when
+    // programming is completed.
+    pub fn next(&mut self, cycle: u64) {
+        if let Some(c) = self.enabled_cycle {
+            if cycle - c > EEMPE_TIMEOUT {
+                self.enabled_cycle = None;
+                let eecr = self.eecr();
+                self.set_eecr(eecr & !(1 << EEMPE));
+            }
+        }
+        if let Some((addr, data, done)) = self.programming {
+            if cycle >= done {
+                self.eeprom.borrow_mut().set(addr, data);
+                self.programming = None;
+                let eecr = self.eecr();
+                self.set_eecr(eecr & !(1 << EEPE));
+            }
+        }
+    }
+}
+
+impl fmt::Display for EEPROMController {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "eeprom =====
+    eecr: {:08b},    eear: {:#05x},    busy: {}{}",
+            self.eecr(),
+            self.eear(),
+            self.is_busy(),
+            self.eeprom.borrow(),
+        )
+    }
+}
@@ -0,0 +1,159 @@
+use super::sram::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// INTn sense control (ISCn1:0 in EICRA)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sense {
+    LowLevel,
+    AnyChange,
+    FallingEdge,
+    RisingEdge,
+}
+
+pub struct ExternalInterrupt {
+    sram: Rc<RefCell<SRAM>>,
+    // Levels of INT0 and INT1 at the last step
+    int_levels: [bool; 2],
+    // Levels of the PCINT pins at the last step
+    pcint_levels: [u8; 3],
+    // EIFR and PCIFR as seen by the peripheral
+    eifr_flags: u8,
+    pcifr_flags: u8,
+
+    eicra: RegisterAddr,
+    eifr: RegisterAddr,
+    pcifr: RegisterAddr,
+    pcmsk: [RegisterAddr; 3],
+    // INT0 and INT1 pins
+    int_pins: [RegisterBitAddr; 2],
+    // PINx of PCINT0 ~ 7, PCINT8 ~ 14 and PCINT16 ~ 23
+    pcint_ports: [RegisterAddr; 3],
+}
+
+impl ExternalInterrupt {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        eicra: RegisterAddr,
+        eifr: RegisterAddr,
+        pcifr: RegisterAddr,
+        pcmsk: [RegisterAddr; 3],
+        int_pins: [RegisterBitAddr; 2],
+        pcint_ports: [RegisterAddr; 3],
+    ) -> ExternalInterrupt {
+        ExternalInterrupt {
+            sram: sram,
+            int_levels: [false; 2],
+            pcint_levels: [0; 3],
+            eifr_flags: 0,
+            pcifr_flags: 0,
+            eicra: eicra,
+            eifr: eifr,
+            pcifr: pcifr,
+            pcmsk: pcmsk,
+            int_pins: int_pins,
+            pcint_ports: pcint_ports,
+        }
+    }
+
+    pub fn initialize(&mut self) {
+        let sram = self.sram.borrow();
+        self.int_levels = [
+            sram.get_bit(self.int_pins[0]),
+            sram.get_bit(self.int_pins[1]),
+        ];
+        self.pcint_levels = [
+            sram.get(self.pcint_ports[0]),
+            sram.get(self.pcint_ports[1]),
+            sram.get(self.pcint_ports[2]),
+        ];
+        self.eifr_flags = 0;
+        self.pcifr_flags = 0;
+    }
+
+    fn sense(&self, n: usize) -> Sense {
+        match (self.sram.borrow().get(self.eicra) >> (2 * n)) & 0b11 {
+            0b00 => Sense::LowLevel,
+            0b01 => Sense::AnyChange,
+            0b10 => Sense::FallingEdge,
+            _ => Sense::RisingEdge,
+        }
+    }
+
+    // Flags are cleared by writing one to them or by executing the interrupt.
+    // SBI and CBI write only the addressed bit.
+    fn access_flags(&mut self) {
+        let sram = self.sram.borrow();
+        if sram.is_written(self.eifr) {
+            self.eifr_flags &= !(sram.written_bits(self.eifr) & sram.get(self.eifr));
+        } else {
+            self.eifr_flags = sram.get(self.eifr);
+        }
+        if sram.is_written(self.pcifr) {
+            self.pcifr_flags &= !(sram.written_bits(self.pcifr) & sram.get(self.pcifr));
+        } else {
+            self.pcifr_flags = sram.get(self.pcifr);
+        }
+    }
+
+    // Edges on INT0 and INT1 are sampled by clkI/O, the low level and pin
+    // changes are detected asynchronously so that they wake the MCU up from
+    // any sleep mode. INTn is triggered even if the pin is an output.
+    pub fn next(&mut self, clk_io: bool) {
+        self.access_flags();
+
+        for n in 0..2 {
+            let level = self.sram.borrow().get_bit(self.int_pins[n]);
+            let last_level = self.int_levels[n];
+            self.int_levels[n] = level;
+            let is_requested = match self.sense(n) {
+                // INTFn is always cleared on the low level, the interrupt
+                // is requested by the level of the pin itself.
+                Sense::LowLevel => {
+                    self.eifr_flags &= !(1 << n);
+                    false
+                }
+                Sense::AnyChange => clk_io && level != last_level,
+                Sense::FallingEdge => clk_io && last_level && !level,
+                Sense::RisingEdge => clk_io && !last_level && level,
+            };
+            if is_requested {
+                self.eifr_flags |= 1 << n;
+            }
+        }
+
+        for n in 0..3 {
+            let (levels, pcmsk) = {
+                let sram = self.sram.borrow();
+                (sram.get(self.pcint_ports[n]), sram.get(self.pcmsk[n]))
+            };
+            if (levels ^ self.pcint_levels[n]) & pcmsk != 0 {
+                self.pcifr_flags |= 1 << n;
+            }
+            self.pcint_levels[n] = levels;
+        }
+
+        let mut sram = self.sram.borrow_mut();
+        sram.set(self.eifr, self.eifr_flags);
+        sram.set(self.pcifr, self.pcifr_flags);
+    }
+}
+
+impl fmt::Display for ExternalInterrupt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sram = self.sram.borrow();
+        write!(
+            f,
+            "external interrupt =====
+    eicra: {:08b},    eifr: {:08b},    pcifr: {:08b},
+    pcmsk0: {:08b},    pcmsk1: {:08b},    pcmsk2: {:08b}",
+            sram.get(self.eicra),
+            sram.get(self.eifr),
+            sram.get(self.pcifr),
+            sram.get(self.pcmsk[0]),
+            sram.get(self.pcmsk[1]),
+            sram.get(self.pcmsk[2]),
+        )
+    }
+}
@@ -1,8 +1,8 @@
 use super::util::bit::*;
+use super::util::hex::*;
 use super::word::*;
 use std::fmt;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs;
 
 pub struct FlashMemory {
     data: Vec<u16>,
@@ -23,6 +23,21 @@ impl FlashMemory {
         self.data[a] = v;
     }
 
+    // Size in words
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    // Size of the program counter in bytes. Flash memory larger than
+    // 128 KB (64K words) needs 22-bit PC.
+    pub fn pc_size(&self) -> usize {
+        if self.data.len() > 0x10000 {
+            3
+        } else {
+            2
+        }
+    }
+
     pub fn word(&self, pc: usize) -> Word {
         Word(self.get(pc))
     }
@@ -32,74 +47,42 @@ impl FlashMemory {
     }
 
     pub fn z_program_memory(&self, z_addr: u16) -> u8 {
-        if z_addr % 2 == 0 {
-            let addr = z_addr / 2;
-            low_byte(self.get(addr as usize))
+        self.program_memory(z_addr as usize)
+    }
+
+    // Read a byte of program memory. `addr` is a byte address, whose
+    // upper bits beyond the flash size are ignored.
+    pub fn program_memory(&self, addr: usize) -> u8 {
+        let addr = addr % (2 * self.size());
+        if addr % 2 == 0 {
+            low_byte(self.get(addr / 2))
         } else {
-            let addr = (z_addr - 1) / 2;
-            high_byte(self.get(addr as usize))
+            high_byte(self.get(addr / 2))
         }
     }
 
-    // Example intel Hex file's line
-    // :100000 | 00 | 0C945C000C946E000C946E000C946E00 | CA
     pub fn load_hex(&mut self, filepath: &str) {
-        let f = File::open(filepath).expect("file not found");
-        let f = BufReader::new(f);
-
-        let mut memory_addr = 0;
-
-        for line in f.lines() {
-            let line = line.unwrap();
-
-            let record_type = &line[7..9];
-            if record_type != "00" {
-                continue;
-            }
-
-            let data = &line[9..line.len() - 2];
-            // TODO: need refactoring
-            for list in data.chars().collect::<Vec<char>>().chunks(4) {
-                if list.len() != 4 {
-                    continue;
-                }
-                let a = list[0].to_digit(16).unwrap();
-                let b = list[1].to_digit(16).unwrap();
-                let c = list[2].to_digit(16).unwrap();
-                let d = list[3].to_digit(16).unwrap();
-                self.set(memory_addr, (c << 12 | d << 8 | a << 4 | b) as u16);
-                memory_addr += 1;
-            }
-        }
+        let hex = fs::read_to_string(filepath).expect("file not found");
+        self.load_hex_from_string(hex).expect("invalid hex file");
     }
 
-    pub fn load_hex_from_string(&mut self, hex: String) {
-        let hex_lines: Vec<&str> = hex.split("\n").collect();
-        let mut memory_addr = 0;
-        for line in hex_lines {
-            if line.len() < 11 {
-                continue;
-            }
-
-            let record_type = &line[7..9];
-            if record_type != "00" {
-                continue;
-            }
-
-            // TODO: need refactoring
-            let data = &line[9..line.len() - 2];
-            for list in data.chars().collect::<Vec<char>>().chunks(4) {
-                if list.len() != 4 {
-                    continue;
-                }
-                let a = list[0].to_digit(16).unwrap();
-                let b = list[1].to_digit(16).unwrap();
-                let c = list[2].to_digit(16).unwrap();
-                let d = list[3].to_digit(16).unwrap();
-                self.set(memory_addr, (c << 12 | d << 8 | a << 4 | b) as u16);
-                memory_addr += 1;
+    // Each data byte is placed at the address given by the hex record,
+    // so that images with a bootloader are loaded correctly. Nothing is
+    // loaded if the image is invalid.
+    pub fn load_hex_from_string(&mut self, hex: String) -> Result<(), HexError> {
+        let data = parse_intel_hex(&hex)?;
+        if let Some((addr, _)) = data.iter().find(|(addr, _)| addr / 2 >= self.size()) {
+            return Err(HexError::AddressOutOfRange { addr: *addr });
+        }
+        for (addr, b) in data {
+            let w = self.get(addr / 2);
+            if addr % 2 == 0 {
+                self.set(addr / 2, concat(high_byte(w), b));
+            } else {
+                self.set(addr / 2, concat(b, low_byte(w)));
             }
         }
+        Ok(())
     }
 }
 
@@ -8,9 +8,14 @@ use super::util::bit::*;
 pub enum Instr {
     ADD, ADC, ADIW, SUB, SBC, SUBI, SBCI, SBIW, DEC, COM, LD1, LD2, LD3, LDI,
     LDDY1, LDDY2, LDDY3, LDDZ1, LDDZ2, LDDZ3, LDS, OUT, IN, NOP, CALL, RCALL,
-    ROL, LSL, JMP, RJMP, AND, ANDI, OR, EOR, ORI, STS, ST1, ST2, ST3, STY1,
-    STY2, STY3, STZ1, STZ2, STZ3, LPM1, LPM2, LPM3, CP, CPI, CPC, CPSE, BREQ,
-    BRNE, BRCS, SBIS, SEI, CLI, RET, PUSH, POP, MOV, MOVW,
+    ROL, LSL, LSR, ASR, ROR, SWAP, NEG, INC, TST, BST, BLD, JMP, RJMP, IJMP,
+    EIJMP, ICALL, EICALL, AND, ANDI, OR, EOR, ORI, STS, ST1, ST2, ST3, STY1,
+    STY2, STY3, STZ1, STZ2, STZ3, LPM1, LPM2, LPM3, ELPM1,
+    ELPM2, ELPM3, SPM, CP, CPI, CPC, CPSE, BRCS,
+    BRCC, BREQ, BRNE, BRMI, BRPL, BRVS, BRVC, BRLT, BRGE, BRHS, BRHC, BRTS,
+    BRTC, BRIE, BRID, SBI, CBI, SBIS, SBIC, SBRS, SBRC, SEI, CLI, RET, RETI, PUSH, POP, MOV, MOVW, MUL,
+    MULS, MULSU, FMUL, FMULS, FMULSU, SLEEP, WDR, BREAK, SER, CLR, SEC, SEZ, SEN,
+    SEV, SES, SEH, SET, CLC, CLZ, CLN, CLV, CLS, CLH, CLT,
 }
 
 #[rustfmt::skip]
@@ -45,8 +50,8 @@ pub fn adiw(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     let (k, d_addr) = flash_memory.word(pc).operand62();
     let (dh, dl) = sram.gets(d_addr + 1, d_addr);
     let res = concat(dh, dl).wrapping_add(k as u16);
-    sram.set(d_addr, high_byte(res));
-    sram.set(d_addr + 1, low_byte(res));
+    sram.set(d_addr + 1, high_byte(res));
+    sram.set(d_addr, low_byte(res));
 
     sram.set_bit(sram.bit_map.v, !msb(dh) & msb(high_byte(res)));
     sram.set_bit(sram.bit_map.n, msb(high_byte(res)));
@@ -54,7 +59,7 @@ pub fn adiw(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     sram.set_bit(sram.bit_map.c, !msb(high_byte(res)) & msb(dh));
     sram.set_bit(sram.bit_map.s, sram.signed_test());
 
-    (pc + 1, cycle + 1)
+    (pc + 1, cycle + 2)
 }
 
 pub fn sbci(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
@@ -99,7 +104,7 @@ pub fn com(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
     let res = 0xff - d;
     sram.set(d_addr, res);
     sram.set_status_by_bit_instruction(res);
-    sram.set_bit(sram.bit_map.c, false);
+    sram.set_bit(sram.bit_map.c, true);
     (pc + 1, cycle + 1)
 }
 
@@ -121,13 +126,13 @@ pub fn sbc(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
     sram.set(d_addr, res);
 
     sram.set_bit(sram.bit_map.h, has_borrow_from_bit3_k(d, r, res));
-    sram.set_bit(sram.bit_map.v, has_2complement_overflow(d, r, res));
+    sram.set_bit(sram.bit_map.v, has_2complement_overflow_2(d, r, res));
     sram.set_bit(sram.bit_map.n, msb(res));
     if res != 0 {
         sram.set_bit(sram.bit_map.z, false);
     }
     sram.set_bit(sram.bit_map.s, sram.signed_test());
-    sram.set_bit(sram.bit_map.c, d < (r.wrapping_add(c)));
+    sram.set_bit(sram.bit_map.c, (d as u16) < r as u16 + c as u16);
 
     (pc + 1, cycle + 1)
 }
@@ -182,10 +187,11 @@ pub fn lds(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
 }
 
 pub fn lddy1(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let d_addr = flash_memory.word(pc).operand5();
-    let y_addr = sram.get_word(sram.word_map.y);
+    let w = flash_memory.word(pc);
+    let (d_addr, q) = (w.operand5(), w.operand6());
+    let y_addr = sram.get_word(sram.word_map.y) + q as u16;
     sram.set(d_addr, sram.get(y_addr as usize));
-    (pc + 1, cycle + 2) // 1 cycles in Manual
+    (pc + 1, cycle + 2)
 }
 
 pub fn lddy2(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
@@ -201,14 +207,15 @@ pub fn lddy3(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     let y_addr = sram.get_word(sram.word_map.y) - 1;
     sram.set_word(sram.word_map.y, y_addr);
     sram.set(d_addr, sram.get(y_addr as usize));
-    (pc + 1, cycle + 2)
+    (pc + 1, cycle + 3)
 }
 
 pub fn lddz1(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let d_addr = flash_memory.word(pc).operand5();
-    let z_addr = sram.get_word(sram.word_map.z);
+    let w = flash_memory.word(pc);
+    let (d_addr, q) = (w.operand5(), w.operand6());
+    let z_addr = sram.get_word(sram.word_map.z) + q as u16;
     sram.set(d_addr, sram.get(z_addr as usize));
-    (pc + 1, cycle + 2) // 1 cycles in Manual
+    (pc + 1, cycle + 2)
 }
 
 pub fn lddz2(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
@@ -224,7 +231,7 @@ pub fn lddz3(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     let z_addr = sram.get_word(sram.word_map.z) - 1;
     sram.set_word(sram.word_map.z, z_addr);
     sram.set(d_addr, sram.get(z_addr as usize));
-    (pc + 1, cycle + 2)
+    (pc + 1, cycle + 3)
 }
 
 pub fn out(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
@@ -242,12 +249,7 @@ pub fn in_instr(
 ) -> (usize, u64) {
     let (a_addr, d_addr) = flash_memory.word(pc).operand65();
     let a = sram.get(a_addr);
-    if a_addr == 0x5f {
-        // SREG
-        sram.set(d_addr, a & 0b111_1111);
-    } else {
-        sram.set(d_addr, a);
-    };
+    sram.set(d_addr, a);
     (pc + 1, cycle + 1)
 }
 
@@ -255,69 +257,176 @@ pub fn nop(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
     (pc + 1, cycle + 1)
 }
 
+// Devices with 22-bit PC take one more cycle to push and pop the
+// return address.
 pub fn call(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    sram.push_pc_stack(pc + 2);
+    let pc_size = flash_memory.pc_size();
+    sram.push_pc_stack(pc + 2, pc_size);
     let (w1, w2) = flash_memory.double_word(pc);
-    (w1.operand22(w2) as usize, cycle + 4)
+    (w1.operand22(w2) as usize, cycle + 2 + pc_size as u64)
 }
 
+// ROL Rd is ADC Rd, Rd
 pub fn rol(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let d_addr = flash_memory.word(pc).operand10() as usize;
-    let d_old = sram.get(d_addr);
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
     let c = sram.get_bit(sram.bit_map.c) as u8;
-    let d_new = (d_old << 1) | c;
-    sram.set(d_addr, d_new);
-
-    sram.set_bit(sram.bit_map.h, (d_old & 0b0000_1000) >> 3 == 1);
-    sram.set_bit(sram.bit_map.n, msb(d_new));
-    sram.set_bit(sram.bit_map.z, d_new == 0);
-    sram.set_bit(sram.bit_map.c, msb(d_old));
-    sram.set_bit(
-        sram.bit_map.v,
-        sram.get_bit(sram.bit_map.n) ^ sram.get_bit(sram.bit_map.c),
-    );
+    let res = (d << 1) | c;
+    sram.set(d_addr, res);
+    sram.set_bit(sram.bit_map.h, bit(d, 3));
+    sram.set_status_by_shift_instruction(res, msb(d));
+    (pc + 1, cycle + 1)
+}
+
+// LSL Rd is ADD Rd, Rd
+pub fn lsl(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    let res = d << 1;
+    sram.set(d_addr, res);
+    sram.set_bit(sram.bit_map.h, bit(d, 3));
+    sram.set_status_by_shift_instruction(res, msb(d));
+    (pc + 1, cycle + 1)
+}
+
+pub fn lsr(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    let res = d >> 1;
+    sram.set(d_addr, res);
+    sram.set_status_by_shift_instruction(res, lsb(d));
+    (pc + 1, cycle + 1)
+}
+
+pub fn asr(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    let res = (d & 0b1000_0000) | (d >> 1);
+    sram.set(d_addr, res);
+    sram.set_status_by_shift_instruction(res, lsb(d));
+    (pc + 1, cycle + 1)
+}
+
+pub fn ror(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    let c = sram.get_bit(sram.bit_map.c) as u8;
+    let res = (c << 7) | (d >> 1);
+    sram.set(d_addr, res);
+    sram.set_status_by_shift_instruction(res, lsb(d));
+    (pc + 1, cycle + 1)
+}
+
+pub fn swap(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    sram.set(d_addr, d.rotate_left(4));
+    (pc + 1, cycle + 1)
+}
+
+pub fn neg(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    let res = 0u8.wrapping_sub(d);
+    sram.set(d_addr, res);
+
+    sram.set_bit(sram.bit_map.h, bit(res, 3) | bit(d, 3));
+    sram.set_bit(sram.bit_map.v, res == 0x80u8);
+    sram.set_bit(sram.bit_map.n, msb(res));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    sram.set_bit(sram.bit_map.c, res != 0);
     sram.set_bit(sram.bit_map.s, sram.signed_test());
 
-    (pc + 1, cycle + 3)
+    (pc + 1, cycle + 1)
 }
 
-pub fn lsl(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let d_addr = flash_memory.word(pc).operand10() as usize;
-    let d_old = sram.get(d_addr);
-    let d_new = d_old << 1;
-    sram.set(d_addr, d_new);
-
-    sram.set_bit(sram.bit_map.h, (d_old & 0b0000_1000) >> 3 == 1);
-    sram.set_bit(sram.bit_map.n, msb(d_new));
-    sram.set_bit(sram.bit_map.z, d_new == 0);
-    sram.set_bit(sram.bit_map.c, msb(d_old));
-    sram.set_bit(
-        sram.bit_map.v,
-        sram.get_bit(sram.bit_map.n) ^ sram.get_bit(sram.bit_map.c),
-    );
+pub fn inc(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    let res = d.wrapping_add(1);
+    sram.set(d_addr, res);
+
+    sram.set_bit(sram.bit_map.v, d == 0x7fu8);
+    sram.set_bit(sram.bit_map.n, msb(res));
+    sram.set_bit(sram.bit_map.z, res == 0);
     sram.set_bit(sram.bit_map.s, sram.signed_test());
 
-    (pc + 1, cycle + 3)
+    (pc + 1, cycle + 1)
+}
+
+// TST Rd is AND Rd, Rd
+pub fn tst(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let d = sram.get(d_addr);
+    sram.set_status_by_bit_instruction(d);
+    (pc + 1, cycle + 1)
+}
+
+pub fn bst(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let w = flash_memory.word(pc);
+    let (d_addr, b) = (w.operand5(), w.operand3());
+    sram.set_bit(sram.bit_map.t, sram.get_bit((d_addr, b)));
+    (pc + 1, cycle + 1)
+}
+
+pub fn bld(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let w = flash_memory.word(pc);
+    let (d_addr, b) = (w.operand5(), w.operand3());
+    sram.set_bit((d_addr, b), sram.get_bit(sram.bit_map.t));
+    (pc + 1, cycle + 1)
 }
 
 pub fn rcall(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let k = flash_memory.word(pc).operand12() as u32;
-    let pc = pc;
-    (pc + k as usize + 1, cycle + 3)
+    let k = flash_memory.word(pc).operand12();
+    let pc_size = flash_memory.pc_size();
+    sram.push_pc_stack(pc + 1, pc_size);
+    let result = add_12bits_in_twos_complement_form(pc as u32, k) + 1;
+    (wrap_pc(flash_memory, result), cycle + 1 + pc_size as u64)
+}
+
+pub fn icall(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let pc_size = flash_memory.pc_size();
+    sram.push_pc_stack(pc + 1, pc_size);
+    let z = sram.get_word(sram.word_map.z);
+    (z as usize, cycle + 1 + pc_size as u64)
+}
+
+// EIND is only present on devices with more than 128 KB flash memory,
+// otherwise it reads as 0 and EICALL works as ICALL.
+pub fn eicall(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    sram.push_pc_stack(pc + 1, flash_memory.pc_size());
+    let eind = sram.get(sram.map.eind) as usize;
+    let z = sram.get_word(sram.word_map.z) as usize;
+    (eind << 16 | z, cycle + 4)
+}
+
+// Relative jumps and calls wrap around the flash memory.
+fn wrap_pc(flash_memory: &FlashMemory, pc: i32) -> usize {
+    pc.rem_euclid(flash_memory.size() as i32) as usize
 }
 
 pub fn jmp(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let cycle_diff = if pc == 0 { 2 } else { 3 };
     let (w1, w2) = flash_memory.double_word(pc);
     let k = w1.operand22(w2);
-    (k as usize, cycle + cycle_diff)
+    (k as usize, cycle + 3)
 }
 
 pub fn rjmp(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
     let k = flash_memory.word(pc).operand12();
     let pc = pc;
-    let result = add_12bits_in_twos_complement_form(pc as u32, k) + 1u32;
-    (result as usize, cycle + 2)
+    let result = add_12bits_in_twos_complement_form(pc as u32, k) + 1;
+    (wrap_pc(flash_memory, result), cycle + 2)
+}
+
+pub fn ijmp(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let z = sram.get_word(sram.word_map.z);
+    (z as usize, cycle + 2)
+}
+
+pub fn eijmp(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let eind = sram.get(sram.map.eind) as usize;
+    let z = sram.get_word(sram.word_map.z) as usize;
+    (eind << 16 | z, cycle + 2)
 }
 
 pub fn sts(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
@@ -349,6 +458,59 @@ pub fn lpm3(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     (pc + 1, cycle + 3)
 }
 
+// RAMPZ:Z
+fn rampz_z(sram: &SRAM) -> usize {
+    (sram.get(sram.map.rampz) as usize) << 16 | sram.get_word(sram.word_map.z) as usize
+}
+
+pub fn elpm1(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let addr = rampz_z(sram);
+    sram.set(0, flash_memory.program_memory(addr));
+    (pc + 1, cycle + 3)
+}
+
+pub fn elpm2(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let addr = rampz_z(sram);
+    sram.set(d_addr, flash_memory.program_memory(addr));
+    (pc + 1, cycle + 3)
+}
+
+pub fn elpm3(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let d_addr = flash_memory.word(pc).operand5();
+    let addr = rampz_z(sram);
+    sram.set(d_addr, flash_memory.program_memory(addr));
+    let addr = addr + 1;
+    sram.set(sram.map.rampz, (addr >> 16) as u8);
+    sram.set_word(sram.word_map.z, addr as u16);
+    (pc + 1, cycle + 3)
+}
+
+// Flash memory is programmed by SelfProgramming after this instruction.
+pub fn spm(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    (pc + 1, cycle + 1)
+}
+
+// The MCU enters the sleep mode selected by SMCR after this instruction.
+pub fn sleep(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    (pc + 1, cycle + 1)
+}
+
+// On-chip debugging is not supported, so BREAK is treated as NOP.
+pub fn break_instr(
+    sram: &mut SRAM,
+    flash_memory: &FlashMemory,
+    pc: usize,
+    cycle: u64,
+) -> (usize, u64) {
+    (pc + 1, cycle + 1)
+}
+
+// The watchdog timer is reset by the MCU after this instruction.
+pub fn wdr(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    (pc + 1, cycle + 1)
+}
+
 pub fn st1(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
     let d_addr = flash_memory.word(pc).operand5();
     let x_addr = sram.get_word(sram.word_map.x);
@@ -376,8 +538,9 @@ pub fn st3(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
 }
 
 pub fn sty1(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let d_addr = flash_memory.word(pc).operand5();
-    let y_addr = sram.get_word(sram.word_map.y);
+    let w = flash_memory.word(pc);
+    let (d_addr, q) = (w.operand5(), w.operand6());
+    let y_addr = sram.get_word(sram.word_map.y) + q as u16;
     let d = sram.get(d_addr);
     sram.set(y_addr as usize, d);
     (pc + 1, cycle + 2)
@@ -402,8 +565,9 @@ pub fn sty3(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
 }
 
 pub fn stz1(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let d_addr = flash_memory.word(pc).operand5();
-    let z_addr = sram.get_word(sram.word_map.z);
+    let w = flash_memory.word(pc);
+    let (d_addr, q) = (w.operand5(), w.operand6());
+    let z_addr = sram.get_word(sram.word_map.z) + q as u16;
     let d = sram.get(d_addr);
     sram.set(z_addr as usize, d);
     (pc + 1, cycle + 2)
@@ -457,23 +621,28 @@ pub fn cpc(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
     if res != 0 {
         sram.set_bit(sram.bit_map.z, false);
     }
-    sram.set_bit(sram.bit_map.c, d < r + c);
+    sram.set_bit(sram.bit_map.c, (d as u16) < r as u16 + c as u16);
     sram.set_bit(sram.bit_map.s, sram.signed_test());
     (pc + 1, cycle + 1)
 }
 
+// Skip the next instruction. The skip size depends on the size of the
+// next instruction.
+fn skip(flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let next_word = flash_memory.get(pc + 1 as usize);
+    let (next_instr, _) = OPCODE_TREE.with(|tree| tree.find(next_word));
+    if INSTRUCTION_32_BIT.contains(&next_instr) {
+        (pc + 3, cycle + 3)
+    } else {
+        (pc + 2, cycle + 2)
+    }
+}
+
 pub fn cpse(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
     let (r_addr, d_addr) = flash_memory.word(pc).operand55();
     let (r, d) = sram.gets(r_addr, d_addr);
     if r == d {
-        // The skip size is diffrenet by next instruction size.
-        let next_word = flash_memory.get(pc + 1 as usize);
-        let (next_instr, _) = OPCODE_TREE.with(|tree| tree.find(next_word));
-        if INSTRUCTION_32_BIT.contains(&next_instr) {
-            (pc + 3, cycle + 3)
-        } else {
-            (pc + 2, cycle + 2)
-        }
+        skip(flash_memory, pc, cycle)
     } else {
         (pc + 1, cycle + 1)
     }
@@ -524,48 +693,75 @@ pub fn eor(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -
     (pc + 1, cycle + 1)
 }
 
-pub fn breq(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    if sram.get_bit(sram.bit_map.z) {
-        let k = flash_memory.word(pc).operand7();
-        let pc = pc;
-        let result = add_7bits_in_twos_complement_form(pc as u32, k.wrapping_add(1u8));
-        (result as usize, cycle + 2)
+// BRBS and BRBC test the SREG bit s, every conditional branch (BREQ, BRCS, ...)
+// is an alias of them.
+pub fn brbs(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (k, s) = flash_memory.word(pc).operand73();
+    if sram.get_bit(sram.sreg_bit(s)) {
+        let result = add_7bits_in_twos_complement_form(pc as u32, k) + 1;
+        (wrap_pc(flash_memory, result), cycle + 2)
     } else {
         (pc + 1, cycle + 1)
     }
 }
 
-pub fn brne(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    if sram.get_bit(sram.bit_map.z) {
+pub fn brbc(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (k, s) = flash_memory.word(pc).operand73();
+    if sram.get_bit(sram.sreg_bit(s)) {
         (pc + 1, cycle + 1)
     } else {
-        let k = flash_memory.word(pc).operand7();
-        let pc = pc;
-        let result = add_7bits_in_twos_complement_form(pc as u32, k.wrapping_add(1u8));
-        (result as usize, cycle + 2)
+        let result = add_7bits_in_twos_complement_form(pc as u32, k) + 1;
+        (wrap_pc(flash_memory, result), cycle + 2)
     }
 }
 
-pub fn brcs(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    if sram.get_bit(sram.bit_map.c) {
-        let k = flash_memory.word(pc).operand7();
-        let pc = pc;
-        let result = add_7bits_in_twos_complement_form(pc as u32, k.wrapping_add(1u8));
-        (result as usize, cycle + 2)
+pub fn sbi(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (a_addr, b) = flash_memory.word(pc).operand53();
+    sram.set_bit((a_addr, b), true);
+    (pc + 1, cycle + 2)
+}
+
+pub fn cbi(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (a_addr, b) = flash_memory.word(pc).operand53();
+    sram.set_bit((a_addr, b), false);
+    (pc + 1, cycle + 2)
+}
+
+pub fn sbis(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (a_addr, b) = flash_memory.word(pc).operand53();
+    if sram.get_bit((a_addr, b)) {
+        skip(flash_memory, pc, cycle)
     } else {
         (pc + 1, cycle + 1)
     }
 }
 
-pub fn sbis(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+pub fn sbic(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
     let (a_addr, b) = flash_memory.word(pc).operand53();
-    // I/O Register starts from 0x20(0x32), so there is offset.
-    let a = sram.get((a_addr + 0x20) as usize);
-    if bit(a, b) {
-        // TODO: ATmega328p is 16bit Program Counter machine...
-        (pc + 2, cycle + 2)
+    if sram.get_bit((a_addr, b)) {
+        (pc + 1, cycle + 1)
     } else {
+        skip(flash_memory, pc, cycle)
+    }
+}
+
+pub fn sbrs(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let w = flash_memory.word(pc);
+    let (r_addr, b) = (w.operand5(), w.operand3());
+    if sram.get_bit((r_addr, b)) {
+        skip(flash_memory, pc, cycle)
+    } else {
+        (pc + 1, cycle + 1)
+    }
+}
+
+pub fn sbrc(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let w = flash_memory.word(pc);
+    let (r_addr, b) = (w.operand5(), w.operand3());
+    if sram.get_bit((r_addr, b)) {
         (pc + 1, cycle + 1)
+    } else {
+        skip(flash_memory, pc, cycle)
     }
 }
 
@@ -576,27 +772,39 @@ pub fn sbiw(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     sram.set(d_addr + 1, high_byte(result));
     sram.set(d_addr, low_byte(result));
 
-    sram.set_bit(sram.bit_map.v, msb(high_byte(result)) & !msb(dh));
+    sram.set_bit(sram.bit_map.v, !msb(high_byte(result)) & msb(dh));
     sram.set_bit(sram.bit_map.c, msb(high_byte(result)) & !msb(dh));
     sram.set_bit(sram.bit_map.n, msb(high_byte(result)));
-    sram.set_bit(sram.bit_map.z, msb(high_byte(result)));
+    sram.set_bit(sram.bit_map.z, result == 0);
     sram.set_bit(sram.bit_map.s, sram.signed_test());
     (pc + 1, cycle + 2)
 }
 
-pub fn sei(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    sram.set_bit(sram.bit_map.i, true);
+// BSET and BCLR set or clear the SREG bit s, every flag instruction
+// (SEC, CLC, SEI, CLI, ...) is an alias of them.
+pub fn bset(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let s = flash_memory.word(pc).operand_s();
+    sram.set_bit(sram.sreg_bit(s), true);
     (pc + 1, cycle + 1)
 }
 
-pub fn cli(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    sram.set_bit(sram.bit_map.i, false);
+pub fn bclr(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let s = flash_memory.word(pc).operand_s();
+    sram.set_bit(sram.sreg_bit(s), false);
     (pc + 1, cycle + 1)
 }
 
 pub fn ret(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
-    let pc = sram.pop_pc_stack();
-    (pc as usize, cycle + 4)
+    let pc_size = flash_memory.pc_size();
+    let pc = sram.pop_pc_stack(pc_size);
+    (pc, cycle + 2 + pc_size as u64)
+}
+
+pub fn reti(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let pc_size = flash_memory.pc_size();
+    let pc = sram.pop_pc_stack(pc_size);
+    sram.set_bit(sram.bit_map.i, true);
+    (pc, cycle + 2 + pc_size as u64)
 }
 
 pub fn push(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
@@ -627,3 +835,656 @@ pub fn movw(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64)
     sram.set(d_addr + 1, rh);
     (pc + 1, cycle + 1)
 }
+
+pub fn mul(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (r_addr, d_addr) = flash_memory.word(pc).operand55();
+    let (r, d) = sram.gets(r_addr, d_addr);
+    let res = d as u16 * r as u16;
+    sram.set_multiplication_result(res);
+    sram.set_bit(sram.bit_map.c, msb_u16(res));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    (pc + 1, cycle + 2)
+}
+
+pub fn muls(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (d_addr, r_addr) = flash_memory.word(pc).operand44_16();
+    let (d, r) = sram.gets(d_addr, r_addr);
+    let res = (d as i8 as i16 * r as i8 as i16) as u16;
+    sram.set_multiplication_result(res);
+    sram.set_bit(sram.bit_map.c, msb_u16(res));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    (pc + 1, cycle + 2)
+}
+
+pub fn mulsu(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (d_addr, r_addr) = flash_memory.word(pc).operand33();
+    let (d, r) = sram.gets(d_addr, r_addr);
+    let res = (d as i8 as i16 * r as i16) as u16;
+    sram.set_multiplication_result(res);
+    sram.set_bit(sram.bit_map.c, msb_u16(res));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    (pc + 1, cycle + 2)
+}
+
+// Fractional multiplications shift the product left by one bit.
+// C is taken from bit 15 of the product before the shift.
+pub fn fmul(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (d_addr, r_addr) = flash_memory.word(pc).operand33();
+    let (d, r) = sram.gets(d_addr, r_addr);
+    let product = d as u16 * r as u16;
+    let res = product << 1;
+    sram.set_multiplication_result(res);
+    sram.set_bit(sram.bit_map.c, msb_u16(product));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    (pc + 1, cycle + 2)
+}
+
+pub fn fmuls(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (d_addr, r_addr) = flash_memory.word(pc).operand33();
+    let (d, r) = sram.gets(d_addr, r_addr);
+    let product = (d as i8 as i16 * r as i8 as i16) as u16;
+    let res = product << 1;
+    sram.set_multiplication_result(res);
+    sram.set_bit(sram.bit_map.c, msb_u16(product));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    (pc + 1, cycle + 2)
+}
+
+pub fn fmulsu(sram: &mut SRAM, flash_memory: &FlashMemory, pc: usize, cycle: u64) -> (usize, u64) {
+    let (d_addr, r_addr) = flash_memory.word(pc).operand33();
+    let (d, r) = sram.gets(d_addr, r_addr);
+    let product = (d as i8 as i16 * r as i16) as u16;
+    let res = product << 1;
+    sram.set_multiplication_result(res);
+    sram.set_bit(sram.bit_map.c, msb_u16(product));
+    sram.set_bit(sram.bit_map.z, res == 0);
+    (pc + 1, cycle + 2)
+}
+
+#[cfg(test)]
+use super::arch::atmega328p::{REGISTER_BIT_MAP, REGISTER_MAP, REGISTER_WORD_MAP, SRAM_SIZE};
+
+#[cfg(test)]
+pub(crate) fn new_sram() -> SRAM {
+    let mut sram = SRAM::new(
+        SRAM_SIZE,
+        &REGISTER_MAP,
+        &REGISTER_WORD_MAP,
+        &REGISTER_BIT_MAP,
+    );
+    sram.set_word(REGISTER_WORD_MAP.sp, REGISTER_MAP.ramend as u16);
+    sram
+}
+
+// Execute the first instruction of `words` placed at 0x100 and return the
+// next pc and the number of cycles.
+#[cfg(test)]
+pub(crate) fn exec(sram: &mut SRAM, words: &[u16]) -> (usize, u64) {
+    let mut flash_memory = FlashMemory::new(0x4000);
+    for (i, w) in words.iter().enumerate() {
+        flash_memory.set(0x100 + i, *w);
+    }
+    let (_, f) = OPCODE_TREE.with(|tree| tree.find(words[0]));
+    f(sram, &flash_memory, 0x100, 0)
+}
+
+#[cfg(test)]
+fn set_all(sram: &mut SRAM, values: &[(usize, u8)]) {
+    for (a, v) in values.iter() {
+        sram.set(*a, *v);
+    }
+}
+
+#[cfg(test)]
+fn sreg(sram: &SRAM) -> u8 {
+    sram.get(sram.map.sreg)
+}
+
+#[test]
+fn test_arithmetic() {
+    let mut sram = new_sram();
+
+    // add r16, r17
+    set_all(&mut sram, &[(16, 0x7f), (17, 0x01)]);
+    assert_eq!(exec(&mut sram, &[0x0f01]), (0x101, 1));
+    assert_eq!((sram.get(16), sreg(&sram)), (0x80, 0b0010_1100));
+
+    // adc r16, r17
+    set_all(
+        &mut sram,
+        &[(16, 0xff), (17, 0x00), (REGISTER_MAP.sreg, 0b0000_0001)],
+    );
+    exec(&mut sram, &[0x1f01]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x00, 0b0010_0011));
+
+    // adiw r24, 1
+    set_all(&mut sram, &[(25, 0x00), (24, 0xff), (REGISTER_MAP.sreg, 0)]);
+    assert_eq!(exec(&mut sram, &[0x9601]), (0x101, 2));
+    assert_eq!((sram.get(25), sram.get(24), sreg(&sram)), (0x01, 0x00, 0));
+    set_all(&mut sram, &[(25, 0xff), (24, 0xff)]);
+    exec(&mut sram, &[0x9601]);
+    assert_eq!(
+        (sram.get(25), sram.get(24), sreg(&sram)),
+        (0x00, 0x00, 0b0000_0011)
+    );
+
+    // sub r16, r17
+    set_all(&mut sram, &[(16, 0x00), (17, 0x01)]);
+    exec(&mut sram, &[0x1b01]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xff, 0b0011_0101));
+
+    // subi r16, 0x01
+    sram.set(16, 0x80);
+    exec(&mut sram, &[0x5001]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x7f, 0b0011_1000));
+
+    // sbc r16, r17, C is set when Rr + C overflows
+    set_all(
+        &mut sram,
+        &[(16, 0x00), (17, 0xff), (REGISTER_MAP.sreg, 0b0000_0011)],
+    );
+    exec(&mut sram, &[0x0b01]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x00, 0b0010_0011));
+
+    // sbci r17, 0x00, Z is cleared by a non-zero result
+    set_all(&mut sram, &[(17, 0x00), (REGISTER_MAP.sreg, 0b0000_0011)]);
+    exec(&mut sram, &[0x4010]);
+    assert_eq!((sram.get(17), sreg(&sram)), (0xff, 0b0011_0101));
+
+    // sbiw r24, 1
+    set_all(&mut sram, &[(25, 0x00), (24, 0x01), (REGISTER_MAP.sreg, 0)]);
+    assert_eq!(exec(&mut sram, &[0x9701]), (0x101, 2));
+    assert_eq!(
+        (sram.get(25), sram.get(24), sreg(&sram)),
+        (0x00, 0x00, 0b0000_0010)
+    );
+    set_all(&mut sram, &[(25, 0x80), (24, 0x00)]);
+    exec(&mut sram, &[0x9701]);
+    assert_eq!(
+        (sram.get(25), sram.get(24), sreg(&sram)),
+        (0x7f, 0xff, 0b0001_1000)
+    );
+
+    // com r16
+    sram.set(16, 0x0f);
+    exec(&mut sram, &[0x9500]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xf0, 0b0001_0101));
+
+    // neg r16
+    sram.set(16, 0x01);
+    exec(&mut sram, &[0x9501]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xff, 0b0011_0101));
+
+    // inc r16
+    set_all(&mut sram, &[(16, 0x7f), (REGISTER_MAP.sreg, 0)]);
+    exec(&mut sram, &[0x9503]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x80, 0b0000_1100));
+
+    // dec r16
+    set_all(&mut sram, &[(16, 0x80), (REGISTER_MAP.sreg, 0)]);
+    exec(&mut sram, &[0x950a]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x7f, 0b0001_1000));
+}
+
+#[test]
+fn test_logic() {
+    let mut sram = new_sram();
+
+    // and r16, r17
+    set_all(&mut sram, &[(16, 0xf0), (17, 0x0f)]);
+    exec(&mut sram, &[0x2301]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x00, 0b0000_0010));
+
+    // andi r16, 0x80 (cbr r16, 0x7f)
+    sram.set(16, 0xff);
+    exec(&mut sram, &[0x7800]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x80, 0b0001_0100));
+
+    // or r16, r17
+    set_all(&mut sram, &[(16, 0xf0), (17, 0x0f)]);
+    exec(&mut sram, &[0x2b01]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xff, 0b0001_0100));
+
+    // ori r16, 0x01 (sbr r16, 0x01)
+    sram.set(16, 0x00);
+    exec(&mut sram, &[0x6001]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x01, 0b0000_0000));
+
+    // eor r16, r17
+    set_all(&mut sram, &[(16, 0xff), (17, 0x0f)]);
+    exec(&mut sram, &[0x2701]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xf0, 0b0001_0100));
+
+    // clr r16
+    exec(&mut sram, &[0x2700]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x00, 0b0000_0010));
+
+    // ser r16
+    exec(&mut sram, &[0xef0f]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xff, 0b0000_0010));
+
+    // tst r16
+    exec(&mut sram, &[0x2300]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0xff, 0b0001_0100));
+}
+
+#[test]
+fn test_multiplication() {
+    let mut sram = new_sram();
+    set_all(&mut sram, &[(16, 0x80), (17, 0xff)]);
+
+    // (instruction, R1:R0, C)
+    let cases = [
+        (0x9f01, 0x7f80, false), // mul r16, r17
+        (0x0201, 0x0080, false), // muls r16, r17
+        (0x0301, 0x8080, true),  // mulsu r16, r17
+        (0x0309, 0xff00, false), // fmul r16, r17
+        (0x0381, 0x0100, false), // fmuls r16, r17
+        (0x0389, 0x0100, true),  // fmulsu r16, r17
+    ];
+    for (w, res, c) in cases.iter() {
+        assert_eq!(exec(&mut sram, &[*w]), (0x101, 2));
+        assert_eq!(concat(sram.get(1), sram.get(0)), *res);
+        assert_eq!(sram.get_bit(sram.bit_map.c), *c);
+    }
+}
+
+#[test]
+fn test_jump_and_call() {
+    let mut sram = new_sram();
+    sram.set_word(sram.word_map.z, 0x0200);
+
+    assert_eq!(exec(&mut sram, &[0xcfff]), (0x100, 2)); // rjmp .-2
+    assert_eq!(exec(&mut sram, &[0x940c, 0x1234]), (0x1234, 3)); // jmp 0x1234
+
+    // jmp 0x0034 at the reset vector takes 3 cycles as well.
+    let mut flash_memory = FlashMemory::new(0x4000);
+    flash_memory.set(0, 0x940c);
+    flash_memory.set(1, 0x0034);
+    assert_eq!(jmp(&mut sram, &flash_memory, 0, 0), (0x34, 3));
+    // rjmp .-4, rcall .-4 and brne .-4 at 0 wrap around to the end of the
+    // flash memory.
+    flash_memory.set(0, 0xcffd);
+    assert_eq!(rjmp(&mut sram, &flash_memory, 0, 0), (0x3ffe, 2));
+    flash_memory.set(0, 0xdffd);
+    assert_eq!(rcall(&mut sram, &flash_memory, 0, 0), (0x3ffe, 3));
+    assert_eq!(sram.pop_pc_stack(2), 0x0001);
+    flash_memory.set(0, 0xf7e9);
+    sram.set_bit(sram.bit_map.z, false);
+    assert_eq!(brbc(&mut sram, &flash_memory, 0, 0), (0x3ffe, 2));
+    assert_eq!(exec(&mut sram, &[0x9409]), (0x200, 2)); // ijmp
+    assert_eq!(exec(&mut sram, &[0x9419]), (0x200, 2)); // eijmp
+
+    // rcall .+4
+    assert_eq!(exec(&mut sram, &[0xd002]), (0x103, 3));
+    assert_eq!(sram.sp(), 0x08fd);
+    assert_eq!(exec(&mut sram, &[0x9508]), (0x101, 4)); // ret
+
+    // call 0x0200
+    assert_eq!(exec(&mut sram, &[0x940e, 0x0200]), (0x200, 4));
+    assert_eq!(exec(&mut sram, &[0x9518]), (0x102, 4)); // reti
+    assert!(sram.get_bit(sram.bit_map.i));
+
+    assert_eq!(exec(&mut sram, &[0x9509]), (0x200, 3)); // icall
+    assert_eq!(exec(&mut sram, &[0x9519]), (0x200, 4)); // eicall
+    assert_eq!(sram.pop_pc_stack(2), 0x101);
+    assert_eq!(sram.pop_pc_stack(2), 0x101);
+    assert_eq!(sram.sp(), 0x08ff);
+}
+
+// The return address is pushed from its low byte and RET pops it back.
+#[test]
+fn test_return_address() {
+    let mut sram = new_sram();
+    sram.set_word(sram.word_map.z, 0x0200);
+    // call 0x0200, rcall .+4 and icall at 0x100
+    for (words, next, ret) in [
+        (&[0x940e, 0x0200][..], 0x200, 0x102),
+        (&[0xd002][..], 0x103, 0x101),
+        (&[0x9509][..], 0x200, 0x101),
+    ]
+    .iter()
+    {
+        assert_eq!(exec(&mut sram, words).0, *next);
+        assert_eq!(sram.sp(), 0x08fd);
+        assert_eq!((sram.get(0x08ff), sram.get(0x08fe)), (*ret as u8, 0x01));
+        assert_eq!(exec(&mut sram, &[0x9508]), (*ret, 4)); // ret
+        assert_eq!(sram.sp(), 0x08ff);
+    }
+}
+
+#[test]
+fn test_branch() {
+    let mut sram = new_sram();
+
+    // breq .+4, brne .+4
+    sram.set_bit(sram.bit_map.z, true);
+    assert_eq!(exec(&mut sram, &[0xf011]), (0x103, 2));
+    assert_eq!(exec(&mut sram, &[0xf411]), (0x101, 1));
+    // brne .-2
+    sram.set_bit(sram.bit_map.z, false);
+    assert_eq!(exec(&mut sram, &[0xf7f9]), (0x100, 2));
+
+    // brbs s, .+4 / brbc s, .+4 for every bit of SREG, e.g. BRCS and BRCC
+    for s in 0..8 {
+        sram.set(REGISTER_MAP.sreg, 1 << s);
+        assert_eq!(exec(&mut sram, &[0xf010 | s]), (0x103, 2));
+        assert_eq!(exec(&mut sram, &[0xf410 | s]), (0x101, 1));
+        sram.set(REGISTER_MAP.sreg, !(1 << s));
+        assert_eq!(exec(&mut sram, &[0xf010 | s]), (0x101, 1));
+        assert_eq!(exec(&mut sram, &[0xf410 | s]), (0x103, 2));
+    }
+    sram.set(REGISTER_MAP.sreg, 0);
+
+    // cp r16, r17
+    set_all(&mut sram, &[(16, 0x01), (17, 0x02)]);
+    exec(&mut sram, &[0x1701]);
+    assert_eq!((sram.get(16), sreg(&sram)), (0x01, 0b0011_0101));
+
+    // cpc r16, r17, Z is kept by a zero result
+    set_all(
+        &mut sram,
+        &[(16, 0x00), (17, 0xff), (REGISTER_MAP.sreg, 0b0000_0011)],
+    );
+    exec(&mut sram, &[0x0701]);
+    assert_eq!(sreg(&sram), 0b0010_0011);
+
+    // cpi r16, 0x10
+    sram.set(16, 0x10);
+    exec(&mut sram, &[0x3100]);
+    assert_eq!(sreg(&sram), 0b0000_0010);
+}
+
+#[test]
+fn test_skip() {
+    let mut sram = new_sram();
+    let nop = 0x0000;
+    let jmp = 0x940c;
+
+    // cpse r16, r17
+    set_all(&mut sram, &[(16, 0x01), (17, 0x01)]);
+    assert_eq!(exec(&mut sram, &[0x1301, nop]), (0x102, 2));
+    assert_eq!(exec(&mut sram, &[0x1301, jmp, 0]), (0x103, 3));
+    sram.set(17, 0x02);
+    assert_eq!(exec(&mut sram, &[0x1301, nop]), (0x101, 1));
+
+    // sbrc r16, 0, sbrs r16, 0
+    assert_eq!(exec(&mut sram, &[0xfd00, nop]), (0x101, 1));
+    assert_eq!(exec(&mut sram, &[0xff00, nop]), (0x102, 2));
+
+    // sbic 0x05, 5, sbis 0x05, 5
+    sram.set(REGISTER_MAP.portb, 0b0010_0000);
+    assert_eq!(exec(&mut sram, &[0x992d, nop]), (0x101, 1));
+    assert_eq!(exec(&mut sram, &[0x9b2d, jmp, 0]), (0x103, 3));
+}
+
+#[test]
+fn test_data_transfer() {
+    let mut sram = new_sram();
+
+    // mov r16, r17
+    sram.set(17, 0x5a);
+    exec(&mut sram, &[0x2f01]);
+    assert_eq!(sram.get(16), 0x5a);
+
+    // movw r31:r30, r1:r0
+    set_all(&mut sram, &[(1, 0x12), (0, 0x34)]);
+    assert_eq!(exec(&mut sram, &[0x01f0]), (0x101, 1));
+    assert_eq!(sram.get_word(sram.word_map.z), 0x1234);
+    // movw r1:r0, r1:r0
+    exec(&mut sram, &[0x0100]);
+    assert_eq!((sram.get(1), sram.get(0)), (0x12, 0x34));
+
+    // ldi r16, 0x5a
+    exec(&mut sram, &[0xe50a]);
+    assert_eq!(sram.get(16), 0x5a);
+
+    // lds r16, k for data memory, I/O register and register file
+    for (k, v) in [(0x0100, 0x11), (0x0025, 0x22), (0x0011, 0x33)].iter() {
+        sram.set(*k as usize, *v);
+        assert_eq!(exec(&mut sram, &[0x9100, *k]), (0x102, 2));
+        assert_eq!(sram.get(16), *v);
+    }
+    // sts k, r16
+    for k in [0x0100, 0x0025, 0x0011].iter() {
+        sram.set(16, *k as u8);
+        assert_eq!(exec(&mut sram, &[0x9300, *k]), (0x102, 2));
+        assert_eq!(sram.get(*k as usize), *k as u8);
+    }
+
+    // ld r16, X / X+ / -X
+    set_all(&mut sram, &[(0x0200, 0x01), (0x0201, 0x02)]);
+    sram.set_word(sram.word_map.x, 0x0200);
+    assert_eq!(exec(&mut sram, &[0x910c]), (0x101, 2));
+    assert_eq!(sram.get(16), 0x01);
+    exec(&mut sram, &[0x910d]);
+    assert_eq!(sram.get_word(sram.word_map.x), 0x0201);
+    assert_eq!(exec(&mut sram, &[0x910e]), (0x101, 3));
+    assert_eq!(
+        (sram.get(16), sram.get_word(sram.word_map.x)),
+        (0x01, 0x0200)
+    );
+
+    // ldd r16, Y+63 / ld r16, Y+ / ld r16, -Y
+    sram.set(0x023f, 0x3f);
+    sram.set_word(sram.word_map.y, 0x0200);
+    exec(&mut sram, &[0xad0f]);
+    assert_eq!(sram.get(16), 0x3f);
+    exec(&mut sram, &[0x9109]);
+    assert_eq!(
+        (sram.get(16), sram.get_word(sram.word_map.y)),
+        (0x01, 0x0201)
+    );
+    assert_eq!(exec(&mut sram, &[0x910a]), (0x101, 3));
+    assert_eq!(sram.get_word(sram.word_map.y), 0x0200);
+
+    // ldd r16, Z+1 / ld r16, Z+ / ld r16, -Z
+    sram.set_word(sram.word_map.z, 0x0200);
+    exec(&mut sram, &[0x8101]);
+    assert_eq!(sram.get(16), 0x02);
+    exec(&mut sram, &[0x9101]);
+    assert_eq!(
+        (sram.get(16), sram.get_word(sram.word_map.z)),
+        (0x01, 0x0201)
+    );
+    assert_eq!(exec(&mut sram, &[0x9102]), (0x101, 3));
+    assert_eq!(sram.get_word(sram.word_map.z), 0x0200);
+
+    // st X+, r16 / st -X, r16 / st X, r16
+    sram.set(16, 0xa0);
+    sram.set_word(sram.word_map.x, 0x0300);
+    assert_eq!(exec(&mut sram, &[0x930d]), (0x101, 2));
+    exec(&mut sram, &[0x930e]);
+    exec(&mut sram, &[0x930c]);
+    assert_eq!(
+        (sram.get(0x0300), sram.get_word(sram.word_map.x)),
+        (0xa0, 0x0300)
+    );
+
+    // std Y+2, r16 / st Y+, r16 / st -Y, r16
+    sram.set_word(sram.word_map.y, 0x0310);
+    exec(&mut sram, &[0x830a]);
+    exec(&mut sram, &[0x9309]);
+    exec(&mut sram, &[0x930a]);
+    assert_eq!((sram.get(0x0312), sram.get(0x0310)), (0xa0, 0xa0));
+    assert_eq!(sram.get_word(sram.word_map.y), 0x0310);
+
+    // std Z+1, r16 / st Z+, r16 / st -Z, r16
+    sram.set_word(sram.word_map.z, 0x0320);
+    exec(&mut sram, &[0x8301]);
+    exec(&mut sram, &[0x9301]);
+    exec(&mut sram, &[0x9302]);
+    assert_eq!((sram.get(0x0321), sram.get(0x0320)), (0xa0, 0xa0));
+    assert_eq!(sram.get_word(sram.word_map.z), 0x0320);
+
+    // in r16, SREG / out SREG, r16
+    sram.set(REGISTER_MAP.sreg, 0b1000_0001);
+    exec(&mut sram, &[0xb70f]);
+    assert_eq!(sram.get(16), 0b1000_0001);
+    sram.set(16, 0b0000_0010);
+    exec(&mut sram, &[0xbf0f]);
+    assert_eq!(sreg(&sram), 0b0000_0010);
+}
+
+// Encode `ldd r16, Y+q` / `ldd r16, Z+q`, or `std` of the same operands.
+#[cfg(test)]
+fn displacement(is_store: bool, is_y: bool, q: u16) -> u16 {
+    let base = 0x8000 | (is_store as u16) << 9 | (is_y as u16) << 3;
+    base | (q & 0x20) << 8 | (q & 0x18) << 7 | 16 << 4 | (q & 0x07)
+}
+
+#[test]
+fn test_displacement() {
+    let mut sram = new_sram();
+    for (is_y, ptr) in [(true, sram.word_map.y), (false, sram.word_map.z)].iter() {
+        for q in 0..64 {
+            // ldd r16, Y+q / Z+q
+            for i in 0..64 {
+                sram.set(0x0400 + i, 0x80 | i as u8);
+            }
+            sram.set_word(*ptr, 0x0400);
+            assert_eq!(
+                exec(&mut sram, &[displacement(false, *is_y, q)]),
+                (0x101, 2)
+            );
+            assert_eq!(sram.get(16), 0x80 | q as u8);
+            assert_eq!(sram.get_word(*ptr), 0x0400);
+
+            // std Y+q / Z+q, r16
+            for i in 0..64 {
+                sram.set(0x0500 + i, 0);
+            }
+            sram.set(16, 0x5a);
+            sram.set_word(*ptr, 0x0500);
+            assert_eq!(exec(&mut sram, &[displacement(true, *is_y, q)]), (0x101, 2));
+            for i in 0..64 {
+                let expected = if i == q as usize { 0x5a } else { 0 };
+                assert_eq!(sram.get(0x0500 + i), expected, "q: {}, i: {}", q, i);
+            }
+            assert_eq!(sram.get_word(*ptr), 0x0500);
+        }
+    }
+}
+
+#[test]
+fn test_program_memory() {
+    let mut sram = new_sram();
+    // The instruction itself is read as data at 0x100 words = 0x200 bytes.
+    sram.set_word(sram.word_map.z, 0x0200);
+
+    assert_eq!(exec(&mut sram, &[0x95c8]), (0x101, 3)); // lpm
+    assert_eq!(sram.get(0), 0xc8);
+    exec(&mut sram, &[0x9105]); // lpm r16, Z+
+    assert_eq!(
+        (sram.get(16), sram.get_word(sram.word_map.z)),
+        (0x05, 0x0201)
+    );
+    exec(&mut sram, &[0x9104, 0x0000]); // lpm r16, Z
+    assert_eq!(sram.get(16), 0x91);
+
+    sram.set_word(sram.word_map.z, 0x0200);
+    assert_eq!(exec(&mut sram, &[0x95d8]), (0x101, 3)); // elpm
+    assert_eq!(sram.get(0), 0xd8);
+    exec(&mut sram, &[0x9107]); // elpm r16, Z+
+    assert_eq!(
+        (sram.get(16), sram.get_word(sram.word_map.z)),
+        (0x07, 0x0201)
+    );
+    exec(&mut sram, &[0x9106]); // elpm r16, Z
+    assert_eq!(sram.get(16), 0x91);
+
+    // The upper bits of Z and RAMPZ beyond the flash size are ignored.
+    sram.set_word(sram.word_map.z, 0xffff);
+    exec(&mut sram, &[0x95c8]); // lpm
+    assert_eq!(sram.get(0), 0x00);
+    sram.set_word(sram.word_map.z, 0x8200);
+    exec(&mut sram, &[0x95c8]); // lpm
+    assert_eq!(sram.get(0), 0xc8);
+    sram.set(REGISTER_MAP.rampz, 0x01);
+    sram.set_word(sram.word_map.z, 0x0200);
+    exec(&mut sram, &[0x95d8]); // elpm
+    assert_eq!(sram.get(0), 0xd8);
+    sram.set(REGISTER_MAP.rampz, 0);
+
+    // The page is erased or written by SelfProgramming after SPM.
+    assert_eq!(exec(&mut sram, &[0x95e8]), (0x101, 1)); // spm
+}
+
+#[test]
+fn test_stack() {
+    let mut sram = new_sram();
+
+    // push r16 / pop r17
+    sram.set(16, 0x5a);
+    assert_eq!(exec(&mut sram, &[0x930f]), (0x101, 2));
+    assert_eq!((sram.get(0x08ff), sram.sp()), (0x5a, 0x08fe));
+    assert_eq!(exec(&mut sram, &[0x911f]), (0x101, 2));
+    assert_eq!((sram.get(17), sram.sp()), (0x5a, 0x08ff));
+
+    // Save and restore SREG through the stack.
+    sram.set(REGISTER_MAP.sreg, 0b1000_0011);
+    exec(&mut sram, &[0xb70f]); // in r16, SREG
+    exec(&mut sram, &[0x930f]); // push r16
+    sram.set(REGISTER_MAP.sreg, 0);
+    exec(&mut sram, &[0x910f]); // pop r16
+    exec(&mut sram, &[0xbf0f]); // out SREG, r16
+    assert_eq!(sreg(&sram), 0b1000_0011);
+}
+
+#[test]
+fn test_bit_and_flag() {
+    let mut sram = new_sram();
+
+    // sbi 0x05, 5 / cbi 0x05, 5
+    assert_eq!(exec(&mut sram, &[0x9a2d]), (0x101, 2));
+    assert_eq!(sram.get(REGISTER_MAP.portb), 0b0010_0000);
+    assert_eq!(exec(&mut sram, &[0x982d]), (0x101, 2));
+    assert_eq!(sram.get(REGISTER_MAP.portb), 0b0000_0000);
+
+    // bst r16, 7 / bld r17, 0
+    set_all(&mut sram, &[(16, 0x80), (17, 0x00)]);
+    exec(&mut sram, &[0xfb07]);
+    exec(&mut sram, &[0xf910]);
+    assert_eq!(sram.get(17), 0x01);
+
+    // bset s / bclr s (sec, sez, ..., sei / clc, clz, ..., cli)
+    sram.set(REGISTER_MAP.sreg, 0);
+    for s in 0..8 {
+        assert_eq!(exec(&mut sram, &[0x9408 | s << 4]), (0x101, 1));
+        assert_eq!(sreg(&sram), ((1u16 << (s + 1)) - 1) as u8);
+    }
+    for s in 0..8 {
+        exec(&mut sram, &[0x9488 | s << 4]);
+        assert_eq!(sreg(&sram), !((1u16 << (s + 1)) - 1) as u8);
+    }
+}
+
+#[test]
+fn test_shift() {
+    let mut sram = new_sram();
+
+    // (instruction, Rd, C, result, SREG)
+    let cases = [
+        (0x0f00, 0x88, false, 0x10, 0b0011_1001), // lsl r16
+        (0x1f00, 0x08, true, 0x11, 0b0010_0000),  // rol r16
+        (0x9506, 0x81, false, 0x40, 0b0001_1001), // lsr r16
+        (0x9505, 0x81, false, 0xc0, 0b0001_0101), // asr r16
+        (0x9507, 0x02, true, 0x81, 0b0000_1100),  // ror r16
+        (0x9502, 0x12, true, 0x21, 0b0000_0001),  // swap r16
+    ];
+    for (w, d, c, res, s) in cases.iter() {
+        sram.set(16, *d);
+        sram.set(REGISTER_MAP.sreg, *c as u8);
+        assert_eq!(exec(&mut sram, &[*w]), (0x101, 1));
+        assert_eq!((sram.get(16), sreg(&sram)), (*res, *s));
+    }
+}
+
+#[test]
+fn test_mcu_control() {
+    let mut sram = new_sram();
+    for w in [0x0000, 0x9588, 0x95a8, 0x9598].iter() {
+        // nop, sleep, wdr, break
+        assert_eq!(exec(&mut sram, &[*w]), (0x101, 1));
+    }
+}
@@ -0,0 +1,210 @@
+use super::opcode_tree::*;
+
+// The AVRe+ instruction set of ATmega328P, plus the instructions of the
+// other AVR cores which are not available on it.
+// (mnemonic, example opcode, cycles, test)
+// Cycles separated by "/" depend on the condition (not taken / taken /
+// skipping a 2-word instruction). Cycles of the 16-bit PC devices, checked
+// against the emulator by test_cycles.
+#[rustfmt::skip]
+pub const INSTRUCTION_SET: [(&str, u16, &str, &str); 127] = [
+    // Arithmetic and logic
+    ("ADD Rd, Rr",     0x0f01, "1",     "instruction::test_arithmetic"),
+    ("ADC Rd, Rr",     0x1f01, "1",     "instruction::test_arithmetic"),
+    ("ADIW Rd, K",     0x9601, "2",     "instruction::test_arithmetic"),
+    ("SUB Rd, Rr",     0x1b01, "1",     "instruction::test_arithmetic"),
+    ("SUBI Rd, K",     0x5001, "1",     "instruction::test_arithmetic"),
+    ("SBC Rd, Rr",     0x0b01, "1",     "instruction::test_arithmetic"),
+    ("SBCI Rd, K",     0x4010, "1",     "instruction::test_arithmetic"),
+    ("SBIW Rd, K",     0x9701, "2",     "instruction::test_arithmetic"),
+    ("AND Rd, Rr",     0x2301, "1",     "instruction::test_logic"),
+    ("ANDI Rd, K",     0x7800, "1",     "instruction::test_logic"),
+    ("OR Rd, Rr",      0x2b01, "1",     "instruction::test_logic"),
+    ("ORI Rd, K",      0x6001, "1",     "instruction::test_logic"),
+    ("EOR Rd, Rr",     0x2701, "1",     "instruction::test_logic"),
+    ("COM Rd",         0x9500, "1",     "instruction::test_arithmetic"),
+    ("NEG Rd",         0x9501, "1",     "instruction::test_arithmetic"),
+    ("SBR Rd, K",      0x6001, "1",     "instruction::test_logic"),
+    ("CBR Rd, K",      0x7800, "1",     "instruction::test_logic"),
+    ("INC Rd",         0x9503, "1",     "instruction::test_arithmetic"),
+    ("DEC Rd",         0x950a, "1",     "instruction::test_arithmetic"),
+    ("TST Rd",         0x2300, "1",     "instruction::test_logic"),
+    ("CLR Rd",         0x2700, "1",     "instruction::test_logic"),
+    ("SER Rd",         0xef0f, "1",     "instruction::test_logic"),
+    ("MUL Rd, Rr",     0x9f01, "2",     "instruction::test_multiplication"),
+    ("MULS Rd, Rr",    0x0201, "2",     "instruction::test_multiplication"),
+    ("MULSU Rd, Rr",   0x0301, "2",     "instruction::test_multiplication"),
+    ("FMUL Rd, Rr",    0x0309, "2",     "instruction::test_multiplication"),
+    ("FMULS Rd, Rr",   0x0381, "2",     "instruction::test_multiplication"),
+    ("FMULSU Rd, Rr",  0x0389, "2",     "instruction::test_multiplication"),
+    ("DES K",          0x940b, "-",     "-"),
+    // Change of flow
+    ("RJMP k",         0xcfff, "2",     "instruction::test_jump_and_call"),
+    ("IJMP",           0x9409, "2",     "instruction::test_jump_and_call"),
+    ("EIJMP",          0x9419, "2",     "instruction::test_jump_and_call"),
+    ("JMP k",          0x940c, "3",     "instruction::test_jump_and_call"),
+    ("RCALL k",        0xd002, "3",     "instruction::test_jump_and_call"),
+    ("ICALL",          0x9509, "3",     "instruction::test_jump_and_call"),
+    ("EICALL",         0x9519, "4",     "instruction::test_jump_and_call"),
+    ("CALL k",         0x940e, "4",     "instruction::test_jump_and_call"),
+    ("RET",            0x9508, "4",     "instruction::test_jump_and_call"),
+    ("RETI",           0x9518, "4",     "instruction::test_jump_and_call"),
+    ("CPSE Rd, Rr",    0x1301, "1/2/3", "instruction::test_skip"),
+    ("CP Rd, Rr",      0x1701, "1",     "instruction::test_branch"),
+    ("CPC Rd, Rr",     0x0701, "1",     "instruction::test_branch"),
+    ("CPI Rd, K",      0x3100, "1",     "instruction::test_branch"),
+    ("SBRC Rr, b",     0xfd00, "1/2/3", "instruction::test_skip"),
+    ("SBRS Rr, b",     0xff00, "1/2/3", "instruction::test_skip"),
+    ("SBIC A, b",      0x992d, "1/2/3", "instruction::test_skip"),
+    ("SBIS A, b",      0x9b2d, "1/2/3", "instruction::test_skip"),
+    ("BRBS s, k",      0xf011, "1/2",   "instruction::test_branch"),
+    ("BRBC s, k",      0xf411, "1/2",   "instruction::test_branch"),
+    ("BREQ k",         0xf011, "1/2",   "instruction::test_branch"),
+    ("BRNE k",         0xf411, "1/2",   "instruction::test_branch"),
+    ("BRCS k",         0xf010, "1/2",   "instruction::test_branch"),
+    ("BRCC k",         0xf410, "1/2",   "instruction::test_branch"),
+    ("BRSH k",         0xf410, "1/2",   "instruction::test_branch"),
+    ("BRLO k",         0xf010, "1/2",   "instruction::test_branch"),
+    ("BRMI k",         0xf012, "1/2",   "instruction::test_branch"),
+    ("BRPL k",         0xf412, "1/2",   "instruction::test_branch"),
+    ("BRGE k",         0xf414, "1/2",   "instruction::test_branch"),
+    ("BRLT k",         0xf014, "1/2",   "instruction::test_branch"),
+    ("BRHS k",         0xf015, "1/2",   "instruction::test_branch"),
+    ("BRHC k",         0xf415, "1/2",   "instruction::test_branch"),
+    ("BRTS k",         0xf016, "1/2",   "instruction::test_branch"),
+    ("BRTC k",         0xf416, "1/2",   "instruction::test_branch"),
+    ("BRVS k",         0xf013, "1/2",   "instruction::test_branch"),
+    ("BRVC k",         0xf413, "1/2",   "instruction::test_branch"),
+    ("BRIE k",         0xf017, "1/2",   "instruction::test_branch"),
+    ("BRID k",         0xf417, "1/2",   "instruction::test_branch"),
+    // Data transfer
+    ("MOV Rd, Rr",     0x2f01, "1",     "instruction::test_data_transfer"),
+    ("MOVW Rd, Rr",    0x01f0, "1",     "instruction::test_data_transfer"),
+    ("LDI Rd, K",      0xe50a, "1",     "instruction::test_data_transfer"),
+    ("LDS Rd, k",      0x9100, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, X",       0x910c, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, X+",      0x910d, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, -X",      0x910e, "3",     "instruction::test_data_transfer"),
+    ("LD Rd, Y",       0x8108, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, Y+",      0x9109, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, -Y",      0x910a, "3",     "instruction::test_data_transfer"),
+    ("LDD Rd, Y+q",    0xad0f, "2",     "instruction::test_displacement"),
+    ("LD Rd, Z",       0x8100, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, Z+",      0x9101, "2",     "instruction::test_data_transfer"),
+    ("LD Rd, -Z",      0x9102, "3",     "instruction::test_data_transfer"),
+    ("LDD Rd, Z+q",    0x8101, "2",     "instruction::test_displacement"),
+    ("STS k, Rr",      0x9300, "2",     "instruction::test_data_transfer"),
+    ("ST X, Rr",       0x930c, "2",     "instruction::test_data_transfer"),
+    ("ST X+, Rr",      0x930d, "2",     "instruction::test_data_transfer"),
+    ("ST -X, Rr",      0x930e, "2",     "instruction::test_data_transfer"),
+    ("ST Y, Rr",       0x8308, "2",     "instruction::test_data_transfer"),
+    ("ST Y+, Rr",      0x9309, "2",     "instruction::test_data_transfer"),
+    ("ST -Y, Rr",      0x930a, "2",     "instruction::test_data_transfer"),
+    ("STD Y+q, Rr",    0x830a, "2",     "instruction::test_displacement"),
+    ("ST Z, Rr",       0x8300, "2",     "instruction::test_data_transfer"),
+    ("ST Z+, Rr",      0x9301, "2",     "instruction::test_data_transfer"),
+    ("ST -Z, Rr",      0x9302, "2",     "instruction::test_data_transfer"),
+    ("STD Z+q, Rr",    0x8301, "2",     "instruction::test_displacement"),
+    ("LPM",            0x95c8, "3",     "instruction::test_program_memory"),
+    ("LPM Rd, Z",      0x9104, "3",     "instruction::test_program_memory"),
+    ("LPM Rd, Z+",     0x9105, "3",     "instruction::test_program_memory"),
+    ("ELPM",           0x95d8, "3",     "instruction::test_program_memory"),
+    ("ELPM Rd, Z",     0x9106, "3",     "instruction::test_program_memory"),
+    ("ELPM Rd, Z+",    0x9107, "3",     "instruction::test_program_memory"),
+    ("SPM",            0x95e8, "1",     "instruction::test_program_memory"),
+    ("IN Rd, A",       0xb70f, "1",     "instruction::test_data_transfer"),
+    ("OUT A, Rr",      0xbf0f, "1",     "instruction::test_data_transfer"),
+    ("PUSH Rr",        0x930f, "2",     "instruction::test_stack"),
+    ("POP Rd",         0x911f, "2",     "instruction::test_stack"),
+    ("XCH Z, Rd",      0x9304, "-",     "-"),
+    ("LAS Z, Rd",      0x9305, "-",     "-"),
+    ("LAC Z, Rd",      0x9306, "-",     "-"),
+    ("LAT Z, Rd",      0x9307, "-",     "-"),
+    // Bit and bit-test
+    ("LSL Rd",         0x0f00, "1",     "instruction::test_shift"),
+    ("LSR Rd",         0x9506, "1",     "instruction::test_shift"),
+    ("ROL Rd",         0x1f00, "1",     "instruction::test_shift"),
+    ("ROR Rd",         0x9507, "1",     "instruction::test_shift"),
+    ("ASR Rd",         0x9505, "1",     "instruction::test_shift"),
+    ("SWAP Rd",        0x9502, "1",     "instruction::test_shift"),
+    ("SBI A, b",       0x9a2d, "2",     "instruction::test_bit_and_flag"),
+    ("CBI A, b",       0x982d, "2",     "instruction::test_bit_and_flag"),
+    ("BST Rr, b",      0xfb07, "1",     "instruction::test_bit_and_flag"),
+    ("BLD Rd, b",      0xf910, "1",     "instruction::test_bit_and_flag"),
+    ("BSET s",         0x9408, "1",     "instruction::test_bit_and_flag"),
+    ("BCLR s",         0x9488, "1",     "instruction::test_bit_and_flag"),
+    ("SEC/SEZ/SEN/SEV/SES/SEH/SET/SEI", 0x9478, "1", "instruction::test_bit_and_flag"),
+    ("CLC/CLZ/CLN/CLV/CLS/CLH/CLT/CLI", 0x94f8, "1", "instruction::test_bit_and_flag"),
+    // MCU control
+    ("BREAK",          0x9598, "1",     "instruction::test_mcu_control"),
+    ("NOP",            0x0000, "1",     "instruction::test_mcu_control"),
+    ("SLEEP",          0x9588, "1",     "instruction::test_mcu_control"),
+    ("WDR",            0x95a8, "1",     "instruction::test_mcu_control"),
+];
+
+pub fn is_implemented(opcode: u16) -> bool {
+    OPCODE_TREE.with(|tree| tree.decode(opcode).is_some())
+}
+
+// Markdown table of the instruction set, whether each instruction is
+// decoded by the emulator is checked with its example opcode.
+pub fn instruction_table() -> String {
+    let mut table = String::from(
+        "| Mnemonic | Implemented | Cycles | Test |\n|----------|-------------|--------|------|\n",
+    );
+    for (mnemonic, opcode, cycles, test) in INSTRUCTION_SET.iter() {
+        let implemented = if is_implemented(*opcode) { "yes" } else { "no" };
+        table.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            mnemonic, implemented, cycles, test
+        ));
+    }
+    table
+}
+
+#[test]
+fn test_instruction_set() {
+    for (mnemonic, opcode, _, test) in INSTRUCTION_SET.iter() {
+        assert_eq!(is_implemented(*opcode), *test != "-", "{}", mnemonic);
+    }
+}
+
+// Every implemented instruction is executed with the registers, SREG and
+// the I/O registers 0x00 ~ 0x1f all cleared, all set or set to their own
+// addresses, followed by a 1-word or a 2-word instruction, so that each
+// cycle count of the table is observed.
+#[test]
+fn test_cycles() {
+    use super::instruction::{exec, new_sram};
+    use std::collections::BTreeSet;
+
+    for (mnemonic, opcode, cycles, _) in INSTRUCTION_SET.iter() {
+        if !is_implemented(*opcode) {
+            continue;
+        }
+        let expected = cycles
+            .split('/')
+            .map(|c| c.parse().unwrap())
+            .collect::<BTreeSet<u64>>();
+        let mut measured = BTreeSet::new();
+        for fill in 0..3 {
+            for next in [0x0000, 0x940c].iter() {
+                // The second word of LDS and STS must be a valid address.
+                if *next == 0x940c && expected.len() < 3 {
+                    continue;
+                }
+                let mut sram = new_sram();
+                for addr in (0..26).chain(0x20..0x40).chain(0x5f..0x60) {
+                    sram.set(addr, [0x00, 0xff, addr as u8][fill]);
+                }
+                for p in [sram.word_map.x, sram.word_map.y, sram.word_map.z].iter() {
+                    sram.set_word(*p, 0x0100);
+                }
+                // RET and RETI pop within the data memory.
+                sram.set_word(sram.word_map.sp, 0x08f0);
+                measured.insert(exec(&mut sram, &[*opcode, *next, 0]).1);
+            }
+        }
+        assert_eq!(measured, expected, "{}", mnemonic);
+    }
+}
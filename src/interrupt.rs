@@ -0,0 +1,275 @@
+use super::sram::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Trigger {
+    // The flag is cleared by hardware when the vector is executed.
+    Flag,
+    // Requested while the flag is set (e.g. RXCn, UDREn).
+    Level,
+    // Requested while the flag is cleared (e.g. SELFPRGEN for SPM READY).
+    LevelLow,
+    // Same as Flag, and also requested while `pin` is low if the sense
+    // control bits from `isc` are 00 (e.g. INT0 on the low level).
+    FlagOrLowLevel {
+        pin: RegisterBitAddr,
+        isc: RegisterBitAddr,
+    },
+}
+
+// An interrupt source. `addr` is the program address of the vector,
+// lower addresses have higher priority.
+#[derive(Debug)]
+pub struct Interrupt {
+    pub addr: usize,
+    pub enable: RegisterBitAddr,
+    pub flag: RegisterBitAddr,
+    pub trigger: Trigger,
+}
+
+impl Interrupt {
+    fn is_requested(&self, sram: &SRAM) -> bool {
+        let flag = sram.get_bit(self.flag);
+        sram.get_bit(self.enable)
+            && match self.trigger {
+                Trigger::Flag | Trigger::Level => flag,
+                Trigger::LevelLow => !flag,
+                Trigger::FlagOrLowLevel { pin, isc } => {
+                    let is_low_level = (sram.get(isc.0) >> isc.1) & 0b11 == 0;
+                    flag || is_low_level && !sram.get_bit(pin)
+                }
+            }
+    }
+}
+
+pub struct InterruptController {
+    sram: Rc<RefCell<SRAM>>,
+    table: &'static [Interrupt],
+    pc_size: usize,
+}
+
+impl InterruptController {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        table: &'static [Interrupt],
+        pc_size: usize,
+    ) -> InterruptController {
+        InterruptController {
+            sram: sram,
+            table: table,
+            pc_size: pc_size,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        let sram = self.sram.borrow();
+        sram.get_bit(sram.bit_map.i)
+    }
+
+    // The table is ordered by vector address, so the first match has the
+    // highest priority.
+    pub fn pending(&self) -> Option<&'static Interrupt> {
+        let sram = self.sram.borrow();
+        self.table.iter().find(|i| i.is_requested(&sram))
+    }
+
+    // Take the pending interrupt if the global interrupt flag is set.
+    // PC is pushed onto the stack, I is cleared and the program jumps to
+    // the vector. The response takes 4 clock cycles (5 with 22-bit PC).
+    pub fn next(&mut self, pc: usize, cycle: u64) -> Option<(usize, u64)> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let interrupt = self.pending()?;
+
+        let mut sram = self.sram.borrow_mut();
+        if let Trigger::Flag | Trigger::FlagOrLowLevel { .. } = interrupt.trigger {
+            sram.set_bit(interrupt.flag, false);
+        }
+        let i = sram.bit_map.i;
+        sram.push_pc_stack(pc, self.pc_size);
+        sram.set_bit(i, false);
+        Some((interrupt.addr, cycle + 2 + self.pc_size as u64))
+    }
+}
+
+impl fmt::Display for InterruptController {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "interrupt =====
+    enabled: {},    pending: {:?}",
+            self.is_enabled(),
+            self.pending().map(|i| i.addr),
+        )
+    }
+}
+
+#[cfg(test)]
+use super::arch::atmega328p::{REGISTER_BIT_MAP, REGISTER_MAP, REGISTER_WORD_MAP, SRAM_SIZE};
+
+// INT0, USART UDRE, EE READY and SPM READY in order of priority
+#[cfg(test)]
+const TEST_TABLE: [Interrupt; 4] = [
+    Interrupt {
+        addr: 0x0002,
+        enable: REGISTER_BIT_MAP.int0,
+        flag: REGISTER_BIT_MAP.intf0,
+        trigger: Trigger::Flag,
+    },
+    Interrupt {
+        addr: 0x0026,
+        enable: REGISTER_BIT_MAP.udrie0,
+        flag: REGISTER_BIT_MAP.udre0,
+        trigger: Trigger::Level,
+    },
+    Interrupt {
+        addr: 0x002c,
+        enable: REGISTER_BIT_MAP.eerie,
+        flag: REGISTER_BIT_MAP.eepe,
+        trigger: Trigger::LevelLow,
+    },
+    Interrupt {
+        addr: 0x0032,
+        enable: REGISTER_BIT_MAP.spmie,
+        flag: REGISTER_BIT_MAP.selfprgen,
+        trigger: Trigger::LevelLow,
+    },
+];
+
+#[cfg(test)]
+fn new_controller(pc_size: usize) -> (Rc<RefCell<SRAM>>, InterruptController) {
+    let sram = Rc::new(RefCell::new(SRAM::new(
+        SRAM_SIZE,
+        &REGISTER_MAP,
+        &REGISTER_WORD_MAP,
+        &REGISTER_BIT_MAP,
+    )));
+    sram.borrow_mut()
+        .set_word(REGISTER_WORD_MAP.sp, REGISTER_MAP.ramend as u16);
+    let controller = InterruptController::new(Rc::clone(&sram), &TEST_TABLE, pc_size);
+    (sram, controller)
+}
+
+#[test]
+fn test_priority() {
+    let (sram, mut controller) = new_controller(2);
+    for (enable, flag) in [
+        (REGISTER_BIT_MAP.int0, REGISTER_BIT_MAP.intf0),
+        (REGISTER_BIT_MAP.udrie0, REGISTER_BIT_MAP.udre0),
+    ]
+    .iter()
+    {
+        sram.borrow_mut().set_bit(*enable, true);
+        sram.borrow_mut().set_bit(*flag, true);
+    }
+
+    // Not taken while I is cleared.
+    assert_eq!(controller.pending().map(|i| i.addr), Some(0x0002));
+    assert_eq!(controller.next(0x100, 10), None);
+
+    // INT0 has the higher priority, then UDRE.
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.i, true);
+    assert_eq!(controller.next(0x100, 10), Some((0x0002, 14)));
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.i, true);
+    assert_eq!(controller.next(0x0002, 20), Some((0x0026, 24)));
+}
+
+#[test]
+fn test_entry() {
+    let (sram, mut controller) = new_controller(2);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.int0, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.intf0, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.i, true);
+
+    // 2 + 2 cycles to push the 16-bit PC and jump to the vector
+    assert_eq!(controller.next(0x1234, 0), Some((0x0002, 4)));
+    let mut sram = sram.borrow_mut();
+    assert!(!sram.get_bit(REGISTER_BIT_MAP.i));
+    assert_eq!(sram.sp(), 0x08fd);
+    assert_eq!(sram.pop_pc_stack(2), 0x1234);
+}
+
+#[test]
+fn test_entry_22bit_pc() {
+    let (sram, mut controller) = new_controller(3);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.int0, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.intf0, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.i, true);
+
+    assert_eq!(controller.next(0x01_2345, 0), Some((0x0002, 5)));
+    let mut sram = sram.borrow_mut();
+    assert_eq!(sram.sp(), 0x08fc);
+    assert_eq!(sram.pop_pc_stack(3), 0x01_2345);
+}
+
+#[test]
+fn test_trigger() {
+    let (sram, mut controller) = new_controller(2);
+    let take = |controller: &mut InterruptController| {
+        sram.borrow_mut().set_bit(REGISTER_BIT_MAP.i, true);
+        controller.next(0x100, 0).map(|(addr, _)| addr)
+    };
+
+    // INTF0 is cleared by hardware when the vector is executed.
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.int0, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.intf0, true);
+    assert_eq!(take(&mut controller), Some(0x0002));
+    assert!(!sram.borrow().get_bit(REGISTER_BIT_MAP.intf0));
+    assert_eq!(take(&mut controller), None);
+
+    // UDRE0 keeps requesting the interrupt while it is set.
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.udrie0, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.udre0, true);
+    assert_eq!(take(&mut controller), Some(0x0026));
+    assert!(sram.borrow().get_bit(REGISTER_BIT_MAP.udre0));
+    assert_eq!(take(&mut controller), Some(0x0026));
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.udrie0, false);
+
+    // EE READY and SPM READY are requested while EEPE and SELFPRGEN are
+    // cleared.
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.eepe, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.eerie, true);
+    assert_eq!(take(&mut controller), None);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.eepe, false);
+    assert_eq!(take(&mut controller), Some(0x002c));
+    assert_eq!(take(&mut controller), Some(0x002c));
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.eerie, false);
+
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.selfprgen, true);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.spmie, true);
+    assert_eq!(take(&mut controller), None);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.selfprgen, false);
+    assert_eq!(take(&mut controller), Some(0x0032));
+    assert_eq!(take(&mut controller), Some(0x0032));
+}
+
+#[test]
+fn test_low_level() {
+    const TABLE: [Interrupt; 1] = [Interrupt {
+        addr: 0x0002,
+        enable: REGISTER_BIT_MAP.int0,
+        flag: REGISTER_BIT_MAP.intf0,
+        trigger: Trigger::FlagOrLowLevel {
+            pin: (REGISTER_MAP.pind, 2),
+            isc: (REGISTER_MAP.eicra, 0),
+        },
+    }];
+    let (sram, _) = new_controller(2);
+    let controller = InterruptController::new(Rc::clone(&sram), &TABLE, 2);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.int0, true);
+
+    // INT0 is requested while PD2 is low without INTF0.
+    assert_eq!(controller.pending().map(|i| i.addr), Some(0x0002));
+    sram.borrow_mut().set(REGISTER_MAP.pind, 0b0000_0100);
+    assert_eq!(controller.pending().map(|i| i.addr), None);
+
+    // Only INTF0 requests it on the falling edge.
+    sram.borrow_mut().set(REGISTER_MAP.pind, 0);
+    sram.borrow_mut().set(REGISTER_MAP.eicra, 0b10);
+    assert_eq!(controller.pending().map(|i| i.addr), None);
+    sram.borrow_mut().set_bit(REGISTER_BIT_MAP.intf0, true);
+    assert_eq!(controller.pending().map(|i| i.addr), Some(0x0002));
+}
@@ -4,14 +4,36 @@ use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
+// The synchronizer latches the pin level at the falling edge of the clock
+// and the flip-flop passes it to PINx at the next rising edge, so that a
+// level can be read 1 cycle after it is latched. The levels are latched at
+// the end of each instruction, so OUT needs a NOP before IN reads the pin
+// back, and a level driven by the host before a step is read 2 cycles
+// later rather than the 1/2 ~ 1 1/2 cycles of the datasheet.
+const SYNCHRONIZER_CYCLES: u64 = 1;
+
 pub struct IOPort {
     sram: Rc<RefCell<SRAM>>,
-    last_portx: u8,
-    last_ddrx: u8,
-    last_pinx: u8,
+    // Levels driven by the host and the pins driven by the host
+    inputs: u8,
+    driven: u8,
+    // Pins and their levels driven by alternate port functions
+    overrides: u8,
+    override_levels: u8,
+    // Levels of the pins and the pins changed by the last step
+    levels: u8,
+    edges: u8,
+    // Levels held by the synchronizer latch and the cycle when latched
+    latch: u8,
+    latch_cycle: u64,
+    // Levels read from PINx
+    synchronized: u8,
+    // Output pins driven to the other level by the host
+    contention: u8,
     portx: RegisterAddr,
     ddrx: RegisterAddr,
     pinx: RegisterAddr,
+    pud: RegisterBitAddr,
 }
 
 impl IOPort {
@@ -20,18 +42,40 @@ impl IOPort {
         portx: RegisterAddr,
         ddrx: RegisterAddr,
         pinx: RegisterAddr,
+        pud: RegisterBitAddr,
     ) -> IOPort {
         IOPort {
             sram: sram,
-            last_portx: 0,
-            last_ddrx: 0,
-            last_pinx: 0,
+            inputs: 0,
+            driven: 0,
+            overrides: 0,
+            override_levels: 0,
+            levels: 0,
+            edges: 0,
+            latch: 0,
+            latch_cycle: 0,
+            synchronized: 0,
+            contention: 0,
             portx: portx,
             ddrx: ddrx,
             pinx: pinx,
+            pud: pud,
         }
     }
 
+    // The pins keep being driven by the host over a reset.
+    pub fn initialize(&mut self) {
+        self.overrides = 0;
+        let levels = self.resolve_levels();
+        self.levels = levels;
+        self.edges = 0;
+        self.latch = levels;
+        self.latch_cycle = 0;
+        self.synchronized = levels;
+        self.contention = 0;
+        self.sram.borrow_mut().set(self.pinx, levels);
+    }
+
     fn portx(&self) -> u8 {
         self.sram.borrow().get(self.portx)
     }
@@ -43,31 +87,94 @@ impl IOPort {
     pub fn pinx(&self) -> u8 {
         self.sram.borrow().get(self.pinx)
     }
-}
 
-impl Iterator for IOPort {
-    type Item = ();
-    // TODO: 1 cycle ずれている
-    fn next(&mut self) -> Option<()> {
-        if self.last_portx != self.portx()
-            || self.last_ddrx != self.ddrx()
-            || self.last_pinx != self.pinx()
-        {
-            // update pinx
-            for n in 0..8 {
-                // 出力 buffer が ON（ DDXn が ON ）ならば、PORTXn を PINXn に反映
-                if bit(self.ddrx(), n) {
-                    let portx = self.portx();
-                    self.sram
-                        .borrow_mut()
-                        .set_bit((self.pinx, n), bit(portx, n))
-                }
-            }
-            self.last_portx = self.portx();
-            self.last_ddrx = self.ddrx();
-            self.last_pinx = self.pinx();
+    // Levels of the pins before the synchronizer
+    pub fn levels(&self) -> u8 {
+        self.levels
+    }
+
+    // Pins whose level changed by the last step
+    pub fn edges(&self) -> u8 {
+        self.edges
+    }
+
+    pub fn contention(&self) -> u8 {
+        self.contention
+    }
+
+    // `inputs` is valid for the pins in `driven`. The other pins are
+    // released by the host.
+    pub fn set_inputs(&mut self, inputs: u8, driven: u8) {
+        self.inputs = inputs;
+        self.driven = driven;
+    }
+
+    // Alternate port functions (e.g. OC0A) drive the pins in `overrides`
+    // regardless of PORTx.
+    pub fn set_overrides(&mut self, overrides: u8, levels: u8) {
+        self.overrides = overrides;
+        self.override_levels = levels;
+    }
+
+    // Pins driven by the MCU and their levels
+    fn outputs(&self) -> (u8, u8) {
+        let outputs = self.ddrx() | self.overrides;
+        let levels = (self.portx() & !self.overrides) | (self.override_levels & self.overrides);
+        (outputs, levels & outputs)
+    }
+
+    fn resolve_levels(&self) -> u8 {
+        let (outputs, output_levels) = self.outputs();
+        let portx = self.portx();
+        let pud = self.sram.borrow().get_bit(self.pud);
+        let mut levels = 0;
+        for n in 0..8 {
+            let level = if bit(outputs, n) {
+                // The output driver is stronger than the host.
+                bit(output_levels, n)
+            } else if bit(self.driven, n) {
+                bit(self.inputs, n)
+            } else if bit(portx, n) && !pud {
+                // Pull-up
+                true
+            } else {
+                // A floating pin keeps its last level.
+                bit(self.levels, n)
+            };
+            levels |= (level as u8) << n;
+        }
+        levels
+    }
+
+    // Writing one to PINxn toggles PORTxn. SBI writes only one bit, so
+    // that the other bits are not toggled.
+    fn access_pinx(&mut self) {
+        let mut sram = self.sram.borrow_mut();
+        let toggled = sram.get(self.pinx) & sram.written_bits(self.pinx);
+        if toggled != 0 {
+            let portx = sram.get(self.portx);
+            sram.set(self.portx, portx ^ toggled);
+        }
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_pinx();
+
+        let levels = self.resolve_levels();
+        self.edges = self.levels ^ levels;
+        self.levels = levels;
+        let (outputs, output_levels) = self.outputs();
+        self.contention = outputs & self.driven & (self.inputs ^ output_levels);
+
+        if self.levels != self.latch {
+            self.latch = self.levels;
+            self.latch_cycle = cycle;
+        }
+        if cycle - self.latch_cycle >= SYNCHRONIZER_CYCLES {
+            self.synchronized = self.latch;
         }
-        Some(())
+        let synchronized = self.synchronized;
+        self.sram.borrow_mut().set(self.pinx, synchronized);
     }
 }
 
@@ -75,10 +182,12 @@ impl fmt::Display for IOPort {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "portx: {:08b}    ddrx: {:08b}    pinx: {:08b}",
+            "portx: {:08b}    ddrx: {:08b}    pinx: {:08b}    pins: {:08b}    contention: {:08b}",
             self.portx(),
             self.ddrx(),
             self.pinx(),
+            self.levels,
+            self.contention,
         )
     }
 }
@@ -1,12 +1,25 @@
+pub mod adc;
+mod analog_comparator;
 pub mod arch;
 pub mod avrmcu;
+mod clock;
+mod eeprom;
+mod eeprom_controller;
+mod external_interrupt;
 mod flash_memory;
 mod instruction;
+pub mod instruction_set;
+mod interrupt;
 mod io_port;
 mod opcode_tree;
+mod self_programming;
+pub mod spi;
 mod sram;
 mod timer16bit;
 mod timer8bit;
+pub mod twi;
+mod usart;
 mod util;
+mod watchdog;
 mod word;
 mod wasm;
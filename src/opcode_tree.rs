@@ -1,5 +1,6 @@
 use super::instruction::*;
 use super::util::bit::*;
+use super::word::*;
 use std::fmt;
 
 thread_local! {
@@ -20,10 +21,10 @@ thread_local! {
         t.add((0b1001_0000_0000_1100, 0b1111_1110_0000_1111), Instr::LD1, &ld1);
         t.add((0b1001_0000_0000_1101, 0b1111_1110_0000_1111), Instr::LD2, &ld2);
         t.add((0b1001_0000_0000_1110, 0b1111_1110_0000_1111), Instr::LD3, &ld3);
-        t.add((0b1000_0000_0000_1000, 0b1111_1110_0000_1111), Instr::LDDY1, &lddy1);
+        t.add((0b1000_0000_0000_1000, 0b1101_0010_0000_1000), Instr::LDDY1, &lddy1);
         t.add((0b1001_0000_0000_1001, 0b1111_1110_0000_1111), Instr::LDDY2, &lddy2);
         t.add((0b1001_0000_0000_1010, 0b1111_1110_0000_1111), Instr::LDDY3, &lddy3);
-        t.add((0b1000_0000_0000_0000, 0b1111_1110_0000_1111), Instr::LDDZ1, &lddz1);
+        t.add((0b1000_0000_0000_0000, 0b1101_0010_0000_1000), Instr::LDDZ1, &lddz1);
         t.add((0b1001_0000_0000_0001, 0b1111_1110_0000_1111), Instr::LDDZ2, &lddz2);
         t.add((0b1001_0000_0000_0010, 0b1111_1110_0000_1111), Instr::LDDZ3, &lddz3);
         t.add((0b1001_0000_0000_0000, 0b1111_1110_0000_1111), Instr::LDS, &lds);
@@ -32,10 +33,20 @@ thread_local! {
         t.add((0b0000_0000_0000_0000, 0b1111_1111_1111_1111), Instr::NOP, &nop);
         t.add((0b1001_0100_0000_1110, 0b1111_1110_0000_1110), Instr::CALL, &call);
         t.add((0b1101_0000_0000_0000, 0b1111_0000_0000_0000), Instr::RCALL, &rcall);
-        // t.add((0b0001_1100_0000_0000, 0b1111_1100_0000_0000), Instr::ROL, &rol);
-        // t.add((0b0000_1100_0000_0000, 0b1111_1100_0000_0000), Instr::LSL, &lsl);
+        t.add((0b1001_0100_0000_0110, 0b1111_1110_0000_1111), Instr::LSR, &lsr);
+        t.add((0b1001_0100_0000_0101, 0b1111_1110_0000_1111), Instr::ASR, &asr);
+        t.add((0b1001_0100_0000_0111, 0b1111_1110_0000_1111), Instr::ROR, &ror);
+        t.add((0b1001_0100_0000_0010, 0b1111_1110_0000_1111), Instr::SWAP, &swap);
+        t.add((0b1001_0100_0000_0001, 0b1111_1110_0000_1111), Instr::NEG, &neg);
+        t.add((0b1001_0100_0000_0011, 0b1111_1110_0000_1111), Instr::INC, &inc);
+        t.add((0b1111_1010_0000_0000, 0b1111_1110_0000_1000), Instr::BST, &bst);
+        t.add((0b1111_1000_0000_0000, 0b1111_1110_0000_1000), Instr::BLD, &bld);
         t.add((0b1001_0100_0000_1100, 0b1111_1110_0000_1110), Instr::JMP, &jmp);
         t.add((0b1100_0000_0000_0000, 0b1111_0000_0000_0000), Instr::RJMP, &rjmp);
+        t.add((0b1001_0100_0000_1001, 0b1111_1111_1111_1111), Instr::IJMP, &ijmp);
+        t.add((0b1001_0100_0001_1001, 0b1111_1111_1111_1111), Instr::EIJMP, &eijmp);
+        t.add((0b1001_0101_0000_1001, 0b1111_1111_1111_1111), Instr::ICALL, &icall);
+        t.add((0b1001_0101_0001_1001, 0b1111_1111_1111_1111), Instr::EICALL, &eicall);
         t.add((0b0110_0000_0000_0000, 0b1111_0000_0000_0000), Instr::ORI, &ori);
         t.add((0b0010_0000_0000_0000, 0b1111_1100_0000_0000), Instr::AND, &and);
         t.add((0b0111_0000_0000_0000, 0b1111_0000_0000_0000), Instr::ANDI, &andi);
@@ -45,30 +56,77 @@ thread_local! {
         t.add((0b1001_0010_0000_1100, 0b1111_1110_0000_1111), Instr::ST1, &st1);
         t.add((0b1001_0010_0000_1101, 0b1111_1110_0000_1111), Instr::ST2, &st2);
         t.add((0b1001_0010_0000_1110, 0b1111_1110_0000_1111), Instr::ST3, &st3);
-        t.add((0b1000_0010_0000_1000, 0b1111_1110_0000_1111), Instr::STY1, &sty1);
+        t.add((0b1000_0010_0000_1000, 0b1101_0010_0000_1000), Instr::STY1, &sty1);
         t.add((0b1001_0010_0000_1001, 0b1111_1110_0000_1111), Instr::STY2, &sty2);
         t.add((0b1001_0010_0000_1010, 0b1111_1110_0000_1111), Instr::STY3, &sty3);
-        t.add((0b1000_0010_0000_0000, 0b1111_1110_0000_1111), Instr::STZ1, &stz1);
+        t.add((0b1000_0010_0000_0000, 0b1101_0010_0000_1000), Instr::STZ1, &stz1);
         t.add((0b1001_0010_0000_0001, 0b1111_1110_0000_1111), Instr::STZ2, &stz2);
         t.add((0b1001_0010_0000_0010, 0b1111_1110_0000_1111), Instr::STZ3, &stz3);
         t.add((0b1001_0101_1100_1000, 0b1111_1111_1111_1111), Instr::LPM1, &lpm1);
         t.add((0b1001_0000_0000_0100, 0b1111_1110_0000_1111), Instr::LPM2, &lpm2);
         t.add((0b1001_0000_0000_0101, 0b1111_1110_0000_1111), Instr::LPM3, &lpm3);
+        t.add((0b1001_0101_1101_1000, 0b1111_1111_1111_1111), Instr::ELPM1, &elpm1);
+        t.add((0b1001_0000_0000_0110, 0b1111_1110_0000_1111), Instr::ELPM2, &elpm2);
+        t.add((0b1001_0000_0000_0111, 0b1111_1110_0000_1111), Instr::ELPM3, &elpm3);
+        t.add((0b1001_0101_1110_1000, 0b1111_1111_1111_1111), Instr::SPM, &spm);
         t.add((0b0001_0100_0000_0000, 0b1111_1100_0000_0000), Instr::CP, &cp);
         t.add((0b0011_0000_0000_0000, 0b1111_0000_0000_0000), Instr::CPI, &cpi);
         t.add((0b0000_0100_0000_0000, 0b1111_1100_0000_0000), Instr::CPC, &cpc);
         t.add((0b0001_0000_0000_0000, 0b1111_1100_0000_0000), Instr::CPSE, &cpse);
-        t.add((0b1111_0000_0000_0001, 0b1111_1100_0000_0111), Instr::BREQ, &breq);
-        t.add((0b1111_0100_0000_0001, 0b1111_1100_0000_0111), Instr::BRNE, &brne);
-        t.add((0b1111_0000_0000_0000, 0b1111_1100_0000_0111), Instr::BRCS, &brcs);
+        // BRLO and BRSH have the same opcodes as BRCS and BRCC.
+        t.add((0b1111_0000_0000_0000, 0b1111_1100_0000_0111), Instr::BRCS, &brbs);
+        t.add((0b1111_0100_0000_0000, 0b1111_1100_0000_0111), Instr::BRCC, &brbc);
+        t.add((0b1111_0000_0000_0001, 0b1111_1100_0000_0111), Instr::BREQ, &brbs);
+        t.add((0b1111_0100_0000_0001, 0b1111_1100_0000_0111), Instr::BRNE, &brbc);
+        t.add((0b1111_0000_0000_0010, 0b1111_1100_0000_0111), Instr::BRMI, &brbs);
+        t.add((0b1111_0100_0000_0010, 0b1111_1100_0000_0111), Instr::BRPL, &brbc);
+        t.add((0b1111_0000_0000_0011, 0b1111_1100_0000_0111), Instr::BRVS, &brbs);
+        t.add((0b1111_0100_0000_0011, 0b1111_1100_0000_0111), Instr::BRVC, &brbc);
+        t.add((0b1111_0000_0000_0100, 0b1111_1100_0000_0111), Instr::BRLT, &brbs);
+        t.add((0b1111_0100_0000_0100, 0b1111_1100_0000_0111), Instr::BRGE, &brbc);
+        t.add((0b1111_0000_0000_0101, 0b1111_1100_0000_0111), Instr::BRHS, &brbs);
+        t.add((0b1111_0100_0000_0101, 0b1111_1100_0000_0111), Instr::BRHC, &brbc);
+        t.add((0b1111_0000_0000_0110, 0b1111_1100_0000_0111), Instr::BRTS, &brbs);
+        t.add((0b1111_0100_0000_0110, 0b1111_1100_0000_0111), Instr::BRTC, &brbc);
+        t.add((0b1111_0000_0000_0111, 0b1111_1100_0000_0111), Instr::BRIE, &brbs);
+        t.add((0b1111_0100_0000_0111, 0b1111_1100_0000_0111), Instr::BRID, &brbc);
+        t.add((0b1001_1010_0000_0000, 0b1111_1111_0000_0000), Instr::SBI, &sbi);
+        t.add((0b1001_1000_0000_0000, 0b1111_1111_0000_0000), Instr::CBI, &cbi);
         t.add((0b1001_1011_0000_0000, 0b1111_1111_0000_0000), Instr::SBIS, &sbis);
-        t.add((0b1001_0100_0111_1000, 0b1111_1111_1111_1111), Instr::SEI, &sei);
-        t.add((0b1001_0100_1111_1000, 0b1111_1111_1111_1111), Instr::CLI, &cli);
+        t.add((0b1001_1001_0000_0000, 0b1111_1111_0000_0000), Instr::SBIC, &sbic);
+        t.add((0b1111_1110_0000_0000, 0b1111_1110_0000_1000), Instr::SBRS, &sbrs);
+        t.add((0b1111_1100_0000_0000, 0b1111_1110_0000_1000), Instr::SBRC, &sbrc);
+        t.add((0b1001_0100_0000_1000, 0b1111_1111_1111_1111), Instr::SEC, &bset);
+        t.add((0b1001_0100_0001_1000, 0b1111_1111_1111_1111), Instr::SEZ, &bset);
+        t.add((0b1001_0100_0010_1000, 0b1111_1111_1111_1111), Instr::SEN, &bset);
+        t.add((0b1001_0100_0011_1000, 0b1111_1111_1111_1111), Instr::SEV, &bset);
+        t.add((0b1001_0100_0100_1000, 0b1111_1111_1111_1111), Instr::SES, &bset);
+        t.add((0b1001_0100_0101_1000, 0b1111_1111_1111_1111), Instr::SEH, &bset);
+        t.add((0b1001_0100_0110_1000, 0b1111_1111_1111_1111), Instr::SET, &bset);
+        t.add((0b1001_0100_0111_1000, 0b1111_1111_1111_1111), Instr::SEI, &bset);
+        t.add((0b1001_0100_1000_1000, 0b1111_1111_1111_1111), Instr::CLC, &bclr);
+        t.add((0b1001_0100_1001_1000, 0b1111_1111_1111_1111), Instr::CLZ, &bclr);
+        t.add((0b1001_0100_1010_1000, 0b1111_1111_1111_1111), Instr::CLN, &bclr);
+        t.add((0b1001_0100_1011_1000, 0b1111_1111_1111_1111), Instr::CLV, &bclr);
+        t.add((0b1001_0100_1100_1000, 0b1111_1111_1111_1111), Instr::CLS, &bclr);
+        t.add((0b1001_0100_1101_1000, 0b1111_1111_1111_1111), Instr::CLH, &bclr);
+        t.add((0b1001_0100_1110_1000, 0b1111_1111_1111_1111), Instr::CLT, &bclr);
+        t.add((0b1001_0100_1111_1000, 0b1111_1111_1111_1111), Instr::CLI, &bclr);
         t.add((0b1001_0101_0000_1000, 0b1111_1111_1111_1111), Instr::RET, &ret);
+        t.add((0b1001_0101_0001_1000, 0b1111_1111_1111_1111), Instr::RETI, &reti);
+        t.add((0b1001_0101_1000_1000, 0b1111_1111_1111_1111), Instr::SLEEP, &sleep);
+        t.add((0b1001_0101_1010_1000, 0b1111_1111_1111_1111), Instr::WDR, &wdr);
+        t.add((0b1001_0101_1001_1000, 0b1111_1111_1111_1111), Instr::BREAK, &break_instr);
         t.add((0b1001_0010_0000_1111, 0b1111_1110_0000_1111), Instr::PUSH, &push);
         t.add((0b1001_0000_0000_1111, 0b1111_1110_0000_1111), Instr::POP, &pop);
         t.add((0b0010_1100_0000_0000, 0b1111_1100_0000_0000), Instr::MOV, &mov);
         t.add((0b0000_0001_0000_0000, 0b1111_1111_0000_0000), Instr::MOVW, &movw);
+        t.add((0b1001_1100_0000_0000, 0b1111_1100_0000_0000), Instr::MUL, &mul);
+        t.add((0b0000_0010_0000_0000, 0b1111_1111_0000_0000), Instr::MULS, &muls);
+        t.add((0b0000_0011_0000_0000, 0b1111_1111_1000_1000), Instr::MULSU, &mulsu);
+        t.add((0b0000_0011_0000_1000, 0b1111_1111_1000_1000), Instr::FMUL, &fmul);
+        t.add((0b0000_0011_1000_0000, 0b1111_1111_1000_1000), Instr::FMULS, &fmuls);
+        t.add((0b0000_0011_1000_1000, 0b1111_1111_1000_1000), Instr::FMULSU, &fmulsu);
         t
     };
 }
@@ -133,10 +191,16 @@ impl Node {
     }
 
     pub fn find(&self, word: u16) -> (Instr, InstrFunc) {
-        self.find_recursive(word, 0)
+        self.decode(word)
             .unwrap_or_else(|| panic!("there is no instruction, w: {:016b}", word))
     }
 
+    // Same as find(), but returns None for an unimplemented opcode.
+    pub fn decode(&self, word: u16) -> Option<(Instr, InstrFunc)> {
+        let (instr, f) = self.find_recursive(word, 0)?;
+        Some(alias(instr, word).unwrap_or((instr, f)))
+    }
+
     fn find_recursive(&self, w: u16, depth: u8) -> Option<(Instr, InstrFunc)> {
         if depth >= 16 {
             return Some((
@@ -191,14 +255,89 @@ impl fmt::Display for Node {
     }
 }
 
+// Some instructions are aliases of others with the same register for
+// both operands (e.g. LSL Rd = ADD Rd, Rd) or with a fixed immediate
+// (SER Rd = LDI Rd, 0xff), which can't be expressed by an opcode mask.
+fn alias(instr: Instr, word: u16) -> Option<(Instr, InstrFunc)> {
+    let (r_addr, d_addr) = Word(word).operand55();
+    match instr {
+        Instr::ADD if r_addr == d_addr => Some((Instr::LSL, &lsl)),
+        Instr::ADC if r_addr == d_addr => Some((Instr::ROL, &rol)),
+        Instr::AND if r_addr == d_addr => Some((Instr::TST, &tst)),
+        Instr::EOR if r_addr == d_addr => Some((Instr::CLR, &eor)),
+        Instr::LDI if Word(word).operand84().0 == 0xff => Some((Instr::SER, &ldi)),
+        _ => None,
+    }
+}
+
 #[test]
 fn test_node() {
     &OPCODE_TREE.with(|f| {
-        assert_eq!(Instr::ADD, f.find(0b0000_1100_0000_0000).0);
-        assert_eq!(Instr::ADC, f.find(0b0001_1100_0000_0000).0);
+        assert_eq!(Instr::ADD, f.find(0b0000_1100_0000_0001).0);
+        assert_eq!(Instr::ADC, f.find(0b0001_1100_0000_0001).0);
         assert_eq!(Instr::JMP, f.find(0b1001_0100_0000_1100).0);
         assert_eq!(Instr::SEI, f.find(0b1001_0100_0111_1000).0);
         assert_eq!(Instr::STS, f.find(0b1001_0010_0000_0000).0);
+        assert_eq!(Instr::IJMP, f.find(0b1001_0100_0000_1001).0);
+        assert_eq!(Instr::EIJMP, f.find(0b1001_0100_0001_1001).0);
+        assert_eq!(Instr::ICALL, f.find(0b1001_0101_0000_1001).0);
+        assert_eq!(Instr::EICALL, f.find(0b1001_0101_0001_1001).0);
+        assert_eq!(Instr::ELPM1, f.find(0b1001_0101_1101_1000).0);
+        assert_eq!(Instr::ELPM2, f.find(0b1001_0001_1000_0110).0);
+        assert_eq!(Instr::ELPM3, f.find(0b1001_0001_1000_0111).0);
+        assert_eq!(Instr::SPM, f.find(0b1001_0101_1110_1000).0);
+        assert_eq!(Instr::RET, f.find(0b1001_0101_0000_1000).0);
+        assert_eq!(Instr::RETI, f.find(0b1001_0101_0001_1000).0);
+        assert_eq!(Instr::SLEEP, f.find(0b1001_0101_1000_1000).0);
+        assert_eq!(Instr::WDR, f.find(0b1001_0101_1010_1000).0);
+        assert_eq!(Instr::BREAK, f.find(0b1001_0101_1001_1000).0);
+        assert_eq!(Instr::SEC, f.find(0b1001_0100_0000_1000).0);
+        assert_eq!(Instr::CLT, f.find(0b1001_0100_1110_1000).0);
+        assert_eq!(Instr::CLR, f.find(0b0010_0111_1110_1110).0);
+        assert_eq!(Instr::EOR, f.find(0b0010_0111_1110_1111).0);
+        assert_eq!(Instr::SER, f.find(0b1110_1111_1000_1111).0);
+        assert_eq!(Instr::LDI, f.find(0b1110_1111_1000_1110).0);
+        assert_eq!(Instr::BRCC, f.find(0b1111_0111_1111_1000).0);
+        assert_eq!(Instr::BRGE, f.find(0b1111_0100_0001_0100).0);
+        assert_eq!(Instr::BRID, f.find(0b1111_0100_0000_1111).0);
+        assert_eq!(Instr::BRMI, f.find(0b1111_0011_1111_1010).0);
+        assert_eq!(Instr::SBI, f.find(0b1001_1010_0010_1101).0);
+        assert_eq!(Instr::CBI, f.find(0b1001_1000_0010_1101).0);
+        assert_eq!(Instr::SBIC, f.find(0b1001_1001_0000_0000).0);
+        assert_eq!(Instr::SBRS, f.find(0b1111_1111_1111_0111).0);
+        assert_eq!(Instr::SBRC, f.find(0b1111_1100_0000_0000).0);
+        assert_eq!(Instr::LSL, f.find(0b0000_1111_1111_1111).0);
+        assert_eq!(Instr::ADD, f.find(0b0000_1111_1111_1110).0);
+        assert_eq!(Instr::ROL, f.find(0b0001_1100_0001_0001).0);
+        assert_eq!(Instr::TST, f.find(0b0010_0011_0011_0011).0);
+        assert_eq!(Instr::LSR, f.find(0b1001_0101_1000_0110).0);
+        assert_eq!(Instr::ASR, f.find(0b1001_0101_1000_0101).0);
+        assert_eq!(Instr::ROR, f.find(0b1001_0101_1000_0111).0);
+        assert_eq!(Instr::SWAP, f.find(0b1001_0101_1000_0010).0);
+        assert_eq!(Instr::NEG, f.find(0b1001_0101_1000_0001).0);
+        assert_eq!(Instr::INC, f.find(0b1001_0101_1000_0011).0);
+        assert_eq!(Instr::BST, f.find(0b1111_1011_1000_0111).0);
+        assert_eq!(Instr::BLD, f.find(0b1111_1001_1000_0111).0);
+        assert_eq!(Instr::MUL, f.find(0b1001_1110_0001_0010).0);
+        assert_eq!(Instr::MULS, f.find(0b0000_0010_0101_1110).0);
+        assert_eq!(Instr::MULSU, f.find(0b0000_0011_0101_0110).0);
+        assert_eq!(Instr::FMUL, f.find(0b0000_0011_0101_1110).0);
+        assert_eq!(Instr::FMULS, f.find(0b0000_0011_1101_0110).0);
+        assert_eq!(Instr::FMULSU, f.find(0b0000_0011_1101_1110).0);
+    });
+}
+
+#[test]
+fn test_node_displacement() {
+    OPCODE_TREE.with(|f| {
+        for q in 0..64u16 {
+            let q_bits = (q & 0b10_0000) << 8 | (q & 0b1_1000) << 7 | (q & 0b111);
+            // ldd r24, Y+q / ldd r24, Z+q / std Y+q, r24 / std Z+q, r24
+            assert_eq!(Instr::LDDY1, f.find(0b1000_0001_1000_1000 | q_bits).0);
+            assert_eq!(Instr::LDDZ1, f.find(0b1000_0001_1000_0000 | q_bits).0);
+            assert_eq!(Instr::STY1, f.find(0b1000_0011_1000_1000 | q_bits).0);
+            assert_eq!(Instr::STZ1, f.find(0b1000_0011_1000_0000 | q_bits).0);
+        }
     });
 }
 
@@ -206,6 +345,6 @@ fn test_node() {
 #[should_panic]
 fn test_node_panic() {
     &OPCODE_TREE.with(|f| {
-        let _xxx = f.find(0b1111_1100_0000_0000);
+        let _xxx = f.find(0b1111_1111_1111_1111);
     });
 }
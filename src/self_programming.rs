@@ -0,0 +1,214 @@
+use super::clock::*;
+use super::flash_memory::*;
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// Page erase and page write take 3.7 ~ 4.5 ms.
+const PROGRAMMING_NANOSECONDS: u64 = 4_500_000;
+
+// SPM must be executed within 4 cycles after SELFPRGEN is written.
+const SPM_TIMEOUT: u64 = 4;
+
+pub struct SelfProgramming {
+    sram: Rc<RefCell<SRAM>>,
+    flash_memory: Rc<RefCell<FlashMemory>>,
+    clock: Rc<RefCell<Clock>>,
+    page_size: usize,
+    nrww_start: usize,
+    // Each word of the temporary page buffer can be written only once
+    // until the buffer is erased.
+    buffer: Vec<Option<u16>>,
+    lock_bits: u8,
+    enabled_cycle: Option<u64>,
+    busy_until: Option<u64>,
+
+    spmcsr: RegisterAddr,
+    rampz: RegisterAddr,
+    rwwsb: RegisterBitAddr,
+    selfprgen: RegisterBitAddr,
+}
+
+impl SelfProgramming {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        flash_memory: Rc<RefCell<FlashMemory>>,
+        clock: Rc<RefCell<Clock>>,
+        page_size: usize,
+        nrww_start: usize,
+        spmcsr: RegisterAddr,
+        rampz: RegisterAddr,
+        rwwsb: RegisterBitAddr,
+        selfprgen: RegisterBitAddr,
+    ) -> SelfProgramming {
+        SelfProgramming {
+            sram: sram,
+            flash_memory: flash_memory,
+            clock: clock,
+            page_size: page_size,
+            nrww_start: nrww_start,
+            buffer: vec![None; page_size],
+            lock_bits: 0xff,
+            enabled_cycle: None,
+            busy_until: None,
+            spmcsr: spmcsr,
+            rampz: rampz,
+            rwwsb: rwwsb,
+            selfprgen: selfprgen,
+        }
+    }
+
+    // Lock bits are non-volatile and survive reset.
+    pub fn initialize(&mut self) {
+        self.buffer = vec![None; self.page_size];
+        self.enabled_cycle = None;
+        self.busy_until = None;
+    }
+
+    fn spmcsr(&self) -> u8 {
+        self.sram.borrow().get(self.spmcsr)
+    }
+
+    fn selfprgen(&self) -> bool {
+        self.sram.borrow().get_bit(self.selfprgen)
+    }
+
+    // RAMPZ:Z, byte address. The upper bits beyond the flash size are
+    // ignored.
+    fn z_addr(&self) -> usize {
+        let sram = self.sram.borrow();
+        let z_addr =
+            (sram.get(self.rampz) as usize) << 16 | sram.get_word(sram.word_map.z) as usize;
+        z_addr % (2 * self.flash_memory.borrow().size())
+    }
+
+    pub fn lock_bits(&self) -> u8 {
+        self.lock_bits
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy_until.is_some()
+    }
+
+    // SPM is not allowed to write to the application section if BLB01 is
+    // programmed, nor to the boot loader section if BLB11 is programmed.
+    fn is_writable(&self, page_addr: usize, boot_start: usize) -> bool {
+        if page_addr < boot_start {
+            bit(self.lock_bits, 2)
+        } else {
+            bit(self.lock_bits, 4)
+        }
+    }
+
+    // Clear SELFPRGEN and the command bits, SPMIE and RWWSB are kept.
+    fn complete(&mut self) {
+        let spmcsr = self.spmcsr();
+        self.sram
+            .borrow_mut()
+            .set(self.spmcsr, spmcsr & 0b1100_0000);
+        self.enabled_cycle = None;
+    }
+
+    // Execute SPM at `pc`. SPM only takes effect when it is executed from
+    // the boot loader section within 4 cycles after SELFPRGEN is set.
+    // Returns the number of cycles the CPU is halted, which happens while
+    // the NRWW section is being programmed.
+    pub fn spm(&mut self, pc: usize, cycle: u64, boot_start: usize) -> u64 {
+        let is_enabled = match self.enabled_cycle {
+            Some(c) => self.selfprgen() && cycle - c <= SPM_TIMEOUT,
+            None => false,
+        };
+        if !is_enabled || self.is_busy() || pc < boot_start {
+            return 0;
+        }
+
+        let z_addr = self.z_addr();
+        let page_addr = (z_addr / 2) & !(self.page_size - 1);
+        let halt = match self.spmcsr() & 0b0011_1111 {
+            // Fill the temporary page buffer with R1:R0
+            0b00_0001 => {
+                let offset = (z_addr / 2) & (self.page_size - 1);
+                if self.buffer[offset].is_none() {
+                    let (r1, r0) = self.sram.borrow().gets(1, 0);
+                    self.buffer[offset] = Some(concat(r1, r0));
+                }
+                0
+            }
+            // Page erase and page write
+            cmd @ 0b00_0011 | cmd @ 0b00_0101 if self.is_writable(page_addr, boot_start) => {
+                let mut flash_memory = self.flash_memory.borrow_mut();
+                for i in 0..self.page_size {
+                    let w = if cmd == 0b00_0011 {
+                        0xffff
+                    } else {
+                        // Programming can only clear bits.
+                        flash_memory.get(page_addr + i) & self.buffer[i].unwrap_or(0xffff)
+                    };
+                    flash_memory.set(page_addr + i, w);
+                }
+                if cmd == 0b00_0101 {
+                    self.buffer = vec![None; self.page_size];
+                }
+
+                let programming_cycles = self.clock.borrow().cycles(PROGRAMMING_NANOSECONDS);
+                if page_addr >= self.nrww_start {
+                    programming_cycles
+                } else {
+                    // The CPU keeps running from the NRWW section and the
+                    // RWW section is busy until RWWSRE is written.
+                    self.sram.borrow_mut().set_bit(self.rwwsb, true);
+                    self.busy_until = Some(cycle + programming_cycles);
+                    return 0;
+                }
+            }
+            // Read-while-write section read enable
+            0b01_0001 => {
+                self.sram.borrow_mut().set_bit(self.rwwsb, false);
+                self.buffer = vec![None; self.page_size];
+                0
+            }
+            // Boot lock bit set, only BLB0x and BLB1x can be programmed.
+            0b00_1001 => {
+                let r0 = self.sram.borrow().get(0);
+                self.lock_bits &= r0 | 0b1100_0011;
+                0
+            }
+            _ => 0,
+        };
+        self.complete();
+        halt
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        if let Some(busy_until) = self.busy_until {
+            if cycle >= busy_until {
+                self.busy_until = None;
+                self.complete();
+            }
+            return;
+        }
+
+        if self.selfprgen() {
+            match self.enabled_cycle {
+                None => self.enabled_cycle = Some(cycle),
+                Some(c) if cycle - c > SPM_TIMEOUT => self.complete(),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl fmt::Display for SelfProgramming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "self programming =====
+    spmcsr: {:08b},    busy: {},    lock bits: {:08b}",
+            self.spmcsr(),
+            self.is_busy(),
+            self.lock_bits,
+        )
+    }
+}
@@ -0,0 +1,615 @@
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// SPCR
+const SPE: u8 = 6;
+const DORD: u8 = 5;
+const MSTR: u8 = 4;
+const CPOL: u8 = 3;
+const CPHA: u8 = 2;
+
+// SPSR
+const SPIF: u8 = 7;
+const WCOL: u8 = 6;
+const SPI2X: u8 = 0;
+
+// Indices of the SPI pins
+const SS: usize = 0;
+const MOSI: usize = 1;
+const MISO: usize = 2;
+const SCK: usize = 3;
+
+// An emulated device on the SPI bus, e.g. an SD card, a shift register or
+// a flash chip. Bytes are in the order they are shifted on the wire (MSB
+// first), DORD is handled by the SPI.
+pub trait SpiDevice {
+    // The AVR is the master. SS is driven low (selected) or high.
+    fn select(&mut self, _selected: bool) {}
+
+    // The AVR is the master. Exchange a byte, `mosi` is sent by the AVR
+    // and the returned byte is shifted out on MISO.
+    fn transfer(&mut self, mosi: u8) -> u8;
+
+    // The AVR is a slave. Return the next byte to send on MOSI to start a
+    // transfer. It is polled only while SS is driven low.
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+
+    // The AVR is a slave. The transfer started by `poll` is completed and
+    // `miso` is sent by the AVR.
+    fn receive(&mut self, _miso: u8) {}
+
+    // The AVR is a slave. SCK period in CPU cycles, which must be at
+    // least 4.
+    fn sck_cycles(&self) -> u64 {
+        4
+    }
+}
+
+struct Transfer {
+    // Byte sent by the AVR, in wire order
+    data: u8,
+    // Byte received by the AVR in slave mode, in wire order
+    mosi: u8,
+    start: u64,
+    bit_cycles: u64,
+}
+
+pub struct Spi {
+    sram: Rc<RefCell<SRAM>>,
+    last_cycle: u64,
+    device: Option<Box<dyn SpiDevice>>,
+    transfer: Option<Transfer>,
+    // Data written to SPDR, which is sent by the next transfer in slave mode
+    slave_data: u8,
+    // The receive buffer, SPDR reads this.
+    received: u8,
+    spif: bool,
+    wcol: bool,
+    // SPIF is cleared by reading SPSR with SPIF set, then accessing SPDR.
+    is_spif_read: bool,
+    is_selected: bool,
+    // The data output keeps the last bit after a transfer.
+    last_bit: bool,
+    outputs: [Option<bool>; 4],
+
+    spcr: RegisterAddr,
+    spsr: RegisterAddr,
+    spdr: RegisterAddr,
+    ddr: RegisterAddr,
+    pin: RegisterAddr,
+    // SS, MOSI, MISO and SCK
+    pins: [u8; 4],
+}
+
+impl Spi {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        spcr: RegisterAddr,
+        spsr: RegisterAddr,
+        spdr: RegisterAddr,
+        ddr: RegisterAddr,
+        pin: RegisterAddr,
+        pins: [u8; 4],
+    ) -> Spi {
+        Spi {
+            sram: sram,
+            last_cycle: 0,
+            device: None,
+            transfer: None,
+            slave_data: 0,
+            received: 0,
+            spif: false,
+            wcol: false,
+            is_spif_read: false,
+            is_selected: false,
+            last_bit: false,
+            outputs: [None; 4],
+            spcr: spcr,
+            spsr: spsr,
+            spdr: spdr,
+            ddr: ddr,
+            pin: pin,
+            pins: pins,
+        }
+    }
+
+    // The attached device is kept.
+    pub fn initialize(&mut self) {
+        self.transfer = None;
+        self.slave_data = 0;
+        self.received = 0;
+        self.spif = false;
+        self.wcol = false;
+        self.is_spif_read = false;
+        self.last_bit = false;
+        self.outputs = [None; 4];
+        self.last_cycle = 0;
+        self.select(false);
+    }
+
+    pub fn attach(&mut self, device: Box<dyn SpiDevice>) {
+        self.device = Some(device);
+        self.is_selected = false;
+    }
+
+    // The level driven by the SPI on bit `n` of the port, None if the pin
+    // is not overridden.
+    pub fn output(&self, n: u8) -> Option<bool> {
+        self.pins
+            .iter()
+            .position(|&p| p == n)
+            .and_then(|i| self.outputs[i])
+    }
+
+    fn spcr(&self) -> u8 {
+        self.sram.borrow().get(self.spcr)
+    }
+
+    fn spsr(&self) -> u8 {
+        self.sram.borrow().get(self.spsr)
+    }
+
+    fn is_enabled(&self) -> bool {
+        bit(self.spcr(), SPE)
+    }
+
+    fn is_master(&self) -> bool {
+        bit(self.spcr(), MSTR)
+    }
+
+    fn is_output(&self, i: usize) -> bool {
+        bit(self.sram.borrow().get(self.ddr), self.pins[i])
+    }
+
+    // SCK = clk_io / 2 ~ 128
+    fn master_bit_cycles(&self) -> u64 {
+        let divider = match self.spcr() & 0b11 {
+            0b00 => 4,
+            0b01 => 16,
+            0b10 => 64,
+            _ => 128,
+        };
+        if bit(self.spsr(), SPI2X) {
+            divider / 2
+        } else {
+            divider
+        }
+    }
+
+    // Bytes are exchanged with the device in wire order.
+    fn wire_order(&self, data: u8) -> u8 {
+        if bit(self.spcr(), DORD) {
+            data.reverse_bits()
+        } else {
+            data
+        }
+    }
+
+    fn ss(&self) -> bool {
+        bit(self.sram.borrow().get(self.pin), self.pins[SS])
+    }
+
+    // In master mode, SS as an output is a general output pin which
+    // selects the device.
+    fn select(&mut self, selected: bool) {
+        if self.is_selected != selected {
+            self.is_selected = selected;
+            if let Some(device) = self.device.as_mut() {
+                device.select(selected);
+            }
+        }
+    }
+
+    fn update_select(&mut self) {
+        let selected = self.is_enabled() && self.is_master() && self.is_output(SS) && !self.ss();
+        self.select(selected);
+    }
+
+    // In master mode, SS as an input driven low means that another master
+    // selects the AVR. MSTR is cleared, so that the SPI becomes a slave, and
+    // SPIF is set (mode fault).
+    fn detect_mode_fault(&mut self) {
+        if self.is_enabled() && self.is_master() && !self.is_output(SS) && !self.ss() {
+            self.sram.borrow_mut().set_bit((self.spcr, MSTR), false);
+            self.transfer = None;
+            self.spif = true;
+        }
+    }
+
+    // SPIF and WCOL are read only.
+    fn access_spsr(&mut self) {
+        let (is_read, is_written, spsr) = {
+            let sram = self.sram.borrow();
+            (
+                sram.is_read(self.spsr),
+                sram.is_written(self.spsr),
+                sram.get(self.spsr),
+            )
+        };
+        if !is_written {
+            // SPIF is cleared by executing the interrupt.
+            self.spif = bit(spsr, SPIF);
+        }
+        if is_read && self.spif {
+            self.is_spif_read = true;
+        }
+    }
+
+    fn access_spdr(&mut self, cycle: u64) {
+        let (is_read, is_written, spdr) = {
+            let sram = self.sram.borrow();
+            (
+                sram.is_read(self.spdr),
+                sram.is_written(self.spdr),
+                sram.get(self.spdr),
+            )
+        };
+        if (is_read || is_written) && self.is_spif_read {
+            self.spif = false;
+            self.wcol = false;
+            self.is_spif_read = false;
+        }
+        if !is_written || !self.is_enabled() {
+            return;
+        }
+
+        if self.transfer.is_some() {
+            // Writing SPDR during a transfer is ignored.
+            self.wcol = true;
+        } else if self.is_master() {
+            self.transfer = Some(Transfer {
+                data: self.wire_order(spdr),
+                mosi: 0,
+                start: cycle,
+                bit_cycles: self.master_bit_cycles(),
+            });
+        } else {
+            self.slave_data = self.wire_order(spdr);
+        }
+    }
+
+    fn start_slave_transfer(&mut self, cycle: u64) {
+        if let Some(device) = self.device.as_mut() {
+            if let Some(mosi) = device.poll() {
+                self.transfer = Some(Transfer {
+                    data: self.slave_data,
+                    mosi: mosi,
+                    start: cycle,
+                    bit_cycles: device.sck_cycles().max(4),
+                });
+            }
+        }
+    }
+
+    fn complete(&mut self, transfer: Transfer) {
+        let received = if self.is_master() {
+            match self.device.as_mut() {
+                Some(device) if self.is_selected => device.transfer(transfer.data),
+                // MISO is not driven by anyone.
+                _ => 0xff,
+            }
+        } else {
+            if let Some(device) = self.device.as_mut() {
+                device.receive(transfer.data);
+            }
+            transfer.mosi
+        };
+        self.received = self.wire_order(received);
+        self.spif = true;
+    }
+
+    // SCK and the data bit which is shifted out at `cycle`.
+    fn levels(&self, transfer: &Transfer, cycle: u64) -> (bool, bool) {
+        let elapsed = cycle - transfer.start;
+        let n = (elapsed / transfer.bit_cycles).min(7);
+        let is_second_half = elapsed % transfer.bit_cycles >= transfer.bit_cycles / 2;
+        let spcr = self.spcr();
+        let sck = bit(spcr, CPOL) ^ (is_second_half != bit(spcr, CPHA));
+        (sck, bit(transfer.data, 7 - n as u8))
+    }
+
+    // MOSI and SCK are driven in master mode and MISO in slave mode with SS
+    // low if the pins are configured as outputs. SCK is idle at CPOL.
+    fn update_outputs(&mut self, cycle: u64) {
+        let mut outputs = [None; 4];
+        if self.is_enabled() {
+            let (sck, data) = match &self.transfer {
+                Some(transfer) => self.levels(transfer, cycle),
+                None => (bit(self.spcr(), CPOL), self.last_bit),
+            };
+            self.last_bit = data;
+            if self.is_master() {
+                if self.is_output(MOSI) {
+                    outputs[MOSI] = Some(data);
+                }
+                if self.is_output(SCK) {
+                    outputs[SCK] = Some(sck);
+                }
+            } else if self.is_output(MISO) && !self.ss() {
+                outputs[MISO] = Some(data);
+            }
+        }
+        self.outputs = outputs;
+    }
+
+    fn update_registers(&mut self) {
+        let mut sram = self.sram.borrow_mut();
+        let spsr = (sram.get(self.spsr) & 0b0011_1111)
+            | (self.spif as u8) << SPIF
+            | (self.wcol as u8) << WCOL;
+        sram.set(self.spsr, spsr);
+        sram.set(self.spdr, self.received);
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_spsr();
+        self.access_spdr(cycle);
+        self.detect_mode_fault();
+        self.update_select();
+
+        if !self.is_enabled() {
+            self.transfer = None;
+        } else if !self.is_master() {
+            if self.ss() {
+                // The slave is passive while SS is high, and a partially
+                // received byte is dropped.
+                self.transfer = None;
+            } else if self.transfer.is_none() {
+                self.start_slave_transfer(cycle);
+            }
+        }
+
+        if let Some(transfer) = self.transfer.take() {
+            if cycle >= transfer.start + 8 * transfer.bit_cycles {
+                self.complete(transfer);
+            } else {
+                self.transfer = Some(transfer);
+            }
+        }
+
+        self.update_outputs(cycle);
+        self.update_registers();
+        self.last_cycle = cycle;
+    }
+
+    // The clock of the SPI is stopped by a sleep mode or PRR, an ongoing
+    // transfer is delayed.
+    pub fn pause(&mut self, cycle: u64) {
+        let diff = cycle - self.last_cycle;
+        if let Some(transfer) = self.transfer.as_mut() {
+            transfer.start += diff;
+        }
+        self.last_cycle = cycle;
+    }
+}
+
+impl fmt::Display for Spi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "spi =====
+    spcr: {:08b},    spsr: {:08b},    spdr: {:02x},    transfer: {:?},    selected: {}",
+            self.spcr(),
+            self.spsr(),
+            self.received,
+            self.transfer.as_ref().map(|t| t.data),
+            self.is_selected,
+        )
+    }
+}
+
+#[cfg(test)]
+use super::arch::atmega328p::{REGISTER_BIT_MAP, REGISTER_MAP, REGISTER_WORD_MAP, SRAM_SIZE};
+
+// Records the bytes from the AVR and replies `reply` in master mode, or
+// sends `mosi` once in slave mode.
+#[cfg(test)]
+struct Recorder {
+    bytes: Rc<RefCell<Vec<u8>>>,
+    reply: u8,
+    mosi: Option<u8>,
+}
+
+#[cfg(test)]
+impl SpiDevice for Recorder {
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        self.bytes.borrow_mut().push(mosi);
+        self.reply
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.mosi.take()
+    }
+
+    fn receive(&mut self, miso: u8) {
+        self.bytes.borrow_mut().push(miso);
+    }
+}
+
+// SPI on PB2 ~ PB5 with a Recorder attached
+#[cfg(test)]
+fn new_spi(reply: u8, mosi: Option<u8>) -> (Rc<RefCell<SRAM>>, Spi, Rc<RefCell<Vec<u8>>>) {
+    let sram = Rc::new(RefCell::new(SRAM::new(
+        SRAM_SIZE,
+        &REGISTER_MAP,
+        &REGISTER_WORD_MAP,
+        &REGISTER_BIT_MAP,
+    )));
+    let mut spi = Spi::new(
+        Rc::clone(&sram),
+        REGISTER_MAP.spcr,
+        REGISTER_MAP.spsr,
+        REGISTER_MAP.spdr,
+        REGISTER_MAP.ddrb,
+        REGISTER_MAP.pinb,
+        [2, 3, 4, 5],
+    );
+    let bytes = Rc::new(RefCell::new(vec![]));
+    spi.attach(Box::new(Recorder {
+        bytes: Rc::clone(&bytes),
+        reply: reply,
+        mosi: mosi,
+    }));
+    (sram, spi, bytes)
+}
+
+// Access a register by an instruction at `cycle` and run the SPI.
+#[cfg(test)]
+fn write(sram: &Rc<RefCell<SRAM>>, spi: &mut Spi, cycle: u64, a: RegisterAddr, v: u8) {
+    sram.borrow_mut().start_tracing();
+    sram.borrow_mut().set(a, v);
+    sram.borrow_mut().stop_tracing();
+    spi.next(cycle);
+}
+
+#[cfg(test)]
+fn read(sram: &Rc<RefCell<SRAM>>, spi: &mut Spi, cycle: u64, a: RegisterAddr) -> u8 {
+    sram.borrow_mut().start_tracing();
+    let v = sram.borrow().get(a);
+    sram.borrow_mut().stop_tracing();
+    spi.next(cycle);
+    v
+}
+
+// Run the SPI at `cycle` without any access by instructions.
+#[cfg(test)]
+fn step(sram: &Rc<RefCell<SRAM>>, spi: &mut Spi, cycle: u64) {
+    sram.borrow_mut().start_tracing();
+    sram.borrow_mut().stop_tracing();
+    spi.next(cycle);
+}
+
+// Run the SPI from `cycle` until SPIF is set and return the cycle.
+#[cfg(test)]
+fn wait_spif(sram: &Rc<RefCell<SRAM>>, spi: &mut Spi, cycle: u64) -> u64 {
+    (cycle + 1..cycle + 10_000)
+        .find(|c| {
+            step(sram, spi, *c);
+            sram.borrow().get_bit(REGISTER_BIT_MAP.spif)
+        })
+        .unwrap()
+}
+
+// SS, MOSI and SCK are outputs.
+#[cfg(test)]
+const MASTER_DDRB: u8 = 0b0010_1100;
+
+#[test]
+fn test_clock_rates() {
+    // (SPR1:0, SPI2X, cycles of a byte)
+    let cases = [
+        (0b00, 0, 32),
+        (0b01, 0, 128),
+        (0b10, 0, 512),
+        (0b11, 0, 1024),
+        (0b00, 1, 16),
+        (0b01, 1, 64),
+        (0b10, 1, 256),
+        (0b11, 1, 512),
+    ];
+    for (spr, spi2x, cycles) in cases.iter() {
+        let (sram, mut spi, bytes) = new_spi(0, None);
+        write(&sram, &mut spi, 0, REGISTER_MAP.ddrb, MASTER_DDRB);
+        write(
+            &sram,
+            &mut spi,
+            1,
+            REGISTER_MAP.spcr,
+            1 << SPE | 1 << MSTR | spr,
+        );
+        write(&sram, &mut spi, 2, REGISTER_MAP.spsr, *spi2x);
+        write(&sram, &mut spi, 10, REGISTER_MAP.spdr, 0x5a);
+        assert_eq!(wait_spif(&sram, &mut spi, 10), 10 + cycles);
+        assert_eq!(*bytes.borrow(), vec![0x5a]);
+    }
+}
+
+#[test]
+fn test_data_order() {
+    let (sram, mut spi, bytes) = new_spi(0b0000_0011, None);
+    write(&sram, &mut spi, 0, REGISTER_MAP.ddrb, MASTER_DDRB);
+    write(
+        &sram,
+        &mut spi,
+        1,
+        REGISTER_MAP.spcr,
+        1 << SPE | 1 << DORD | 1 << MSTR,
+    );
+
+    // LSB first, so that the bits are reversed on the wire.
+    write(&sram, &mut spi, 2, REGISTER_MAP.spdr, 0b1000_0000);
+    wait_spif(&sram, &mut spi, 2);
+    assert_eq!(*bytes.borrow(), vec![0b0000_0001]);
+    assert_eq!(sram.borrow().get(REGISTER_MAP.spdr), 0b1100_0000);
+}
+
+#[test]
+fn test_write_collision() {
+    let (sram, mut spi, bytes) = new_spi(0x00, None);
+    write(&sram, &mut spi, 0, REGISTER_MAP.ddrb, MASTER_DDRB);
+    write(&sram, &mut spi, 1, REGISTER_MAP.spcr, 1 << SPE | 1 << MSTR);
+
+    // Writing SPDR during a transfer sets WCOL and the byte is not sent.
+    write(&sram, &mut spi, 2, REGISTER_MAP.spdr, 0x11);
+    write(&sram, &mut spi, 10, REGISTER_MAP.spdr, 0x22);
+    assert!(bit(sram.borrow().get(REGISTER_MAP.spsr), WCOL));
+    let cycle = wait_spif(&sram, &mut spi, 10);
+    assert_eq!(cycle, 34);
+    assert_eq!(*bytes.borrow(), vec![0x11]);
+
+    // Reading SPSR and then SPDR clears SPIF and WCOL.
+    read(&sram, &mut spi, 40, REGISTER_MAP.spsr);
+    read(&sram, &mut spi, 41, REGISTER_MAP.spdr);
+    assert_eq!(sram.borrow().get(REGISTER_MAP.spsr) >> 6, 0b00);
+}
+
+#[test]
+fn test_mode_fault() {
+    let (sram, mut spi, bytes) = new_spi(0x00, None);
+    // SS is an input pulled up by the other master.
+    write(&sram, &mut spi, 0, REGISTER_MAP.ddrb, 0b0010_1000);
+    sram.borrow_mut().set(REGISTER_MAP.pinb, 0b0000_0100);
+    write(&sram, &mut spi, 1, REGISTER_MAP.spcr, 1 << SPE | 1 << MSTR);
+    write(&sram, &mut spi, 2, REGISTER_MAP.spdr, 0x11);
+    assert!(bit(sram.borrow().get(REGISTER_MAP.spcr), MSTR));
+
+    // SS driven low clears MSTR and sets SPIF, the transfer is aborted.
+    sram.borrow_mut().set(REGISTER_MAP.pinb, 0);
+    step(&sram, &mut spi, 10);
+    let (spcr, spsr) = (
+        sram.borrow().get(REGISTER_MAP.spcr),
+        sram.borrow().get(REGISTER_MAP.spsr),
+    );
+    assert!(!bit(spcr, MSTR));
+    assert!(bit(spsr, SPIF));
+    assert!(bytes.borrow().is_empty());
+}
+
+#[test]
+fn test_slave_select() {
+    let (sram, mut spi, bytes) = new_spi(0x00, Some(0x3c));
+    // MISO is an output and SS is high.
+    write(&sram, &mut spi, 0, REGISTER_MAP.ddrb, 0b0001_0000);
+    sram.borrow_mut().set(REGISTER_MAP.pinb, 0b0000_0100);
+    write(&sram, &mut spi, 1, REGISTER_MAP.spcr, 1 << SPE);
+    write(&sram, &mut spi, 2, REGISTER_MAP.spdr, 0xa5);
+
+    // The slave is passive and MISO is not driven while SS is high.
+    for cycle in 3..100 {
+        step(&sram, &mut spi, cycle);
+    }
+    assert!(bytes.borrow().is_empty());
+    assert_eq!(spi.output(4), None);
+
+    // A byte is exchanged with SS low, SCK = 4 cycles by default.
+    sram.borrow_mut().set(REGISTER_MAP.pinb, 0);
+    assert_eq!(wait_spif(&sram, &mut spi, 100), 133);
+    assert_eq!(*bytes.borrow(), vec![0xa5]);
+    assert_eq!(sram.borrow().get(REGISTER_MAP.spdr), 0x3c);
+    assert!(spi.output(4).is_some());
+}
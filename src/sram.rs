@@ -1,4 +1,5 @@
 use super::util::bit::*;
+use std::cell::RefCell;
 use std::fmt;
 
 macro_rules! define_stationary_struct {
@@ -16,9 +17,22 @@ define_stationary_struct!(
     RegisterBitMap,
     RegisterBitAddr,
     c, z, n, v, s, h, t, i,
-    tov0, ocf0a, ocf0b, // Timer 0
-    tov1, ocf1a, ocf1b, // Timer 1
-    tov2, ocf2a, ocf2b  // Timer 2
+    spmie, rwwsb, selfprgen,
+    se, prtim0, prtim1, prtim2, prusart0, prspi, prtwi,     // Power management
+    pradc,
+    pud,                                                    // I/O ports
+    rxc0, txc0, udre0, rxcie0, txcie0, udrie0,              // USART 0
+    spif, spie,                                             // SPI
+    twint, twie,                                            // TWI
+    adif, adie, aci, intf0,                                 // ADC and its triggers
+    aco, acie, acic,                                        // Analog comparator
+    int0, int1, intf1, pcie0, pcie1, pcie2,                 // External interrupts
+    pcif0, pcif1, pcif2,
+    eepe, eerie,                                            // EEPROM
+    wdif, wdie, wdrf,                                       // Watchdog
+    tov0, ocf0a, ocf0b,       toie0, ocie0a, ocie0b,        // Timer 0
+    tov1, ocf1a, ocf1b, icf1, toie1, ocie1a, ocie1b, icie1, // Timer 1
+    tov2, ocf2a, ocf2b,       toie2, ocie2a, ocie2b         // Timer 2
 );
 
 pub type RegisterAddr = usize;
@@ -26,8 +40,10 @@ pub type RegisterAddr = usize;
 define_stationary_struct!(
     RegisterMap,
     RegisterAddr,
-    sreg, sph, spl, portd, ddrd, pind, ucsr0a, ucsr0b, ucsr0c,
-    portc, ddrc, pinc, portb, ddrb, pinb, ramend, mcusr, twsr, twar, twdr,
+    sreg, sph, spl, eind, rampz, spmcsr, smcr, prr, portd, ddrd, pind, ucsr0a, ucsr0b, ucsr0c, udr0,
+    portc, ddrc, pinc, portb, ddrb, pinb, ramend, mcusr, twsr, twar, twdr, spcr, spsr, spdr,
+    twbr, twcr, twamr, admux, adcsra, adcsrb, acsr, eifr, eecr, eedr, wdtcsr, assr,
+    mcucr, clkpr, eicra, eimsk, pcicr, pcifr, pcmsk0, pcmsk1, pcmsk2,
     // TODO: This may not compatible with archs except atmega328p.
     tcnt0, tccr0a, tccr0b,         ocr0a, ocr0b, timsk0, tifr0, // Timer 0 (8-bit)
            tccr1a, tccr1b, tccr1c,               timsk1, tifr1, // Timer 1 (16-bit)
@@ -40,14 +56,34 @@ define_stationary_struct!(
     RegisterWordMap,
     RegisterWordAddr,
     sp, x, y, z,
-    tcnt1, ocr1a, ocr1b, icr1 // timer 1 (16-bit)
+    tcnt1, ocr1a, ocr1b, icr1, // timer 1 (16-bit)
+    ubrr0,                     // USART 0
+    adc,                       // ADC
+    eear                       // EEPROM
 );
 
+// I/O registers and extended I/O registers
+const IO_REGISTERS: std::ops::Range<usize> = 0x20..0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read(usize),
+    Write(usize),
+    // SBI and CBI write only one bit.
+    WriteBit(usize, u8),
+}
+
 pub struct SRAM {
     data: Vec<u8>,
     pub map: &'static RegisterMap,
     pub word_map: &'static RegisterWordMap,
     pub bit_map: &'static RegisterBitMap,
+
+    // Accesses to I/O registers by the last executed instruction, so that
+    // peripherals can react to reads and writes with side effects
+    // (e.g. reading UDR0 pops the receive buffer).
+    is_tracing: bool,
+    accesses: RefCell<Vec<Access>>,
 }
 
 impl SRAM {
@@ -62,31 +98,74 @@ impl SRAM {
             map: map,
             word_map: word_map,
             bit_map: bit_map,
+            is_tracing: false,
+            accesses: RefCell::new(vec![]),
         }
     }
 
     pub fn get(&self, a: usize) -> u8 {
+        if self.is_tracing && IO_REGISTERS.contains(&a) {
+            self.accesses.borrow_mut().push(Access::Read(a));
+        }
         self.data[a]
     }
 
     pub fn gets(&self, a: usize, b: usize) -> (u8, u8) {
-        (self.data[a], self.data[b])
+        (self.get(a), self.get(b))
     }
 
     pub fn set(&mut self, a: usize, v: u8) {
+        if self.is_tracing && IO_REGISTERS.contains(&a) {
+            self.accesses.get_mut().push(Access::Write(a));
+        }
         self.data[a] = v;
     }
 
+    // Record the accesses of the instruction executed until stop_tracing().
+    pub fn start_tracing(&mut self) {
+        self.accesses.get_mut().clear();
+        self.is_tracing = true;
+    }
+
+    pub fn stop_tracing(&mut self) {
+        self.is_tracing = false;
+    }
+
+    pub fn is_read(&self, a: RegisterAddr) -> bool {
+        self.accesses.borrow().contains(&Access::Read(a))
+    }
+
+    pub fn is_written(&self, a: RegisterAddr) -> bool {
+        self.written_bits(a) != 0
+    }
+
+    // Bits written by the last executed instruction
+    pub fn written_bits(&self, a: RegisterAddr) -> u8 {
+        self.accesses
+            .borrow()
+            .iter()
+            .fold(0, |bits, access| match *access {
+                Access::Write(w) if w == a => 0xff,
+                Access::WriteBit(w, b) if w == a => bits | 1 << b,
+                _ => bits,
+            })
+    }
+
     pub fn get_bit(&self, addr: RegisterBitAddr) -> bool {
         bit(self.data[addr.0], addr.1)
     }
 
     pub fn set_bit(&mut self, addr: RegisterBitAddr, v: bool) {
-        let old = self.get(addr.0);
+        if self.is_tracing && IO_REGISTERS.contains(&addr.0) {
+            self.accesses
+                .get_mut()
+                .push(Access::WriteBit(addr.0, addr.1));
+        }
+        let old = self.data[addr.0];
         if v {
-            self.set(addr.0, old | (1 << addr.1));
+            self.data[addr.0] = old | (1 << addr.1);
         } else {
-            self.set(addr.0, old & !(1 << addr.1));
+            self.data[addr.0] = old & !(1 << addr.1);
         }
     }
 
@@ -99,6 +178,20 @@ impl SRAM {
         self.set(addr.1, low_byte(v));
     }
 
+    // SREG bit by its index s, as encoded in BRBS/BRBC.
+    pub fn sreg_bit(&self, s: u8) -> RegisterBitAddr {
+        match s {
+            0 => self.bit_map.c,
+            1 => self.bit_map.z,
+            2 => self.bit_map.n,
+            3 => self.bit_map.v,
+            4 => self.bit_map.s,
+            5 => self.bit_map.h,
+            6 => self.bit_map.t,
+            _ => self.bit_map.i,
+        }
+    }
+
     pub fn sp(&self) -> u16 {
         self.get_word(self.word_map.sp)
     }
@@ -118,16 +211,16 @@ impl SRAM {
         v
     }
 
-    pub fn push_pc_stack(&mut self, pc: usize) {
-        let w = (pc & 0xffff) as u16;
-        self.push_stack(high_byte(w));
-        self.push_stack(low_byte(w));
+    // The return address is pushed from its low byte, so it is stored in
+    // big-endian order. `size` is 2 bytes for 16-bit PC, 3 for 22-bit PC.
+    pub fn push_pc_stack(&mut self, pc: usize, size: usize) {
+        for n in 0..size {
+            self.push_stack((pc >> (8 * n)) as u8);
+        }
     }
 
-    pub fn pop_pc_stack(&mut self) -> u16 {
-        let l = self.pop_stack();
-        let h = self.pop_stack();
-        concat(h, l)
+    pub fn pop_pc_stack(&mut self, size: usize) -> usize {
+        (0..size).fold(0, |pc, _| pc << 8 | self.pop_stack() as usize)
     }
 
     pub fn set_status_by_arithmetic_instruction(&mut self, d: u8, r: u8, res: u8) {
@@ -153,6 +246,21 @@ impl SRAM {
         self.set_bit(self.bit_map.s, self.signed_test());
     }
 
+    // The 16-bit product is placed in R1 (high byte) and R0 (low byte).
+    pub fn set_multiplication_result(&mut self, res: u16) {
+        self.set(1, high_byte(res));
+        self.set(0, low_byte(res));
+    }
+
+    // C is the bit shifted out of Rd.
+    pub fn set_status_by_shift_instruction(&mut self, res: u8, c: bool) {
+        self.set_bit(self.bit_map.n, msb(res));
+        self.set_bit(self.bit_map.z, res == 0);
+        self.set_bit(self.bit_map.c, c);
+        self.set_bit(self.bit_map.v, msb(res) ^ c);
+        self.set_bit(self.bit_map.s, self.signed_test());
+    }
+
     pub fn signed_test(&self) -> bool {
         self.get_bit(self.bit_map.v) ^ self.get_bit(self.bit_map.n)
     }
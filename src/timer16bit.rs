@@ -1,8 +1,16 @@
 use super::sram::*;
+use super::util::bit::*;
 use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
+// TCCR1B
+const ICNC: u8 = 7;
+const ICES: u8 = 6;
+
+// The noise canceller requires 4 successive equal samples of ICP1.
+const NOISE_CANCELER_SAMPLES: u64 = 4;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Mode {
     Normal,
@@ -12,12 +20,25 @@ pub enum Mode {
 }
 
 pub struct Timer16bit {
-    count: u16,
+    count: u64,
     last_cycle: u64,
     last_mode: Mode,
     last_prescale: Option<u16>,
     is_up_phase: bool,
     sram: Rc<RefCell<SRAM>>,
+    // Compare values in use, OCR1x is double buffered in the PWM modes.
+    ocr: [u16; 2],
+    // Level of the Tn pin and the last sampled one
+    clock_pin: bool,
+    last_clock_pin: bool,
+    // Levels of the OC1A and OC1B outputs
+    oc: [bool; 2],
+    // TIFR1 as seen by the timer
+    flags: u8,
+    // The input capture source and the level passed by the noise canceller
+    icp_level: bool,
+    icp_changed_cycle: u64,
+    icp_filtered: bool,
 
     tcnt: RegisterWordAddr,
     tccra: RegisterAddr,
@@ -29,6 +50,13 @@ pub struct Timer16bit {
     tov: RegisterBitAddr,
     ocfa: RegisterBitAddr,
     ocfb: RegisterBitAddr,
+    icf: RegisterBitAddr,
+    // DDR bits of the OC1A and OC1B pins
+    oc_pins: [RegisterBitAddr; 2],
+    // ICP1 pin, or the analog comparator output selected by ACIC
+    icp: RegisterBitAddr,
+    acic: RegisterBitAddr,
+    aco: RegisterBitAddr,
 }
 
 impl Timer16bit {
@@ -44,6 +72,11 @@ impl Timer16bit {
         tov: RegisterBitAddr,
         ocfa: RegisterBitAddr,
         ocfb: RegisterBitAddr,
+        icf: RegisterBitAddr,
+        oc_pins: [RegisterBitAddr; 2],
+        icp: RegisterBitAddr,
+        acic: RegisterBitAddr,
+        aco: RegisterBitAddr,
     ) -> Timer16bit {
         Timer16bit {
             count: 0,
@@ -52,6 +85,14 @@ impl Timer16bit {
             last_prescale: None,
             is_up_phase: true,
             sram: sram,
+            ocr: [0; 2],
+            clock_pin: false,
+            last_clock_pin: false,
+            oc: [false; 2],
+            flags: 0,
+            icp_level: false,
+            icp_changed_cycle: 0,
+            icp_filtered: false,
             tcnt: tcnt,
             tccra: tccra,
             tccrb: tccrb,
@@ -62,9 +103,28 @@ impl Timer16bit {
             tov: tov,
             ocfa: ocfa,
             ocfb: ocfb,
+            icf: icf,
+            oc_pins: oc_pins,
+            icp: icp,
+            acic: acic,
+            aco: aco,
         }
     }
 
+    pub fn initialize(&mut self) {
+        self.count = 0;
+        self.last_prescale = None;
+        self.is_up_phase = true;
+        self.ocr = [0; 2];
+        self.clock_pin = false;
+        self.last_clock_pin = false;
+        self.oc = [false; 2];
+        self.flags = 0;
+        self.icp_level = false;
+        self.icp_changed_cycle = 0;
+        self.icp_filtered = false;
+    }
+
     fn tcnt(&self) -> u16 {
         self.sram.borrow().get_word(self.tcnt)
     }
@@ -93,20 +153,8 @@ impl Timer16bit {
         self.sram.borrow().get_word(self.ocrb)
     }
 
-    fn tov(&self) -> bool {
-        self.sram.borrow().get_bit(self.tov)
-    }
-
-    fn ocfa(&self) -> bool {
-        self.sram.borrow().get_bit(self.ocfa)
-    }
-
-    fn ocfb(&self) -> bool {
-        self.sram.borrow().get_bit(self.ocfb)
-    }
-
     fn is_on(&self) -> bool {
-        self.prescale().is_some()
+        self.prescale().is_some() || self.external_clock().is_some()
     }
 
     fn prescale(&self) -> Option<u16> {
@@ -126,17 +174,17 @@ impl Timer16bit {
             (0b00, 0b01) => 0x00ff,
             (0b00, 0b10) => 0x01ff,
             (0b00, 0b11) => 0x03ff,
-            (0b01, 0b00) => self.ocra(),
+            (0b01, 0b00) => self.ocr[0],
             (0b01, 0b01) => 0x00ff,
             (0b01, 0b10) => 0x01ff,
             (0b01, 0b11) => 0x03ff,
             (0b10, 0b00) => self.icr(),
-            (0b10, 0b01) => self.ocra(),
+            (0b10, 0b01) => self.ocr[0],
             (0b10, 0b10) => self.icr(),
-            (0b10, 0b11) => self.ocra(),
+            (0b10, 0b11) => self.ocr[0],
             (0b11, 0b00) => self.icr(),
             (0b11, 0b10) => self.icr(),
-            (0b11, 0b11) => self.ocra(),
+            (0b11, 0b11) => self.ocr[0],
             (_, _) => 0xffff,
         }
     }
@@ -163,8 +211,241 @@ impl Timer16bit {
         }
     }
 
+    fn wgm(&self) -> u8 {
+        (self.tccrb() & 0b11000) >> 1 | self.tccra() & 0b11
+    }
+
+    // COM1A1:0 (i = 0) or COM1B1:0 (i = 1)
+    fn com(&self, i: usize) -> u8 {
+        (self.tccra() >> (6 - 2 * i)) & 0b11
+    }
+
+    // Toggle on compare match is only available for OC1A in the PWM modes
+    // with WGM13 set.
+    fn is_connected(&self, i: usize) -> bool {
+        match (self.com(i), self.mode()) {
+            (0b00, _) => false,
+            (0b01, Mode::Normal) | (0b01, Mode::CTC) => true,
+            (0b01, _) => i == 0 && bit(self.wgm(), 3),
+            (_, _) => true,
+        }
+    }
+
+    // The level of OC1A, None if the pin is not overridden by the timer.
+    pub fn output_a(&self) -> Option<bool> {
+        self.output(0)
+    }
+
+    // The level of OC1B, None if the pin is not overridden by the timer.
+    pub fn output_b(&self) -> Option<bool> {
+        self.output(1)
+    }
+
+    // The DDR bit of the pin must be set to output the level.
+    fn output(&self, i: usize) -> Option<bool> {
+        let is_output = self.sram.borrow().get_bit(self.oc_pins[i]);
+        if self.is_connected(i) && is_output {
+            Some(self.oc[i])
+        } else {
+            None
+        }
+    }
+
+    fn update_ocr(&mut self) {
+        self.ocr = [self.ocra(), self.ocrb()];
+    }
+
+    fn compare_match(&mut self, i: usize, is_up_counting: bool) {
+        let flag = [self.ocfa, self.ocfb][i];
+        self.sram.borrow_mut().set_bit(flag, true);
+        if !self.is_connected(i) {
+            return;
+        }
+        let com = self.com(i);
+        self.oc[i] = match (self.mode(), com) {
+            (_, 0b01) => !self.oc[i],
+            (Mode::Normal, _) | (Mode::CTC, _) => com == 0b11,
+            // OCR1x equal to TOP results in a constantly high or low output.
+            (Mode::FastPWM, _) if self.ocr[i] == self.top() => self.oc[i],
+            (Mode::FastPWM, _) => com == 0b11,
+            (Mode::PhaseCorrectPWM, _) => (com == 0b11) == is_up_counting,
+        };
+    }
+
+    // Fast PWM outputs are set (non-inverting) or cleared (inverting) at
+    // BOTTOM.
+    fn bottom(&mut self) {
+        if self.mode() != Mode::FastPWM {
+            return;
+        }
+        for i in 0..2 {
+            let com = self.com(i);
+            if self.is_connected(i) && com != 0b01 {
+                self.oc[i] = com == 0b10;
+            }
+        }
+    }
+
+    // Count a clock of the timer.
+    fn tick(&mut self) {
+        let mode = self.mode();
+        // OCR1x is updated immediately in the non-PWM modes, at TOP in the
+        // phase correct PWM mode and at BOTTOM in the fast PWM and the phase
+        // and frequency correct PWM mode (WGM13:0 = 8, 9).
+        if mode == Mode::Normal || mode == Mode::CTC {
+            self.update_ocr();
+        }
+        let tcnt = self.tcnt();
+        let top = self.top();
+        let next = match mode {
+            Mode::Normal => tcnt.wrapping_add(1),
+            Mode::CTC | Mode::FastPWM if tcnt == top => 0,
+            Mode::CTC | Mode::FastPWM => tcnt.wrapping_add(1),
+            Mode::PhaseCorrectPWM => {
+                if tcnt >= top {
+                    self.is_up_phase = false;
+                }
+                if tcnt == 0 {
+                    self.is_up_phase = true;
+                }
+                if self.is_up_phase {
+                    tcnt + 1
+                } else {
+                    tcnt - 1
+                }
+            }
+        };
+        self.sram.borrow_mut().set_word(self.tcnt, next);
+
+        let tov = match mode {
+            Mode::Normal | Mode::CTC => tcnt == 0xffff,
+            Mode::FastPWM | Mode::PhaseCorrectPWM => next == 0,
+        };
+        if tov {
+            self.sram.borrow_mut().set_bit(self.tov, true);
+        }
+        let is_phase_frequency_correct = self.wgm() == 8 || self.wgm() == 9;
+        match mode {
+            Mode::FastPWM if next == 0 => {
+                self.update_ocr();
+                self.bottom();
+            }
+            Mode::PhaseCorrectPWM if is_phase_frequency_correct && next == 0 => self.update_ocr(),
+            Mode::PhaseCorrectPWM if !is_phase_frequency_correct && next == self.top() => {
+                self.update_ocr()
+            }
+            _ => (),
+        }
+
+        // A match at TOP is on the down-counting slope.
+        let is_up_counting = match next {
+            0 => true,
+            n if n == self.top() => false,
+            _ => self.is_up_phase,
+        };
+        for i in 0..2 {
+            if next == self.ocr[i] {
+                self.compare_match(i, is_up_counting);
+            }
+        }
+    }
+
+    // Flags in TIFR1 are cleared by writing one to them or by executing
+    // the interrupt.
+    fn access_tifr(&mut self) {
+        let tifr = self.tov.0;
+        let (written_bits, value) = {
+            let sram = self.sram.borrow();
+            (sram.written_bits(tifr), sram.get(tifr))
+        };
+        // SBI and CBI write only the addressed bit.
+        if written_bits != 0 {
+            self.flags &= !(written_bits & value);
+        } else {
+            self.flags = value;
+        }
+        self.sram.borrow_mut().set(tifr, self.flags);
+    }
+
+    fn capture_input(&self) -> bool {
+        let sram = self.sram.borrow();
+        if sram.get_bit(self.acic) {
+            sram.get_bit(self.aco)
+        } else {
+            sram.get_bit(self.icp)
+        }
+    }
+
+    // ICR1 is used as TOP in WGM13:0 = 8, 10, 12 and 14, which disables
+    // the input capture.
+    fn is_icr_top(&self) -> bool {
+        matches!(self.wgm(), 8 | 10 | 12 | 14)
+    }
+
+    // TCNT1 is copied into ICR1 on the edge selected by ICES1. The noise
+    // canceller delays the edge until the input has been stable for 4 cycles.
+    fn next_capture(&mut self, cycle: u64) {
+        let level = self.capture_input();
+        if level != self.icp_level {
+            self.icp_level = level;
+            self.icp_changed_cycle = cycle;
+        }
+        if self.icp_level == self.icp_filtered {
+            return;
+        }
+        if bit(self.tccrb(), ICNC) && cycle - self.icp_changed_cycle < NOISE_CANCELER_SAMPLES {
+            return;
+        }
+        self.icp_filtered = self.icp_level;
+        if self.icp_filtered != bit(self.tccrb(), ICES) || self.is_icr_top() {
+            return;
+        }
+        let tcnt = self.tcnt();
+        let mut sram = self.sram.borrow_mut();
+        sram.set_word(self.icr, tcnt);
+        sram.set_bit(self.icf, true);
+    }
+
+    // Clocked by the falling (CSn2:0 = 0b110) or the rising (0b111) edge
+    // of the Tn pin
+    fn external_clock(&self) -> Option<bool> {
+        match self.tccrb() & 0b111 {
+            0b110 => Some(false),
+            0b111 => Some(true),
+            _ => None,
+        }
+    }
+
+    // The level of the Tn pin, driven by the host or by other peripherals
+    pub fn set_clock_pin(&mut self, level: bool) {
+        self.clock_pin = level;
+    }
+
+    // The Tn pin is sampled every cycle by the edge detector.
+    fn is_clock_edge(&mut self) -> bool {
+        let is_edge = match self.external_clock() {
+            Some(is_rising) => self.clock_pin != self.last_clock_pin && self.clock_pin == is_rising,
+            None => false,
+        };
+        self.last_clock_pin = self.clock_pin;
+        is_edge
+    }
+
+    // The clock of the timer is stopped by a sleep mode or PRR.
+    pub fn pause(&mut self, cycle: u64) {
+        self.access_tifr();
+        self.last_clock_pin = self.clock_pin;
+        self.last_cycle = cycle;
+    }
+
     pub fn next(&mut self, cycle: u64) {
-        if !self.is_on() {
+        self.access_tifr();
+        self.next_capture(cycle);
+        if self.is_clock_edge() {
+            self.tick();
+        }
+        if self.prescale().is_none() {
+            self.flags = self.sram.borrow().get(self.tov.0);
             self.last_cycle = cycle;
             return;
         }
@@ -173,16 +454,11 @@ impl Timer16bit {
             // TODO: prescale が増加した場合、その増加の比率だけ count を進め、
             //       tcnt は +1 される、としている.
             if self.last_prescale.is_some() && self.prescale().is_some() {
-                let last_prescale = self.last_prescale.unwrap();
-                let prescale = self.prescale().unwrap();
+                let last_prescale = self.last_prescale.unwrap() as u64;
+                let prescale = self.prescale().unwrap() as u64;
                 if prescale > last_prescale {
                     self.count = self.count * prescale / last_prescale;
-                    let tcnt = self.tcnt();
-                    if self.is_up_phase {
-                        self.sram.borrow_mut().set_word(self.tcnt, tcnt + 1);
-                    } else {
-                        self.sram.borrow_mut().set_word(self.tcnt, tcnt - 1);
-                    };
+                    self.tick();
                 }
                 self.count += 1;
             } else {
@@ -190,47 +466,17 @@ impl Timer16bit {
             }
         } else {
             let diff_clk = cycle - self.last_cycle;
-            self.count += diff_clk as u16;
+            self.count += diff_clk;
 
-            let prescale = self.prescale().unwrap();
-            if self.count > prescale {
+            let prescale = self.prescale().unwrap() as u64;
+            while self.count > prescale {
                 self.count -= prescale;
-                let tcnt = self.tcnt();
-                if self.is_up_phase {
-                    self.sram.borrow_mut().set_word(self.tcnt, tcnt + 1);
-                } else {
-                    self.sram.borrow_mut().set_word(self.tcnt, tcnt - 1);
-                };
-            }
-        }
-
-        // check tcnt's compare match
-        match self.mode() {
-            Mode::Normal => {
-                if self.tcnt() >= self.top() {
-                    self.sram.borrow_mut().set_word(self.tcnt, 0);
-                    self.sram.borrow_mut().set_bit(self.tov, !self.tov());
-                }
-            }
-            Mode::CTC => (),
-            Mode::FastPWM => {
-                // compare match with OCRA, OCRB and update OCnA, OCnB
-                if self.tcnt() >= self.top() {
-                    self.sram.borrow_mut().set_word(self.tcnt, 0);
-                    // update OCnA, OCnB
-                }
-            }
-            Mode::PhaseCorrectPWM => {
-                if self.tcnt() >= self.top() {
-                    self.is_up_phase = false;
-                }
-                if self.tcnt() <= 0 {
-                    self.is_up_phase = true;
-                }
+                self.tick();
             }
         }
 
         // update state
+        self.flags = self.sram.borrow().get(self.tov.0);
         self.last_cycle = cycle;
         self.last_prescale = self.prescale();
     }
@@ -243,7 +489,8 @@ impl fmt::Display for Timer16bit {
             "16bit timer =====
     power: {},    mode: {:?},    prescale: {:?},    top: {},
     count: {:3},    tcnt:  {:3},
-    tccra: {:3},    tccrb: {:3},    tccrc: {:3},    icr: {:3},    ocra: {:3},    ocrb: {:3},",
+    tccra: {:3},    tccrb: {:3},    tccrc: {:3},    icr: {:3},    ocra: {:3},    ocrb: {:3},
+    oca: {:?},    ocb: {:?}",
             if self.is_on() { "ON" } else { "OFF" },
             self.mode(),
             self.prescale(),
@@ -256,6 +503,8 @@ impl fmt::Display for Timer16bit {
             self.icr(),
             self.ocra(),
             self.ocrb(),
+            self.output_a(),
+            self.output_b(),
         )
     }
 }
@@ -1,8 +1,20 @@
+use super::clock::*;
 use super::sram::*;
+use super::util::bit::*;
 use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
+// ASSR
+const AS2: u8 = 5;
+
+// The asynchronous clock from the 32.768 kHz crystal on TOSC1 and TOSC2
+const TOSC_FREQUENCY: u64 = 32_768;
+
+// A register written in the asynchronous mode is transferred to the timer
+// within 2 cycles of TOSC1, while its update busy flag in ASSR is set.
+const UPDATE_CYCLES: u64 = 2;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Mode {
     Normal,
@@ -18,12 +30,25 @@ pub enum Timer8bitType {
 }
 
 pub struct Timer8bit {
-    count: u16,
+    count: u64,
     last_cycle: u64,
     last_mode: Mode,
     is_up_phase: bool,
     timer_type: Timer8bitType,
     sram: Rc<RefCell<SRAM>>,
+    clock: Rc<RefCell<Clock>>,
+    // Compare values in use, OCRnx is double buffered in the PWM modes.
+    ocr: [u8; 2],
+    // Level of the Tn pin and the last sampled one
+    clock_pin: bool,
+    last_clock_pin: bool,
+    // Levels of the OCnA and OCnB outputs
+    oc: [bool; 2],
+    // TIFRn as seen by the timer
+    flags: u8,
+    // TOSC1 cycle until which TCNTn, OCRnA, OCRnB, TCCRnA and TCCRnB are
+    // busy in the asynchronous mode
+    busy: [Option<u64>; 5],
 
     tcnt: RegisterAddr,
     tccra: RegisterAddr,
@@ -33,12 +58,17 @@ pub struct Timer8bit {
     tov: RegisterBitAddr,
     ocfa: RegisterBitAddr,
     ocfb: RegisterBitAddr,
+    // DDR bits of the OCnA and OCnB pins
+    oc_pins: [RegisterBitAddr; 2],
+    // Timer 2 can be clocked asynchronously.
+    assr: Option<RegisterAddr>,
 }
 
 impl Timer8bit {
     pub fn new(
         timer_type: Timer8bitType,
         sram: Rc<RefCell<SRAM>>,
+        clock: Rc<RefCell<Clock>>,
         tcnt: RegisterAddr,
         tccra: RegisterAddr,
         tccrb: RegisterAddr,
@@ -47,6 +77,8 @@ impl Timer8bit {
         tov: RegisterBitAddr,
         ocfa: RegisterBitAddr,
         ocfb: RegisterBitAddr,
+        oc_pins: [RegisterBitAddr; 2],
+        assr: Option<RegisterAddr>,
     ) -> Timer8bit {
         Timer8bit {
             count: 0,
@@ -55,6 +87,13 @@ impl Timer8bit {
             is_up_phase: true,
             timer_type: timer_type,
             sram: sram,
+            clock: clock,
+            ocr: [0; 2],
+            clock_pin: false,
+            last_clock_pin: false,
+            oc: [false; 2],
+            flags: 0,
+            busy: [None; 5],
             tcnt: tcnt,
             tccra: tccra,
             tccrb: tccrb,
@@ -63,9 +102,23 @@ impl Timer8bit {
             tov: tov,
             ocfa: ocfa,
             ocfb: ocfb,
+            oc_pins: oc_pins,
+            assr: assr,
         }
     }
 
+    pub fn initialize(&mut self) {
+        self.count = 0;
+        self.last_mode = Mode::Normal;
+        self.is_up_phase = true;
+        self.ocr = [0; 2];
+        self.clock_pin = false;
+        self.last_clock_pin = false;
+        self.oc = [false; 2];
+        self.flags = 0;
+        self.busy = [None; 5];
+    }
+
     fn tcnt(&self) -> u8 {
         self.sram.borrow().get(self.tcnt)
     }
@@ -86,20 +139,8 @@ impl Timer8bit {
         self.sram.borrow().get(self.ocrb)
     }
 
-    fn tov(&self) -> bool {
-        self.sram.borrow().get_bit(self.tov)
-    }
-
-    fn ocfa(&self) -> bool {
-        self.sram.borrow().get_bit(self.ocfa)
-    }
-
-    fn ocfb(&self) -> bool {
-        self.sram.borrow().get_bit(self.ocfb)
-    }
-
     fn is_on(&self) -> bool {
-        self.prescale().is_some()
+        self.prescale().is_some() || self.external_clock().is_some()
     }
 
     fn prescale_a(&self) -> Option<u16> {
@@ -120,6 +161,9 @@ impl Timer8bit {
             0b010 => Some(8),
             0b011 => Some(32),
             0b100 => Some(64),
+            0b101 => Some(128),
+            0b110 => Some(256),
+            0b111 => Some(1024),
             _ => None,
         }
     }
@@ -147,68 +191,266 @@ impl Timer8bit {
         match ((self.tccrb() & 0b1000) >> 3, self.tccra() & 0b11) {
             (0b0, 0b00) => 0xff,
             (0b0, 0b01) => 0xff,
-            (0b0, 0b10) => self.ocra(),
+            (0b0, 0b10) => self.ocr[0],
             (0b0, 0b11) => 0xff,
-            (0b1, 0b01) => self.ocra(),
-            (0b1, 0b11) => self.ocra(),
+            (0b1, 0b01) => self.ocr[0],
+            (0b1, 0b11) => self.ocr[0],
             (_, _) => 0xff,
         }
     }
 
-    pub fn next(&mut self, cycle: u64) {
-        if !self.is_on() {
-            self.last_cycle = cycle;
-            return;
+    fn wgm2(&self) -> bool {
+        bit(self.tccrb(), 3)
+    }
+
+    // COMnA1:0 (i = 0) or COMnB1:0 (i = 1)
+    fn com(&self, i: usize) -> u8 {
+        (self.tccra() >> (6 - 2 * i)) & 0b11
+    }
+
+    // Toggle on compare match is only available for OCnA in the PWM modes
+    // with WGMn2 set.
+    fn is_connected(&self, i: usize) -> bool {
+        match (self.com(i), self.mode()) {
+            (0b00, _) => false,
+            (0b01, Mode::Normal) | (0b01, Mode::CTC) => true,
+            (0b01, _) => i == 0 && self.wgm2(),
+            (_, _) => true,
         }
+    }
 
-        if self.last_mode != self.mode() && self.count > 0 {
-            self.count -= 1;
+    // The level of OCnA, None if the pin is not overridden by the timer.
+    pub fn output_a(&self) -> Option<bool> {
+        self.output(0)
+    }
+
+    // The level of OCnB, None if the pin is not overridden by the timer.
+    pub fn output_b(&self) -> Option<bool> {
+        self.output(1)
+    }
+
+    // The DDR bit of the pin must be set to output the level.
+    fn output(&self, i: usize) -> Option<bool> {
+        let is_output = self.sram.borrow().get_bit(self.oc_pins[i]);
+        if self.is_connected(i) && is_output {
+            Some(self.oc[i])
+        } else {
+            None
         }
+    }
 
-        let diff_clk = cycle - self.last_cycle;
-        self.count += diff_clk as u16;
+    fn update_ocr(&mut self) {
+        self.ocr = [self.ocra(), self.ocrb()];
+    }
 
-        let prescale = self.prescale().unwrap();
-        if self.count > prescale {
-            self.count -= prescale;
-            let tcnt = self.tcnt();
-            if self.is_up_phase {
-                self.sram.borrow_mut().set(self.tcnt, tcnt + 1);
-            } else {
-                self.sram.borrow_mut().set(self.tcnt, tcnt - 1);
-            };
-        }
-
-        match self.mode() {
-            Mode::Normal => {
-                if self.tcnt() >= self.top() {
-                    self.sram.borrow_mut().set(self.tcnt, 0);
-                    self.sram.borrow_mut().set_bit(self.tov, true);
-                }
-            }
-            Mode::CTC => (),
-            Mode::FastPWM => {
-                // TODO: compare match with OCRA, OCRB and update OCnA, OCnB
-                if self.tcnt() >= self.top() {
-                    self.sram.borrow_mut().set(self.tcnt, 0);
-                    // TODO: update OCnA, OCnB
-                }
+    fn compare_match(&mut self, i: usize, is_up_counting: bool) {
+        let flag = [self.ocfa, self.ocfb][i];
+        self.sram.borrow_mut().set_bit(flag, true);
+        if !self.is_connected(i) {
+            return;
+        }
+        let com = self.com(i);
+        self.oc[i] = match (self.mode(), com) {
+            (_, 0b01) => !self.oc[i],
+            (Mode::Normal, _) | (Mode::CTC, _) => com == 0b11,
+            // OCRnx equal to TOP results in a constantly high or low output.
+            (Mode::FastPWM, _) if self.ocr[i] == self.top() => self.oc[i],
+            (Mode::FastPWM, _) => com == 0b11,
+            (Mode::PhaseCorrectPWM, _) => (com == 0b11) == is_up_counting,
+        };
+    }
+
+    // Fast PWM outputs are set (non-inverting) or cleared (inverting) at
+    // BOTTOM.
+    fn bottom(&mut self) {
+        if self.mode() != Mode::FastPWM {
+            return;
+        }
+        for i in 0..2 {
+            let com = self.com(i);
+            if self.is_connected(i) && com != 0b01 {
+                self.oc[i] = com == 0b10;
             }
+        }
+    }
+
+    // Count a clock of the timer.
+    fn tick(&mut self) {
+        let mode = self.mode();
+        // OCRnx is updated immediately in the non-PWM modes, at TOP in the
+        // phase correct PWM mode and at BOTTOM in the fast PWM mode.
+        if mode == Mode::Normal || mode == Mode::CTC {
+            self.update_ocr();
+        }
+        let tcnt = self.tcnt();
+        let top = self.top();
+        let next = match mode {
+            Mode::Normal => tcnt.wrapping_add(1),
+            Mode::CTC | Mode::FastPWM if tcnt == top => 0,
+            Mode::CTC | Mode::FastPWM => tcnt.wrapping_add(1),
             Mode::PhaseCorrectPWM => {
-                if self.tcnt() >= self.top() {
+                if tcnt >= top {
                     self.is_up_phase = false;
                 }
-                if self.tcnt() <= 0 {
+                if tcnt == 0 {
                     self.is_up_phase = true;
                 }
-
-                // うまく実装できないが、登り始めでセットされる..
-                if 1 <= self.tcnt() && self.tcnt() < 10 && self.is_up_phase {
-                    self.sram.borrow_mut().set_bit(self.tov, true);
+                if self.is_up_phase {
+                    tcnt + 1
+                } else {
+                    tcnt - 1
                 }
             }
+        };
+        self.sram.borrow_mut().set(self.tcnt, next);
+
+        let tov = match mode {
+            Mode::Normal | Mode::CTC => tcnt == 0xff,
+            Mode::FastPWM | Mode::PhaseCorrectPWM => next == 0,
+        };
+        if tov {
+            self.sram.borrow_mut().set_bit(self.tov, true);
+        }
+        match mode {
+            Mode::FastPWM if next == 0 => {
+                self.update_ocr();
+                self.bottom();
+            }
+            Mode::PhaseCorrectPWM if next == self.top() => self.update_ocr(),
+            _ => (),
+        }
+
+        // A match at TOP is on the down-counting slope.
+        let is_up_counting = match next {
+            0 => true,
+            n if n == self.top() => false,
+            _ => self.is_up_phase,
+        };
+        for i in 0..2 {
+            if next == self.ocr[i] {
+                self.compare_match(i, is_up_counting);
+            }
+        }
+    }
+
+    // Flags in TIFRn are cleared by writing one to them or by executing
+    // the interrupt.
+    fn access_tifr(&mut self) {
+        let tifr = self.tov.0;
+        let (is_written, value) = {
+            let sram = self.sram.borrow();
+            (sram.is_written(tifr), sram.written_bits(tifr) & sram.get(tifr))
+        };
+        if is_written {
+            self.flags &= !value;
+        } else {
+            self.flags = self.sram.borrow().get(tifr);
+        }
+        self.sram.borrow_mut().set(tifr, self.flags);
+    }
+
+    // Clocked by TOSC1 instead of clkI/O if AS2 is set.
+    pub fn is_async(&self) -> bool {
+        match self.assr {
+            Some(assr) => bit(self.sram.borrow().get(assr), AS2),
+            None => false,
+        }
+    }
+
+    // Cycles of TOSC1 since the power-on
+    fn tosc_cycles(&self, cycle: u64) -> u64 {
+        self.clock.borrow().ticks(cycle, TOSC_FREQUENCY)
+    }
+
+    // Writing a register in the asynchronous mode sets its update busy flag
+    // (TCNnUB, OCRnAUB, OCRnBUB, TCRnAUB and TCRnBUB).
+    fn access_assr(&mut self, cycle: u64) {
+        let assr = match self.assr {
+            Some(assr) => assr,
+            None => return,
+        };
+        let is_async = self.is_async();
+        let tosc = self.tosc_cycles(cycle);
+        let registers = [self.tcnt, self.ocra, self.ocrb, self.tccra, self.tccrb];
+        for (i, &r) in registers.iter().enumerate() {
+            if is_async && self.sram.borrow().is_written(r) {
+                self.busy[i] = Some(tosc + UPDATE_CYCLES);
+            }
+            if self.busy[i].is_some_and(|until| tosc >= until) {
+                self.busy[i] = None;
+            }
+        }
+        let busy = self
+            .busy
+            .iter()
+            .fold(0, |acc, b| acc << 1 | b.is_some() as u8);
+        let mut sram = self.sram.borrow_mut();
+        let value = sram.get(assr) & 0b0110_0000 | busy;
+        sram.set(assr, value);
+    }
+
+    // Clocked by the falling (CSn2:0 = 0b110) or the rising (0b111) edge
+    // of the Tn pin. Timer 2 has no external clock input.
+    fn external_clock(&self) -> Option<bool> {
+        match (&self.timer_type, self.tccrb() & 0b111) {
+            (Timer8bitType::A, 0b110) => Some(false),
+            (Timer8bitType::A, 0b111) => Some(true),
+            (_, _) => None,
+        }
+    }
+
+    // The level of the Tn pin, driven by the host or by other peripherals
+    pub fn set_clock_pin(&mut self, level: bool) {
+        self.clock_pin = level;
+    }
+
+    // The Tn pin is sampled every cycle by the edge detector.
+    fn is_clock_edge(&mut self) -> bool {
+        let is_edge = match self.external_clock() {
+            Some(is_rising) => self.clock_pin != self.last_clock_pin && self.clock_pin == is_rising,
+            None => false,
+        };
+        self.last_clock_pin = self.clock_pin;
+        is_edge
+    }
+
+    // The clock of the timer is stopped by a sleep mode or PRR.
+    pub fn pause(&mut self, cycle: u64) {
+        self.access_tifr();
+        self.last_clock_pin = self.clock_pin;
+        self.last_cycle = cycle;
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_tifr();
+        self.access_assr(cycle);
+        if self.is_clock_edge() {
+            self.tick();
+        }
+        if self.prescale().is_none() {
+            self.flags = self.sram.borrow().get(self.tov.0);
+            self.last_cycle = cycle;
+            return;
+        }
+
+        if self.last_mode != self.mode() && self.count > 0 {
+            self.count -= 1;
+        }
+
+        let diff_clk = if self.is_async() {
+            self.tosc_cycles(cycle) - self.tosc_cycles(self.last_cycle)
+        } else {
+            cycle - self.last_cycle
+        };
+        self.count += diff_clk;
+
+        let prescale = self.prescale().unwrap() as u64;
+        while self.count > prescale {
+            self.count -= prescale;
+            self.tick();
         }
 
+        self.flags = self.sram.borrow().get(self.tov.0);
         self.last_cycle = cycle;
         self.last_mode = self.mode();
     }
@@ -219,10 +461,12 @@ impl fmt::Display for Timer8bit {
         write!(
             f,
             "8bit timer =====
-    power: {},    mode: {:?},    prescale: {:?},    top: {},
+    power: {},    async: {},    mode: {:?},    prescale: {:?},    top: {},
     count: {:3},    tcnt:  {:3},
-    tccra: {:3},    tccrb: {:3},    ocra: {:3},    ocrb: {:3}",
+    tccra: {:3},    tccrb: {:3},    ocra: {:3},    ocrb: {:3},
+    oca: {:?},    ocb: {:?}",
             if self.is_on() { "ON" } else { "OFF" },
+            self.is_async(),
             self.mode(),
             self.prescale(),
             self.top(),
@@ -232,6 +476,8 @@ impl fmt::Display for Timer8bit {
             self.tccrb(),
             self.ocra(),
             self.ocrb(),
+            self.output_a(),
+            self.output_b(),
         )
     }
 }
@@ -0,0 +1,489 @@
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+// TWCR
+const TWINT: u8 = 7;
+const TWEA: u8 = 6;
+const TWSTA: u8 = 5;
+const TWSTO: u8 = 4;
+const TWWC: u8 = 3;
+const TWEN: u8 = 2;
+
+// Status codes (TWS7:3)
+const START: u8 = 0x08;
+const REPEATED_START: u8 = 0x10;
+const MT_SLA_ACK: u8 = 0x18;
+const MT_SLA_NACK: u8 = 0x20;
+const MT_DATA_ACK: u8 = 0x28;
+const MT_DATA_NACK: u8 = 0x30;
+const MR_SLA_ACK: u8 = 0x40;
+const MR_SLA_NACK: u8 = 0x48;
+const MR_DATA_ACK: u8 = 0x50;
+const MR_DATA_NACK: u8 = 0x58;
+const SR_SLA_ACK: u8 = 0x60;
+const SR_GCALL_ACK: u8 = 0x70;
+const SR_DATA_ACK: u8 = 0x80;
+const SR_DATA_NACK: u8 = 0x88;
+const SR_GCALL_DATA_ACK: u8 = 0x90;
+const SR_GCALL_DATA_NACK: u8 = 0x98;
+const SR_STOP: u8 = 0xa0;
+const ST_SLA_ACK: u8 = 0xa8;
+const ST_DATA_ACK: u8 = 0xb8;
+const ST_DATA_NACK: u8 = 0xc0;
+const ST_LAST_DATA: u8 = 0xc8;
+const NO_INFO: u8 = 0xf8;
+
+// A slave device on the bus, e.g. an EEPROM, an RTC or a temperature sensor.
+pub trait TwiDevice {
+    // 7-bit slave address
+    fn address(&self) -> u8;
+
+    // START (or repeated START) and SLA+R/W addressed to the device.
+    // Returns true to acknowledge.
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+
+    // Receive a byte from the master. Returns true to acknowledge.
+    fn write(&mut self, data: u8) -> bool;
+
+    // Send a byte to the master.
+    fn read(&mut self) -> u8;
+
+    fn stop(&mut self) {}
+}
+
+// A transaction on the bus by an external master, which addresses the AVR
+// as a slave.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TwiTransaction {
+    // Write bytes to the 7-bit address
+    Write(u8, Vec<u8>),
+    // Read a number of bytes from the 7-bit address. The master reads at
+    // least one byte after SLA+R, so that a read of 0 bytes reads one.
+    Read(u8, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Start,
+    Stop,
+    // SLA+R/W
+    Address(u8),
+    Transmit(u8),
+    Receive,
+    SlaveAddress,
+    SlaveReceive,
+    SlaveTransmit(u8),
+    SlaveStop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    NotAddressed,
+    // The index of the addressed device
+    Master(Option<usize>),
+    SlaveReceiver(VecDeque<u8>, bool),
+    // Number of bytes the external master still reads
+    SlaveTransmitter(usize),
+}
+
+pub struct Twi {
+    sram: Rc<RefCell<SRAM>>,
+    last_cycle: u64,
+    devices: Vec<Box<dyn TwiDevice>>,
+    transactions: VecDeque<TwiTransaction>,
+    read_data: Vec<u8>,
+    mode: Mode,
+    // The ongoing bus operation and the cycle it completes
+    operation: Option<(Operation, u64)>,
+    status: u8,
+    is_start_pending: bool,
+    twint: bool,
+    twwc: bool,
+
+    twbr: RegisterAddr,
+    twcr: RegisterAddr,
+    twsr: RegisterAddr,
+    twdr: RegisterAddr,
+    twar: RegisterAddr,
+    twamr: RegisterAddr,
+}
+
+impl Twi {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        twbr: RegisterAddr,
+        twcr: RegisterAddr,
+        twsr: RegisterAddr,
+        twdr: RegisterAddr,
+        twar: RegisterAddr,
+        twamr: RegisterAddr,
+    ) -> Twi {
+        Twi {
+            sram: sram,
+            last_cycle: 0,
+            devices: vec![],
+            transactions: VecDeque::new(),
+            read_data: vec![],
+            mode: Mode::NotAddressed,
+            operation: None,
+            status: NO_INFO,
+            is_start_pending: false,
+            twint: false,
+            twwc: false,
+            twbr: twbr,
+            twcr: twcr,
+            twsr: twsr,
+            twdr: twdr,
+            twar: twar,
+            twamr: twamr,
+        }
+    }
+
+    // Attached devices and queued transactions are kept.
+    pub fn initialize(&mut self) {
+        self.mode = Mode::NotAddressed;
+        self.operation = None;
+        self.status = NO_INFO;
+        self.is_start_pending = false;
+        self.twint = false;
+        self.twwc = false;
+        self.last_cycle = 0;
+    }
+
+    pub fn attach(&mut self, device: Box<dyn TwiDevice>) {
+        self.devices.push(device);
+    }
+
+    // Transactions wait until the TWI is enabled and the bus is free.
+    pub fn push_transaction(&mut self, transaction: TwiTransaction) {
+        self.transactions.push_back(transaction);
+    }
+
+    // Bytes read from the AVR by the external master.
+    pub fn drain_read(&mut self) -> Vec<u8> {
+        self.read_data.drain(..).collect()
+    }
+
+    fn twcr(&self) -> u8 {
+        self.sram.borrow().get(self.twcr)
+    }
+
+    fn twdr(&self) -> u8 {
+        self.sram.borrow().get(self.twdr)
+    }
+
+    fn is_enabled(&self) -> bool {
+        bit(self.twcr(), TWEN)
+    }
+
+    // SCL = clk_io / (16 + 2 * TWBR * 4^TWPS)
+    fn bit_cycles(&self) -> u64 {
+        let sram = self.sram.borrow();
+        let prescaler = 1 << (2 * (sram.get(self.twsr) & 0b11));
+        16 + 2 * sram.get(self.twbr) as u64 * prescaler
+    }
+
+    // A byte and the acknowledge take 9 SCL periods, START and STOP take 1.
+    fn schedule(&mut self, operation: Operation, cycle: u64) {
+        let bits = match operation {
+            Operation::Start | Operation::Stop | Operation::SlaveStop => 1,
+            Operation::SlaveAddress => 10,
+            _ => 9,
+        };
+        self.operation = Some((operation, cycle + bits * self.bit_cycles()));
+    }
+
+    fn complete_with(&mut self, status: u8) {
+        self.status = status;
+        self.twint = true;
+    }
+
+    fn is_own_address(&self, address: u8) -> (bool, bool) {
+        let sram = self.sram.borrow();
+        let twar = sram.get(self.twar);
+        let mask = sram.get(self.twamr) >> 1;
+        let general_call = address == 0 && bit(twar, 0);
+        let own = (address ^ (twar >> 1)) & !mask & 0x7f == 0;
+        (own || general_call, general_call)
+    }
+
+    fn device_mut(&mut self, index: Option<usize>) -> Option<&mut Box<dyn TwiDevice>> {
+        index.and_then(move |i| self.devices.get_mut(i))
+    }
+
+    // Software writes one to TWINT to start the next operation.
+    fn access_twcr(&mut self, cycle: u64) {
+        let (is_written, twcr, is_twdr_written) = {
+            let sram = self.sram.borrow();
+            (
+                sram.is_written(self.twcr),
+                sram.get(self.twcr),
+                sram.is_written(self.twdr),
+            )
+        };
+        if is_twdr_written {
+            self.twwc = !self.twint;
+        }
+        if !bit(twcr, TWEN) {
+            // Disabling the TWI terminates all ongoing transmissions.
+            if let Mode::Master(index) = self.mode {
+                if let Some(device) = self.device_mut(index) {
+                    device.stop();
+                }
+            }
+            self.mode = Mode::NotAddressed;
+            self.operation = None;
+            self.status = NO_INFO;
+            self.is_start_pending = false;
+            self.twint = false;
+            return;
+        }
+        if !is_written || !bit(twcr, TWINT) {
+            return;
+        }
+
+        self.twint = false;
+        let operation = match &self.mode {
+            Mode::Master(_) if bit(twcr, TWSTO) => Some(Operation::Stop),
+            Mode::Master(_) if bit(twcr, TWSTA) => Some(Operation::Start),
+            Mode::Master(_) => match self.status {
+                START | REPEATED_START => Some(Operation::Address(self.twdr())),
+                MT_SLA_ACK | MT_SLA_NACK | MT_DATA_ACK | MT_DATA_NACK => {
+                    Some(Operation::Transmit(self.twdr()))
+                }
+                MR_SLA_ACK | MR_DATA_ACK => Some(Operation::Receive),
+                _ => None,
+            },
+            Mode::NotAddressed if bit(twcr, TWSTA) => {
+                // START is transmitted when the bus becomes free.
+                self.is_start_pending = true;
+                None
+            }
+            Mode::SlaveReceiver(..) => match self.status {
+                SR_SLA_ACK | SR_GCALL_ACK | SR_DATA_ACK | SR_GCALL_DATA_ACK => {
+                    Some(Operation::SlaveReceive)
+                }
+                _ => None,
+            },
+            Mode::SlaveTransmitter(_) => match self.status {
+                ST_SLA_ACK | ST_DATA_ACK => Some(Operation::SlaveTransmit(self.twdr())),
+                _ => None,
+            },
+            _ => None,
+        };
+        match operation {
+            Some(operation) => self.schedule(operation, cycle),
+            None => {
+                // Switch to the not addressed slave mode. TWSTO recovers
+                // from an error condition in slave mode.
+                if let Mode::SlaveReceiver(..) | Mode::SlaveTransmitter(_) = self.mode {
+                    self.mode = Mode::NotAddressed;
+                }
+                self.status = NO_INFO;
+                self.sram.borrow_mut().set(self.twcr, twcr & !(1 << TWSTO));
+            }
+        }
+    }
+
+    fn complete(&mut self, operation: Operation, cycle: u64) {
+        let ea = bit(self.twcr(), TWEA);
+        match operation {
+            Operation::Start => {
+                let is_repeated = matches!(self.mode, Mode::Master(_));
+                if !is_repeated {
+                    self.mode = Mode::Master(None);
+                }
+                self.complete_with(if is_repeated { REPEATED_START } else { START });
+            }
+            Operation::Stop => {
+                if let Mode::Master(index) = self.mode {
+                    if let Some(device) = self.device_mut(index) {
+                        device.stop();
+                    }
+                }
+                self.mode = Mode::NotAddressed;
+                self.status = NO_INFO;
+                let twcr = self.twcr();
+                self.sram.borrow_mut().set(self.twcr, twcr & !(1 << TWSTO));
+                // STOP followed by START
+                if bit(self.twcr(), TWSTA) {
+                    self.schedule(Operation::Start, cycle);
+                }
+            }
+            Operation::Address(sla) => {
+                let read = bit(sla, 0);
+                let index = self.devices.iter().position(|d| d.address() == sla >> 1);
+                self.mode = Mode::Master(index);
+                let ack = match self.device_mut(index) {
+                    Some(device) => device.start(read),
+                    None => false,
+                };
+                self.complete_with(match (read, ack) {
+                    (false, true) => MT_SLA_ACK,
+                    (false, false) => MT_SLA_NACK,
+                    (true, true) => MR_SLA_ACK,
+                    (true, false) => MR_SLA_NACK,
+                });
+            }
+            Operation::Transmit(data) => {
+                let index = match self.mode {
+                    Mode::Master(index) => index,
+                    _ => None,
+                };
+                let ack = match self.device_mut(index) {
+                    Some(device) => device.write(data),
+                    None => false,
+                };
+                self.complete_with(if ack { MT_DATA_ACK } else { MT_DATA_NACK });
+            }
+            Operation::Receive => {
+                let index = match self.mode {
+                    Mode::Master(index) => index,
+                    _ => None,
+                };
+                // SDA is pulled up if no device sends data.
+                let data = match self.device_mut(index) {
+                    Some(device) => device.read(),
+                    None => 0xff,
+                };
+                self.sram.borrow_mut().set(self.twdr, data);
+                self.complete_with(if ea { MR_DATA_ACK } else { MR_DATA_NACK });
+            }
+            Operation::SlaveAddress => self.complete_slave_address(ea),
+            Operation::SlaveReceive => {
+                if let Mode::SlaveReceiver(data, general_call) = &mut self.mode {
+                    let general_call = *general_call;
+                    match data.pop_front() {
+                        Some(d) => {
+                            self.sram.borrow_mut().set(self.twdr, d);
+                            self.complete_with(match (general_call, ea) {
+                                (false, true) => SR_DATA_ACK,
+                                (false, false) => SR_DATA_NACK,
+                                (true, true) => SR_GCALL_DATA_ACK,
+                                (true, false) => SR_GCALL_DATA_NACK,
+                            });
+                        }
+                        None => self.schedule(Operation::SlaveStop, cycle),
+                    }
+                }
+            }
+            Operation::SlaveTransmit(data) => {
+                if let Mode::SlaveTransmitter(remaining) = self.mode {
+                    self.read_data.push(data);
+                    let remaining = remaining.saturating_sub(1);
+                    self.mode = Mode::SlaveTransmitter(remaining);
+                    let status = match (remaining, ea) {
+                        // The master does not acknowledge the last byte.
+                        (0, _) => ST_DATA_NACK,
+                        (_, true) => ST_DATA_ACK,
+                        (n, false) => {
+                            // The master reads ones for the rest.
+                            self.read_data.extend(vec![0xff; n]);
+                            ST_LAST_DATA
+                        }
+                    };
+                    self.complete_with(status);
+                }
+            }
+            Operation::SlaveStop => self.complete_with(SR_STOP),
+        }
+    }
+
+    fn complete_slave_address(&mut self, ea: bool) {
+        let transaction = match self.transactions.pop_front() {
+            Some(t) => t,
+            None => return,
+        };
+        let address = match &transaction {
+            TwiTransaction::Write(address, _) | TwiTransaction::Read(address, _) => *address,
+        };
+        let (is_matched, general_call) = self.is_own_address(address);
+        if !is_matched || !ea {
+            // Not acknowledged, the master gives up the transaction.
+            return;
+        }
+        match transaction {
+            TwiTransaction::Write(_, data) => {
+                self.mode = Mode::SlaveReceiver(data.into_iter().collect(), general_call);
+                self.complete_with(if general_call {
+                    SR_GCALL_ACK
+                } else {
+                    SR_SLA_ACK
+                });
+            }
+            TwiTransaction::Read(_, len) => {
+                self.mode = Mode::SlaveTransmitter(len);
+                self.complete_with(ST_SLA_ACK);
+            }
+        }
+    }
+
+    fn update_registers(&mut self) {
+        let mut sram = self.sram.borrow_mut();
+        let twcr = (sram.get(self.twcr) & 0b0111_0101)
+            | (self.twint as u8) << TWINT
+            | (self.twwc as u8) << TWWC;
+        sram.set(self.twcr, twcr);
+        let twsr = self.status | (sram.get(self.twsr) & 0b11);
+        sram.set(self.twsr, twsr);
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_twcr(cycle);
+
+        if let Some((operation, done)) = self.operation {
+            if cycle >= done {
+                self.operation = None;
+                self.complete(operation, done);
+            }
+        }
+
+        // The bus is free. The AVR or an external master starts a
+        // transaction.
+        if self.operation.is_none()
+            && !self.twint
+            && self.mode == Mode::NotAddressed
+            && self.is_enabled()
+        {
+            if self.is_start_pending {
+                self.is_start_pending = false;
+                self.schedule(Operation::Start, cycle);
+            } else if !self.transactions.is_empty() {
+                self.schedule(Operation::SlaveAddress, cycle);
+            }
+        }
+
+        self.update_registers();
+        self.last_cycle = cycle;
+    }
+
+    // The clock of the TWI is stopped by a sleep mode or PRR, an ongoing
+    // operation is delayed.
+    pub fn pause(&mut self, cycle: u64) {
+        let diff = cycle - self.last_cycle;
+        if let Some((_, done)) = self.operation.as_mut() {
+            *done += diff;
+        }
+        self.last_cycle = cycle;
+    }
+}
+
+impl fmt::Display for Twi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "twi =====
+    twcr: {:08b},    status: {:02x},    mode: {:?},    operation: {:?}",
+            self.twcr(),
+            self.status,
+            self.mode,
+            self.operation,
+        )
+    }
+}
@@ -0,0 +1,308 @@
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+// UCSRnA
+const RXC: u8 = 7;
+const TXC: u8 = 6;
+const UDRE: u8 = 5;
+const DOR: u8 = 3;
+const U2X: u8 = 1;
+
+// UCSRnB
+const RXEN: u8 = 4;
+const TXEN: u8 = 3;
+const UCSZ2: u8 = 2;
+const RXB8: u8 = 1;
+const TXB8: u8 = 0;
+
+// The receive buffer is a 2 level FIFO.
+const RECEIVE_BUFFER_SIZE: usize = 2;
+
+// Asynchronous USART. Bytes from the host are received one frame after
+// another at the configured baud rate, and transmitted bytes are queued
+// until the host takes them. Data of 9-bit frames has the 9th bit (RXB8n
+// or TXB8n) as bit 8.
+// TODO: Synchronous and Master SPI modes
+pub struct Usart {
+    sram: Rc<RefCell<SRAM>>,
+    last_cycle: u64,
+
+    // Bytes sent by the host and not received yet
+    rx_line: VecDeque<u16>,
+    // Received bytes with their DOR flag, the front one is in UDRn.
+    receive_buffer: VecDeque<(u16, bool)>,
+    data_overrun: bool,
+    rx_done: Option<u64>,
+
+    transmit_buffer: Option<u16>,
+    transmit_shift: Option<u16>,
+    tx_done: Option<u64>,
+    tx_line: Vec<u16>,
+    txc: bool,
+
+    udr: RegisterAddr,
+    ubrr: RegisterWordAddr,
+    ucsra: RegisterAddr,
+    ucsrb: RegisterAddr,
+    ucsrc: RegisterAddr,
+}
+
+impl Usart {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        udr: RegisterAddr,
+        ubrr: RegisterWordAddr,
+        ucsra: RegisterAddr,
+        ucsrb: RegisterAddr,
+        ucsrc: RegisterAddr,
+    ) -> Usart {
+        Usart {
+            sram: sram,
+            last_cycle: 0,
+            rx_line: VecDeque::new(),
+            receive_buffer: VecDeque::new(),
+            data_overrun: false,
+            rx_done: None,
+            transmit_buffer: None,
+            transmit_shift: None,
+            tx_done: None,
+            tx_line: vec![],
+            txc: false,
+            udr: udr,
+            ubrr: ubrr,
+            ucsra: ucsra,
+            ucsrb: ucsrb,
+            ucsrc: ucsrc,
+        }
+    }
+
+    // Bytes sent by the host are kept until they are received.
+    pub fn initialize(&mut self) {
+        self.receive_buffer.clear();
+        self.data_overrun = false;
+        self.rx_done = None;
+        self.transmit_buffer = None;
+        self.transmit_shift = None;
+        self.tx_done = None;
+        self.txc = false;
+        self.last_cycle = 0;
+    }
+
+    fn ucsra(&self) -> u8 {
+        self.sram.borrow().get(self.ucsra)
+    }
+
+    fn ucsrb(&self) -> u8 {
+        self.sram.borrow().get(self.ucsrb)
+    }
+
+    fn ucsrc(&self) -> u8 {
+        self.sram.borrow().get(self.ucsrc)
+    }
+
+    fn ubrr(&self) -> u16 {
+        self.sram.borrow().get_word(self.ubrr) & 0x0fff
+    }
+
+    // The host sends bytes to RXDn.
+    pub fn push_rx(&mut self, data: &[u8]) {
+        self.rx_line.extend(data.iter().map(|d| *d as u16));
+    }
+
+    // The host sends 9-bit frames to RXDn.
+    pub fn push_rx_9bit(&mut self, data: &[u16]) {
+        self.rx_line.extend(data);
+    }
+
+    // The host takes bytes from TXDn. The 9th bits are dropped.
+    pub fn drain_tx(&mut self) -> Vec<u8> {
+        self.tx_line.drain(..).map(|d| d as u8).collect()
+    }
+
+    // The host takes 9-bit frames from TXDn.
+    pub fn drain_tx_9bit(&mut self) -> Vec<u16> {
+        self.tx_line.drain(..).collect()
+    }
+
+    // 5 ~ 9
+    fn data_bits(&self) -> u8 {
+        match (bit(self.ucsrb(), UCSZ2), (self.ucsrc() >> 1) & 0b11) {
+            (true, _) => 9,
+            (false, ucsz) => 5 + ucsz,
+        }
+    }
+
+    // Start bit, data bits, parity bit and stop bits
+    fn frame_bits(&self) -> u64 {
+        let ucsrc = self.ucsrc();
+        let parity = if (ucsrc >> 4) & 0b11 != 0 { 1 } else { 0 };
+        let stop = if bit(ucsrc, 3) { 2 } else { 1 };
+        1 + self.data_bits() as u64 + parity + stop
+    }
+
+    // Cycles per bit in asynchronous normal and double speed mode.
+    pub fn bit_cycles(&self) -> u64 {
+        let divider = if bit(self.ucsra(), U2X) { 8 } else { 16 };
+        divider * (self.ubrr() as u64 + 1)
+    }
+
+    fn frame_cycles(&self) -> u64 {
+        self.frame_bits() * self.bit_cycles()
+    }
+
+    fn mask(&self) -> u16 {
+        (1 << self.data_bits()) - 1
+    }
+
+    // TXCn is cleared by writing one to it or by executing the interrupt.
+    fn access_ucsra(&mut self) {
+        let sram = self.sram.borrow();
+        let txc = bit(sram.get(self.ucsra), TXC);
+        if sram.is_written(self.ucsra) {
+            self.txc &= !txc;
+        } else {
+            self.txc = txc;
+        }
+    }
+
+    // UDRn is shared by the receive buffer (read) and the transmit buffer
+    // (write). TXB8n is taken with UDRn, so that it must be written first.
+    fn access_udr(&mut self, cycle: u64) {
+        let (is_read, is_written, udr) = {
+            let sram = self.sram.borrow();
+            (
+                sram.is_read(self.udr),
+                sram.is_written(self.udr),
+                sram.get(self.udr),
+            )
+        };
+        if is_written && bit(self.ucsrb(), TXEN) && self.transmit_buffer.is_none() {
+            let txb8 = bit(self.ucsrb(), TXB8) as u16;
+            self.transmit_buffer = Some((txb8 << 8 | udr as u16) & self.mask());
+            self.start_transmission(cycle);
+        }
+        if is_read {
+            self.receive_buffer.pop_front();
+        }
+    }
+
+    fn start_transmission(&mut self, cycle: u64) {
+        if self.transmit_shift.is_none() {
+            self.transmit_shift = self.transmit_buffer.take();
+            if self.transmit_shift.is_some() {
+                self.tx_done = Some(cycle + self.frame_cycles());
+            }
+        }
+    }
+
+    fn next_transmitter(&mut self, cycle: u64) {
+        if !bit(self.ucsrb(), TXEN) {
+            // The transmitter is disabled after the ongoing transmission.
+            if self.transmit_shift.is_none() {
+                self.transmit_buffer = None;
+            }
+        }
+
+        if let Some(tx_done) = self.tx_done {
+            if cycle >= tx_done {
+                self.tx_line.push(self.transmit_shift.take().unwrap());
+                self.tx_done = None;
+                self.start_transmission(tx_done);
+                if self.transmit_shift.is_none() {
+                    self.txc = true;
+                }
+            }
+        }
+    }
+
+    fn next_receiver(&mut self, cycle: u64) {
+        if !bit(self.ucsrb(), RXEN) {
+            // Disabling the receiver flushes the receive buffer.
+            self.receive_buffer.clear();
+            self.rx_done = None;
+            return;
+        }
+
+        match self.rx_done {
+            Some(rx_done) if cycle >= rx_done => {
+                let data = self.rx_line.pop_front().unwrap() & self.mask();
+                if self.receive_buffer.len() < RECEIVE_BUFFER_SIZE {
+                    self.receive_buffer.push_back((data, self.data_overrun));
+                    self.data_overrun = false;
+                } else {
+                    // The new frame is lost and DORn is set until the
+                    // receive buffer is read.
+                    self.data_overrun = true;
+                }
+                self.rx_done = None;
+                if !self.rx_line.is_empty() {
+                    self.rx_done = Some(rx_done + self.frame_cycles());
+                }
+            }
+            None if !self.rx_line.is_empty() => {
+                self.rx_done = Some(cycle + self.frame_cycles());
+            }
+            _ => (),
+        }
+    }
+
+    // Update the status flags, RXB8n and UDRn. U2Xn and MPCMn are kept.
+    // Frame errors and parity errors never happen.
+    fn update_registers(&mut self) {
+        let (rxc, dor, data) = match self.receive_buffer.front() {
+            Some((data, dor)) => (true, *dor || self.data_overrun, *data),
+            None => (false, false, 0),
+        };
+        let udre = self.transmit_buffer.is_none();
+
+        let mut sram = self.sram.borrow_mut();
+        let ucsra = (sram.get(self.ucsra) & 0b0000_0011)
+            | (rxc as u8) << RXC
+            | (self.txc as u8) << TXC
+            | (udre as u8) << UDRE
+            | (dor as u8) << DOR;
+        sram.set(self.ucsra, ucsra);
+        let ucsrb = (sram.get(self.ucsrb) & !(1 << RXB8)) | ((data >> 8) as u8 & 1) << RXB8;
+        sram.set(self.ucsrb, ucsrb);
+        sram.set(self.udr, data as u8);
+    }
+
+    pub fn next(&mut self, cycle: u64) {
+        self.access_ucsra();
+        self.access_udr(cycle);
+        self.next_transmitter(cycle);
+        self.next_receiver(cycle);
+        self.update_registers();
+        self.last_cycle = cycle;
+    }
+
+    // The clock of the USART is stopped by a sleep mode or PRR, ongoing
+    // frames are delayed.
+    pub fn pause(&mut self, cycle: u64) {
+        let diff = cycle - self.last_cycle;
+        self.tx_done = self.tx_done.map(|c| c + diff);
+        self.rx_done = self.rx_done.map(|c| c + diff);
+        self.last_cycle = cycle;
+    }
+}
+
+impl fmt::Display for Usart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "usart =====
+    ucsra: {:08b},    ucsrb: {:08b},    ucsrc: {:08b},    ubrr: {}
+    receive buffer: {:?},    transmit buffer: {:?}",
+            self.ucsra(),
+            self.ucsrb(),
+            self.ucsrc(),
+            self.ubrr(),
+            self.receive_buffer,
+            self.transmit_buffer,
+        )
+    }
+}
@@ -82,16 +82,20 @@ pub fn add_in_twos_complement_form(s: u8, k: u8) -> u8 {
 }
 
 // This calculate relative destination, - 63 < destination < pc + 64.
+// The destination is negative if it goes below address 0.
 // cf. http://kccn.konan-u.ac.jp/information/cs/cyber03/cy3_hum.htm
-pub fn add_7bits_in_twos_complement_form(pc: u32, k: u8) -> u32 {
-    // 0xFF.. is number lower 7 bits is 0, others is 1
-    pc & 0xFFFFFF80_u32 | (pc + k as u32) & 0b111_1111_u32
+pub fn add_7bits_in_twos_complement_form(pc: u32, k: u8) -> i32 {
+    // sign-extend the lower 7 bits of k
+    let k = ((k << 1) as i8 >> 1) as i32;
+    pc as i32 + k
 }
 
 // This calculate relative destination, - 2048 < k in two's complement < +2047.
-pub fn add_12bits_in_twos_complement_form(pc: u32, k: u16) -> u32 {
-    // 0xFF.. is number lower 12 bits is 0, others is 1
-    pc & 0xFFFFF000_u32 | (pc + k as u32) & 0b1111_1111_1111_u32
+// The destination is negative if it goes below address 0.
+pub fn add_12bits_in_twos_complement_form(pc: u32, k: u16) -> i32 {
+    // sign-extend the lower 12 bits of k
+    let k = ((k << 4) as i16 >> 4) as i32;
+    pc as i32 + k
 }
 
 #[test]
@@ -106,10 +110,12 @@ fn test_add_in_twos_complement_form() {
         add_7bits_in_twos_complement_form(0b1_1111_1111_u32, 0b111_1100_u8)
     );
     // 0x105 - 0x6
-    // assert_eq!(
-    //     0x105 - 0x6,
-    //     add_7bits_in_twos_complement_form(0x105_u32, 0x7a_u8)
-    // );
+    assert_eq!(
+        0x105 - 0x6,
+        add_7bits_in_twos_complement_form(0x105_u32, 0x7a_u8)
+    );
+    // 0 - 4
+    assert_eq!(-4, add_7bits_in_twos_complement_form(0u32, 0b111_1100_u8));
 
     // 100 + 3
     assert_eq!(103, add_12bits_in_twos_complement_form(100u32, 0b11_u16));
@@ -123,6 +129,16 @@ fn test_add_in_twos_complement_form() {
         16383 - 4,
         add_12bits_in_twos_complement_form(0b11_1111_1111_1111_u32, 0b1111_1111_1100_u16)
     );
+    // 0x1005 - 0x6
+    assert_eq!(
+        0x1005 - 0x6,
+        add_12bits_in_twos_complement_form(0x1005_u32, 0b1111_1111_1010_u16)
+    );
+    // 0 - 4
+    assert_eq!(
+        -4,
+        add_12bits_in_twos_complement_form(0u32, 0b1111_1111_1100_u16)
+    );
 }
 
 #[test]
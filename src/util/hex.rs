@@ -0,0 +1,177 @@
+use std::fmt;
+
+// An invalid record of Intel HEX. `line` is 1-origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexError {
+    InvalidCharacter { line: usize },
+    InvalidLength { line: usize },
+    InvalidChecksum { line: usize },
+    // A byte address beyond the memory being loaded
+    AddressOutOfRange { addr: usize },
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HexError::InvalidCharacter { line } => write!(f, "invalid character at line {}", line),
+            HexError::InvalidLength { line } => write!(f, "invalid record length at line {}", line),
+            HexError::InvalidChecksum { line } => write!(f, "invalid checksum at line {}", line),
+            HexError::AddressOutOfRange { addr } => write!(f, "address {:#x} out of range", addr),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+// Parse Intel HEX and return pairs of (byte address, data).
+// Data (00), Extended Segment Address (02) and Extended Linear Address (04)
+// records are supported, the others are ignored. Blank lines are skipped.
+//
+// Example intel Hex file's line
+// :10 | 0000 | 00 | 0C945C000C946E000C946E000C946E00 | CA
+pub fn parse_intel_hex(hex: &str) -> Result<Vec<(usize, u8)>, HexError> {
+    let mut base_addr = 0;
+    let mut data = vec![];
+    for (n, line) in hex.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let n = n + 1;
+        if !line.starts_with(':') || !line[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(HexError::InvalidCharacter { line: n });
+        }
+        if line.len() < 11 || line.len() % 2 == 0 {
+            return Err(HexError::InvalidLength { line: n });
+        }
+
+        let bytes = (1..line.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+            .collect::<Vec<u8>>();
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(HexError::InvalidLength { line: n });
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(HexError::InvalidChecksum { line: n });
+        }
+        let offset = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let record = &bytes[4..4 + len];
+
+        match bytes[3] {
+            0x00 => {
+                for (i, b) in record.iter().enumerate() {
+                    data.push((base_addr + offset + i, *b));
+                }
+            }
+            0x02 | 0x04 if len != 2 => return Err(HexError::InvalidLength { line: n }),
+            0x02 => base_addr = ((record[0] as usize) << 8 | record[1] as usize) << 4,
+            0x04 => base_addr = ((record[0] as usize) << 8 | record[1] as usize) << 16,
+            _ => (),
+        }
+    }
+    Ok(data)
+}
+
+// Format data from address 0 as Intel HEX with 16 bytes per record.
+// Extended Linear Address records are inserted for data above 64 KB.
+pub fn to_intel_hex(data: &[u8]) -> String {
+    let mut lines = vec![];
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let addr = i * 16;
+        if addr > 0 && addr % 0x10000 == 0 {
+            lines.push(hex_record(
+                0,
+                0x04,
+                &[(addr >> 24) as u8, (addr >> 16) as u8],
+            ));
+        }
+        lines.push(hex_record(addr as u16, 0x00, chunk));
+    }
+    lines.push(hex_record(0, 0x01, &[]));
+    lines.join("\n") + "\n"
+}
+
+fn hex_record(offset: u16, record_type: u8, record: &[u8]) -> String {
+    let mut bytes = vec![
+        record.len() as u8,
+        (offset >> 8) as u8,
+        offset as u8,
+        record_type,
+    ];
+    bytes.extend_from_slice(record);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    bytes.push(checksum);
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}", hex)
+}
+
+#[test]
+fn test_parse_intel_hex() {
+    let hex = ":100000000C945C000C946E000C946E000C946E00CA
+:020000021000EC
+:027FFE00040479
+:00000001FF";
+    let data = parse_intel_hex(hex).unwrap();
+    assert_eq!(data.len(), 18);
+    assert_eq!(data[0], (0x0000, 0x0c));
+    assert_eq!(data[3], (0x0003, 0x00));
+    assert_eq!(data[16], (0x17ffe, 0x04));
+}
+
+#[test]
+fn test_to_intel_hex() {
+    let data = (0..20).collect::<Vec<u8>>();
+    let hex = to_intel_hex(&data);
+    assert_eq!(
+        hex,
+        ":10000000000102030405060708090A0B0C0D0E0F78
+:0400100010111213A6
+:00000001FF
+"
+    );
+    let parsed = parse_intel_hex(&hex).unwrap();
+    assert_eq!(parsed, data.into_iter().enumerate().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_parse_invalid_intel_hex() {
+    let hex = ":100000000C945C000C946E000C946E000C946E00CA\n:020000021000EC\n";
+    assert_eq!(parse_intel_hex(hex).map(|d| d.len()), Ok(16));
+
+    // Non-hex characters
+    assert_eq!(
+        parse_intel_hex(":10000000XC945C000C946E000C946E000C946E00CA"),
+        Err(HexError::InvalidCharacter { line: 1 })
+    );
+    assert_eq!(
+        parse_intel_hex(":00000001FF\n00000001FF"),
+        Err(HexError::InvalidCharacter { line: 2 })
+    );
+    assert_eq!(
+        parse_intel_hex(":0000\u{3042}01FF"),
+        Err(HexError::InvalidCharacter { line: 1 })
+    );
+    // The length byte is longer or shorter than the record.
+    assert_eq!(
+        parse_intel_hex(":200000000C945C000C946E000C946E000C946E00BA"),
+        Err(HexError::InvalidLength { line: 1 })
+    );
+    assert_eq!(
+        parse_intel_hex(":0F0000000C945C000C946E000C946E000C946E00CB"),
+        Err(HexError::InvalidLength { line: 1 })
+    );
+    assert_eq!(
+        parse_intel_hex(":010000021FDE"),
+        Err(HexError::InvalidLength { line: 1 })
+    );
+    // Checksum
+    assert_eq!(
+        parse_intel_hex(":100000000C945C000C946E000C946E000C946E00CB"),
+        Err(HexError::InvalidChecksum { line: 1 })
+    );
+}
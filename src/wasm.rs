@@ -38,8 +38,10 @@ impl AvrMcu {
         }
     }
 
-    pub fn program(&self, hex: String) {
-        self.avr.program(hex)
+    pub fn program(&self, hex: String) -> Result<(), JsValue> {
+        self.avr
+            .program(hex)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
     pub fn initialize(&mut self) {
@@ -50,7 +52,7 @@ impl AvrMcu {
         from_vec_bool_to_string(&self.avr.get_pins())
     }
 
-    pub fn set_pins(&self, pins: String) {
+    pub fn set_pins(&mut self, pins: String) {
         self.avr.set_pins(from_string_to_vec_bool(&pins));
     }
 }
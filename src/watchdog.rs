@@ -0,0 +1,182 @@
+use super::clock::*;
+use super::sram::*;
+use super::util::bit::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// WDTCSR
+const WDIF: u8 = 7;
+const WDIE: u8 = 6;
+const WDP3: u8 = 5;
+const WDCE: u8 = 4;
+const WDE: u8 = 3;
+
+// MCUSR
+const WDRF: u8 = 3;
+
+// The watchdog is clocked by the 128 kHz oscillator.
+const OSCILLATOR_FREQUENCY: u64 = 128_000;
+
+// WDE and WDP3:0 can be changed within 4 cycles after WDCE and WDE are
+// written to one.
+const CHANGE_TIMEOUT: u64 = 4;
+
+pub struct Watchdog {
+    sram: Rc<RefCell<SRAM>>,
+    clock: Rc<RefCell<Clock>>,
+    // The oscillator cycle when the watchdog timer was reset
+    start: u64,
+    changeable_cycle: Option<u64>,
+    // WDTCSR accepted by the timed sequence, except WDIF
+    control: u8,
+    wdif: bool,
+    // The WDTON fuse forces the system reset mode.
+    is_always_on: bool,
+
+    wdtcsr: RegisterAddr,
+    mcusr: RegisterAddr,
+}
+
+impl Watchdog {
+    pub fn new(
+        sram: Rc<RefCell<SRAM>>,
+        clock: Rc<RefCell<Clock>>,
+        wdtcsr: RegisterAddr,
+        mcusr: RegisterAddr,
+    ) -> Watchdog {
+        Watchdog {
+            sram: sram,
+            clock: clock,
+            start: 0,
+            changeable_cycle: None,
+            control: 0,
+            wdif: false,
+            is_always_on: false,
+            wdtcsr: wdtcsr,
+            mcusr: mcusr,
+        }
+    }
+
+    pub fn initialize(&mut self, cycle: u64, is_always_on: bool) {
+        self.start = self.oscillator_cycles(cycle);
+        self.changeable_cycle = None;
+        self.control = 0;
+        self.wdif = false;
+        self.is_always_on = is_always_on;
+        self.update_registers();
+    }
+
+    fn oscillator_cycles(&self, cycle: u64) -> u64 {
+        self.clock.borrow().ticks(cycle, OSCILLATOR_FREQUENCY)
+    }
+
+    // WDR
+    pub fn reset_timer(&mut self, cycle: u64) {
+        self.start = self.oscillator_cycles(cycle);
+    }
+
+    // Time-out of 2K ~ 1024K oscillator cycles selected by WDP3:0
+    fn timeout_cycles(&self) -> u64 {
+        let wdp = (bit(self.control, WDP3) as u8) << 3 | self.control & 0b111;
+        2048 << wdp.min(9)
+    }
+
+    fn is_interrupt_mode(&self) -> bool {
+        bit(self.control, WDIE) && !self.is_always_on
+    }
+
+    fn is_reset_mode(&self) -> bool {
+        bit(self.control, WDE) || self.is_always_on
+    }
+
+    fn access_wdtcsr(&mut self, cycle: u64) {
+        let (is_written, wdtcsr) = {
+            let sram = self.sram.borrow();
+            (sram.is_written(self.wdtcsr), sram.get(self.wdtcsr))
+        };
+        if !is_written {
+            // Executing the interrupt clears WDIF, and also clears WDIE in
+            // the interrupt and system reset mode.
+            if self.wdif && !bit(wdtcsr, WDIF) && bit(self.control, WDE) {
+                self.control &= !(1 << WDIE);
+            }
+            self.wdif = bit(wdtcsr, WDIF);
+            return;
+        }
+
+        // WDIF is cleared by writing one to it.
+        self.wdif &= !bit(wdtcsr, WDIF);
+        let is_changeable = match self.changeable_cycle {
+            Some(c) => cycle - c <= CHANGE_TIMEOUT,
+            None => false,
+        };
+        if is_changeable {
+            self.control = wdtcsr & 0b0110_1111;
+            self.changeable_cycle = None;
+        } else {
+            // WDIE can be changed and WDE can be set at any time.
+            let wde = bit(self.control, WDE) || bit(wdtcsr, WDE);
+            self.control =
+                (self.control & 0b0010_0111) | (wdtcsr & (1 << WDIE)) | (wde as u8) << WDE;
+            if bit(wdtcsr, WDCE) && bit(wdtcsr, WDE) {
+                self.changeable_cycle = Some(cycle);
+                self.control |= 1 << WDCE;
+            }
+        }
+    }
+
+    fn update_registers(&mut self) {
+        // WDE is overridden by WDRF.
+        if bit(self.sram.borrow().get(self.mcusr), WDRF) {
+            self.control |= 1 << WDE;
+        }
+        let wdtcsr = self.control | (self.wdif as u8) << WDIF;
+        self.sram.borrow_mut().set(self.wdtcsr, wdtcsr);
+    }
+
+    // The watchdog keeps running in all sleep modes. Returns true if a
+    // system reset is requested.
+    pub fn next(&mut self, cycle: u64) -> bool {
+        self.access_wdtcsr(cycle);
+        if let Some(c) = self.changeable_cycle {
+            if cycle - c > CHANGE_TIMEOUT {
+                self.changeable_cycle = None;
+                self.control &= !(1 << WDCE);
+            }
+        }
+        self.update_registers();
+
+        let oscillator_cycles = self.oscillator_cycles(cycle);
+        if !self.is_interrupt_mode() && !self.is_reset_mode() {
+            self.start = oscillator_cycles;
+            return false;
+        }
+        if oscillator_cycles - self.start < self.timeout_cycles() {
+            return false;
+        }
+        self.start = oscillator_cycles;
+        // In the interrupt and system reset mode, a time-out while the
+        // interrupt is still pending resets the MCU.
+        if self.is_interrupt_mode() && !(self.wdif && self.is_reset_mode()) {
+            self.wdif = true;
+            self.update_registers();
+            false
+        } else {
+            true
+        }
+    }
+}
+
+impl fmt::Display for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "watchdog =====
+    wdtcsr: {:08b},    timeout: {},    mcusr: {:08b}",
+            self.control | (self.wdif as u8) << WDIF,
+            self.timeout_cycles(),
+            self.sram.borrow().get(self.mcusr),
+        )
+    }
+}
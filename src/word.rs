@@ -21,6 +21,24 @@ impl Word {
         )
     }
 
+    // d, r
+    // there is a 16 addr offset, d_addr, r_addr = {16, ..., 31}
+    pub fn operand44_16(&self) -> (usize, usize) {
+        (
+            (operand(self.0, 0b0000000011110000) + 16) as usize,
+            (operand(self.0, 0b0000000000001111) + 16) as usize,
+        )
+    }
+
+    // d, r
+    // there is a 16 addr offset, d_addr, r_addr = {16, ..., 23}
+    pub fn operand33(&self) -> (usize, usize) {
+        (
+            (operand(self.0, 0b0000000001110000) + 16) as usize,
+            (operand(self.0, 0b0000000000000111) + 16) as usize,
+        )
+    }
+
     // I/O Register starts from 0x20(0d32), so there is offset.
     pub fn operand65(&self) -> (usize, usize) {
         (
@@ -45,9 +63,18 @@ impl Word {
         )
     }
 
-    pub fn operand53(&self) -> (u8, u8) {
+    // I/O Register starts from 0x20(0d32), so there is offset.
+    pub fn operand53(&self) -> (usize, u8) {
+        (
+            (operand(self.0, 0b0000000011111000) + 0x20) as usize,
+            operand(self.0, 0b0000000000000111) as u8,
+        )
+    }
+
+    // k, s
+    pub fn operand73(&self) -> (u8, u8) {
         (
-            operand(self.0, 0b0000000011111000) as u8,
+            operand(self.0, 0b0000001111111000) as u8,
             operand(self.0, 0b0000000000000111) as u8,
         )
     }
@@ -60,8 +87,18 @@ impl Word {
         operand(self.0, 0b0000000111110000) as usize
     }
 
-    pub fn operand10(&self) -> u16 {
-        operand(self.0, 0b0000_0011_1111_1111)
+    // q, displacement of LDD/STD
+    pub fn operand6(&self) -> u8 {
+        operand(self.0, 0b0010_1100_0000_0111) as u8
+    }
+
+    pub fn operand3(&self) -> u8 {
+        operand(self.0, 0b0000000000000111) as u8
+    }
+
+    // s, SREG bit of BSET/BCLR
+    pub fn operand_s(&self) -> u8 {
+        operand(self.0, 0b0000_0000_0111_0000) as u8
     }
 
     pub fn operand12(&self) -> u16 {
@@ -77,6 +114,26 @@ impl Word {
 fn test_word() {
     let w = Word(0b1001_0100_0000_1110);
     assert_eq!(w.operand22(Word(0b0000_0001_1100_1100)), 0b111001100);
+
+    // muls r21, r30
+    let w = Word(0b0000_0010_0101_1110);
+    assert_eq!(w.operand44_16(), (21, 30));
+
+    // fmulsu r23, r16
+    let w = Word(0b0000_0011_1111_1000);
+    assert_eq!(w.operand33(), (23, 16));
+
+    // bclr 6 (clt)
+    let w = Word(0b1001_0100_1110_1000);
+    assert_eq!(w.operand_s(), 6);
+
+    // ldd r24, Y+q
+    for q in 0..64u16 {
+        let q_bits = (q & 0b10_0000) << 8 | (q & 0b1_1000) << 7 | (q & 0b111);
+        let w = Word(0b1000_0001_1000_1000 | q_bits);
+        assert_eq!(w.operand6(), q as u8);
+        assert_eq!(w.operand5(), 24);
+    }
 }
 
 pub struct WordIter {
@@ -0,0 +1,65 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+use std::time::Duration;
+
+mod common;
+use common::*;
+
+// ADMUX = AVCC reference, left adjusted, ADC0. ADC clock = clk_io / 128.
+// Convert repeatedly and output ADCH to PORTD like `analogRead(A0) >> 2`.
+const ANALOG_READ_HEX: &str = ":100000000FEF0AB900E600937C0007EC00937A003A
+:1000100010917A0016FDFCCF409178005091790044
+:040020005BB9F3CF06
+:00000001FF";
+
+#[test]
+fn analog_read() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(ANALOG_READ_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    // 3.3 V / 5 V * 1024 = 675
+    avr.set_adc_voltage(0, 3.3);
+    for _ in 0..5_000 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), (675 >> 2) as u8);
+
+    // 1 V / 5 V * 1024 = 204
+    avr.set_adc_voltage(0, 1.0);
+    for _ in 0..5_000 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), (204 >> 2) as u8);
+}
+
+// ADMUX = AVCC reference, ADC0. Free running with the ADC interrupt and
+// clk_io / 128. The interrupts are counted on PORTB.
+const FREE_RUNNING_HEX: &str = ":0200000033C00B
+:0200540013C0D7
+:080068000FEF04B900E400935E
+:100070007C000FEE00937A007894FFCF439545B94A
+:020080001895D1
+:00000001FF";
+
+#[test]
+fn free_running_interrupt() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(FREE_RUNNING_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    // 20,000 cycles at 16 MHz. The first conversion takes 25 * 128 cycles
+    // and the others 13.5 * 128 cycles. ADIF is cleared by executing the
+    // interrupt, so that it is taken once per conversion.
+    while avr.time() < Duration::from_micros(1_250) {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 10);
+}
+
+#[test]
+#[should_panic(expected = "ADC channel")]
+fn invalid_channel() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.set_adc_voltage(8, 1.0);
+}
@@ -0,0 +1,80 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// The analog comparator interrupt on rising output edges. The interrupts
+// are counted on PORTB.
+const INTERRUPT_HEX: &str = ":020000001FC01F
+:0C0040000FE304B90BE000BF7894FFCF81
+:02005C0001C0E1
+:06006000439545B9189517
+:00000001FF";
+
+// The bandgap reference is compared with ADC2 selected by ACME and MUX2:0,
+// and ACO is output to PB5.
+const MULTIPLEXER_HEX: &str = ":100000000FE304B900E400BF00937B0002E000931B
+:0A0010007C0000B7007205B9FCCFB8
+:00000001FF";
+
+// Timer1 captures rising edges of ACO selected by ACIC. PB0 is set when
+// ICF1 is set.
+const INPUT_CAPTURE_HEX: &str = ":100000000FE304B901E40093810004E000BFB59B55
+:08001000FECF01E005B9FFCFAE
+:00000001FF";
+
+fn run(avr: &mut ATmega328P) {
+    for _ in 0..100 {
+        avr.next();
+    }
+}
+
+#[test]
+fn interrupt() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(INTERRUPT_HEX.to_string()).unwrap();
+    avr.initialize();
+    avr.set_ain_voltage(1, 1.0);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 0);
+
+    avr.set_ain_voltage(0, 2.0);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 1);
+    avr.set_ain_voltage(0, 0.5);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 1);
+    avr.set_ain_voltage(0, 1.5);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 2);
+}
+
+#[test]
+fn multiplexer() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(MULTIPLEXER_HEX.to_string()).unwrap();
+    avr.initialize();
+    avr.set_adc_voltage(2, 1.0);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 0b0010_0000);
+
+    // 1.1 V < ADC2
+    avr.set_adc_voltage(2, 1.2);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 0);
+}
+
+#[test]
+fn input_capture() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(INPUT_CAPTURE_HEX.to_string()).unwrap();
+    avr.initialize();
+    avr.set_ain_voltage(1, 2.5);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 0);
+
+    avr.set_ain_voltage(0, 3.0);
+    run(&mut avr);
+    assert_eq!(portb(&avr), 1);
+}
@@ -0,0 +1,154 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+use std::fs;
+
+mod common;
+use common::*;
+
+const SAMPLE_FILE_NAME: &str =
+    "hex/arduino_ide/led_flashing/led_flashing.ino.with_bootloader.standard.hex";
+
+// Arduino Uno fuses. BOOTRST is programmed and the boot loader section is
+// 256 words from 0x3f00.
+const ARDUINO_UNO_FUSES: Fuses = Fuses {
+    low: 0xff,
+    high: 0xde,
+    extended: 0xfd,
+};
+
+// Outputs 0x3f to PORTB, followed by a record at 0x10000 beyond the flash.
+const OUT_OF_RANGE_HEX: &str = ":080000000FE304B905B9FFCFBD
+:020000040001F9
+:02000000FFCF30
+:00000001FF";
+
+#[test]
+fn start_sketch_from_bootloader() {
+    let hex = fs::read_to_string(SAMPLE_FILE_NAME).unwrap();
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(hex).unwrap();
+    avr.set_fuses(ARDUINO_UNO_FUSES);
+    avr.initialize();
+
+    // Optiboot jumps to the sketch after power-on reset, then the sketch
+    // turns on the LED (pin 19, PB5).
+    let mut is_led_on = false;
+    for _ in 0..100_000 {
+        avr.next();
+        if avr.get_pins()[18] {
+            is_led_on = true;
+            break;
+        }
+    }
+    assert!(is_led_on);
+}
+
+// The same routine at 0x0040 in the application section and at 0x3800 in
+// the boot loader section. If PC0 is high, BLB01 is programmed first. Then
+// the page at 0x0100 is erased, filled with 0x1234, 0x5678 and written, and
+// the RWW section is re-enabled. PORTB outputs whether RWWSB was set after
+// the write (bit 0) and cleared by RWWSRE (bit 1), and whether the words
+// read back by LPM are 0x5678 (bit 2), 0xffff (bit 3) and 0x12xx (bit 4).
+// PORTD outputs the low byte of the first word.
+const SPM_HEX: &str = ":020000003FC0FF
+:100080000FEF04B90AB900000000309B04C00BEF69
+:10009000002E09E035D0E0E0F2E003E031D033D0CB
+:1000A00004E3002E02E1102E01E02AD0E2E008E78E
+:1000B000002E06E5102E01E023D0E0E005E020D080
+:1000C00022D047B701E11CD057B765917591859152
+:1000D0009591A591B591222746FD216056FF22609A
+:1000E000883719F4963509F42460AF3F19F4BF3FFF
+:1000F00009F42860723109F4206125B96BB9FFCF8A
+:0E01000007BFE895089507B700FDFDCF0895ED
+:107000000FEF04B90AB900000000309B04C00BEF79
+:10701000002E09E035D0E0E0F2E003E031D033D0DB
+:1070200004E3002E02E1102E01E02AD0E2E008E79E
+:10703000002E06E5102E01E023D0E0E005E020D090
+:1070400022D047B701E11CD057B765917591859162
+:107050009591A591B591222746FD216056FF2260AA
+:10706000883719F4963509F42460AF3F19F4BF3F0F
+:1070700009F42860723109F4206125B96BB9FFCF9A
+:0E70800007BFE895089507B700FDFDCF0895FE
+:00000001FF";
+
+// BOOTRST is programmed and the boot loader section is 2048 words from
+// 0x3800.
+const BOOT_2048_FUSES: Fuses = Fuses {
+    low: 0xff,
+    high: 0xd8,
+    extended: 0xfd,
+};
+
+fn run_spm(fuses: Option<Fuses>, blb01: bool) -> (u8, u8) {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(SPM_HEX.to_string()).unwrap();
+    if let Some(fuses) = fuses {
+        avr.set_fuses(fuses);
+    }
+    // PC0 is pin 23.
+    let mut pins = vec![false; 28];
+    pins[22] = blb01;
+    avr.set_pins(pins);
+    avr.initialize();
+
+    for _ in 0..200_000 {
+        avr.next();
+    }
+    (portb(&avr), portd(&avr))
+}
+
+#[test]
+fn spm_from_boot_section() {
+    assert_eq!(run_spm(Some(BOOT_2048_FUSES), false), (0b11111, 0x34));
+}
+
+#[test]
+fn spm_from_application_section() {
+    // SPM is ignored, so that the page keeps the zeros of the unused flash.
+    assert_eq!(run_spm(None, false), (0b00010, 0));
+}
+
+#[test]
+fn spm_to_locked_section() {
+    // BLB01 forbids SPM to the application section.
+    assert_eq!(run_spm(Some(BOOT_2048_FUSES), true), (0b00010, 0));
+}
+
+#[test]
+fn image_out_of_range() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    assert_eq!(
+        avr.program(OUT_OF_RANGE_HEX.to_string()),
+        Err(HexError::AddressOutOfRange { addr: 0x10000 })
+    );
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    // Nothing is loaded.
+    assert_eq!(portb(&avr), 0);
+}
+
+// Timer0 (clk/1024) and Timer1 (clk/8) are started at 0x3800 in the boot
+// loader section, then the NRWW page at 0x3f80 is erased. TCNT1H is output
+// to PORTD and TCNT0 >> 2 to PORTB.
+const HALT_TIMER_HEX: &str = ":107000000FEF04B90AB905E005BD02E00093810065
+:10701000E0E0FFE703E007BFE895009184001091EE
+:0E70200085001BB906B50695069505B9FFCF8C
+:00000001FF";
+
+#[test]
+fn timers_keep_running_while_halted() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(HALT_TIMER_HEX.to_string()).unwrap();
+    avr.set_fuses(BOOT_2048_FUSES);
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+
+    // The CPU is halted for 4.5 ms (72,000 cycles at 16 MHz), so that
+    // TCNT1 is about 9,000 and TCNT0 is about 70.
+    assert_eq!(portd(&avr), 0x23);
+    assert_eq!(portb(&avr), 17);
+}
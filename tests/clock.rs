@@ -0,0 +1,90 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+use std::time::Duration;
+
+// NOP, then RJMP to itself.
+const LOOP_HEX: &str = ":040000000000FFCF2E
+:00000001FF";
+
+// The timed sequence sets CLKPS3:0 to 0b0010 (division factor 4). Writing
+// 0b0001 afterwards without CLKPCE is ignored.
+const CLKPR_HEX: &str = ":1000000000E80093610002E00093610001E00093CA
+:040010006100FFCFBD
+:00000001FF";
+
+// UBRR0 = 103
+const BAUD_HEX: &str = ":0800000007E60093C400FFCFE6
+:00000001FF";
+
+#[test]
+fn default_clock() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(LOOP_HEX.to_string()).unwrap();
+    avr.initialize();
+    assert_eq!(avr.frequency(), 16_000_000);
+
+    // 1 + 999 * 2 cycles of 62.5 ns
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    assert_eq!(avr.time(), Duration::from_nanos(124_937));
+}
+
+#[test]
+fn clock_source() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(LOOP_HEX.to_string()).unwrap();
+
+    avr.set_crystal_frequency(20_000_000);
+    avr.initialize();
+    assert_eq!(avr.frequency(), 20_000_000);
+
+    // Factory default: the internal 8 MHz RC oscillator and CKDIV8
+    avr.set_fuses(Fuses {
+        low: 0x62,
+        high: 0xd9,
+        extended: 0xff,
+    });
+    avr.initialize();
+    assert_eq!(avr.frequency(), 1_000_000);
+
+    // The internal 128 kHz RC oscillator
+    avr.set_fuses(Fuses {
+        low: 0xe3,
+        high: 0xd9,
+        extended: 0xff,
+    });
+    avr.initialize();
+    assert_eq!(avr.frequency(), 128_000);
+}
+
+#[test]
+fn clock_prescaler() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(CLKPR_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(avr.frequency(), 4_000_000);
+
+    // 200 cycles of 250 ns
+    let time = avr.time();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(avr.time() - time, Duration::from_micros(50));
+}
+
+#[test]
+fn usart_baud_rate() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(BAUD_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..10 {
+        avr.next();
+    }
+    // 16 MHz / (16 * (103 + 1))
+    let baud = avr.usart_baud_rate();
+    assert!((9_615.0..9_616.0).contains(&baud), "baud: {}", baud);
+}
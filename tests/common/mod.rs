@@ -0,0 +1,30 @@
+// Helpers shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+// PB0 ~ PB5 are pins 14 ~ 19.
+pub fn portb(avr: &ATmega328P) -> u8 {
+    let pins = avr.get_pins();
+    (0..6).fold(0, |acc, n| acc | (pins[13 + n] as u8) << n)
+}
+
+// PD0 ~ PD4 are pins 2 ~ 6, PD5 ~ PD7 are pins 11 ~ 13.
+pub fn portd(avr: &ATmega328P) -> u8 {
+    let pins = avr.get_pins();
+    [1, 2, 3, 4, 5, 10, 11, 12]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (n, &p)| acc | (pins[p] as u8) << n)
+}
+
+// Drive pin `p` (0-origin), or release it with `None`, and run for a while.
+pub fn set_pin(avr: &mut ATmega328P, p: usize, state: Option<bool>) {
+    let mut states = vec![None; 28];
+    states[p] = state;
+    avr.set_pin_states(states);
+    for _ in 0..100 {
+        avr.next();
+    }
+}
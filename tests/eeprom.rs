@@ -0,0 +1,58 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// Read the byte at EEPROM address 0, increment it, write it back and
+// output it to PORTD after the write is completed.
+const BOOT_COUNTER_HEX: &str = ":100000000FEF0AB900E002BD01BDF89A40B5439573
+:0E00100040BDFA9AF99AF999FECF4BB9FFCF8D
+:00000001FF";
+
+const EEP: &str = ":020000000503F6
+:00000001FF";
+
+// A byte at 0x400 beyond the 1 KB EEPROM
+const OUT_OF_RANGE_EEP: &str = ":020000000503F6
+:010400005AA1
+:00000001FF";
+
+#[test]
+fn keep_data_across_resets() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(BOOT_COUNTER_HEX.to_string()).unwrap();
+    avr.program_eeprom(EEP.to_string()).unwrap();
+    avr.initialize();
+
+    // Erase and write takes 3.4 ms (54,400 cycles at 16 MHz).
+    for _ in 0..20_000 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 0);
+    for _ in 0..30_000 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 6);
+
+    avr.initialize();
+    for _ in 0..50_000 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 7);
+
+    let eep = avr.eeprom_hex();
+    assert!(eep.starts_with(":100000000703FFFF"));
+    assert_eq!(eep.lines().count(), 1024 / 16 + 1);
+}
+
+#[test]
+fn image_out_of_range() {
+    let avr = ATmega328P::new(Package::PDIP28);
+    assert_eq!(
+        avr.program_eeprom(OUT_OF_RANGE_EEP.to_string()),
+        Err(HexError::AddressOutOfRange { addr: 0x400 })
+    );
+    // Nothing is loaded.
+    assert!(avr.eeprom_hex().starts_with(":10000000FFFFFFFF"));
+}
@@ -0,0 +1,108 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// INT0 on the falling edge. The interrupts are counted on PORTB.
+const INT0_HEX: &str = ":020000001FC01F
+:020004002DC00D
+:100040000FE304B902E00093690001E00DBB78946E
+:02005000FFCFE0
+:06006000439545B9189517
+:00000001FF";
+
+// PCINT8 (PC0) enabled by PCMSK1. The interrupts are counted on PORTB.
+const PCINT_HEX: &str = ":020000001FC01F
+:0200100027C007
+:100040000FE304B901E000936C0002E00093680044
+:040050007894FFCFD2
+:06006000439545B9189517
+:00000001FF";
+
+// INT0, INT1 and the pin changes of PC0, PD2 and PD3 set their flags
+// without enabling the interrupts. After both PCIF1 and PCIF2 are set,
+// `sbi EIFR, INTF0`, `cbi PCIFR, PCIF1` and `sbi PCIFR, PCIF2` are executed
+// and EIFR | PCIFR << 2 is output to PORTB.
+const CLEAR_FLAGS_HEX: &str = ":100000000FE304B905E00093690001E000936C0080
+:100010000CE000936D000BB30630E9F7E09AD99835
+:10002000DA9A0CB31BB3110F110F012B05B9FFCFD7
+:00000001FF";
+
+// INT0 on the low level is enabled while I is cleared, and EIFR is output
+// to PORTB.
+const LOW_LEVEL_HEX: &str = ":0E0000000FE304B901E00DBB0CB305B9FDCF51
+:00000001FF";
+
+#[test]
+fn int0_falling_edge() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(INT0_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+
+    // PD2 is pin 4.
+    set_pin(&mut avr, 3, Some(true));
+    assert_eq!(portb(&avr), 0);
+    set_pin(&mut avr, 3, Some(false));
+    assert_eq!(portb(&avr), 1);
+    set_pin(&mut avr, 3, Some(true));
+    set_pin(&mut avr, 3, Some(false));
+    assert_eq!(portb(&avr), 2);
+}
+
+#[test]
+fn pin_change() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PCINT_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+
+    // PC0 is pin 23, PC1 is not enabled by PCMSK1.
+    set_pin(&mut avr, 22, Some(true));
+    assert_eq!(portb(&avr), 1);
+    set_pin(&mut avr, 22, Some(false));
+    assert_eq!(portb(&avr), 2);
+    set_pin(&mut avr, 23, Some(true));
+    set_pin(&mut avr, 23, Some(false));
+    assert_eq!(portb(&avr), 2);
+}
+
+#[test]
+fn clear_flags() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(CLEAR_FLAGS_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+
+    // PD2, PD3 and PC0 are pins 4, 5 and 23.
+    let mut pins = vec![false; 28];
+    pins[3] = true;
+    pins[4] = true;
+    pins[22] = true;
+    avr.set_pins(pins);
+    for _ in 0..100 {
+        avr.next();
+    }
+    // INTF1 and PCIF1 are kept.
+    assert_eq!(portb(&avr), 0b1010);
+}
+
+#[test]
+fn low_level_keeps_intf0_cleared() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(LOW_LEVEL_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    // PD2 is pin 4.
+    set_pin(&mut avr, 3, Some(false));
+    assert_eq!(portb(&avr), 0);
+    set_pin(&mut avr, 3, Some(true));
+    assert_eq!(portb(&avr), 0);
+}
@@ -0,0 +1,32 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// INT0 is kept requested by the low level of PD2 after SEI, and the main
+// routine is a sequence of `inc r17`. Each ISR appends r17 to r21 with
+// 2 bits per entry and disables INT0 on its 3rd entry. r21 is output to
+// PORTB, I in SREG of the main routine to PD7 and I in the ISR to PD6.
+const LOW_LEVEL_HEX: &str = ":060000000C94340047C01F
+:080068000FEF04B90AB901E031
+:100070000DBB7894139513951395139513951395BC
+:100080001395139555B90FB7007860786695062BD0
+:100090000BB9FFCF6FB74395433011F400E00DBBB0
+:0800A000550F550F512B189567
+:00000001FF";
+
+#[test]
+fn one_instruction_after_sei_and_reti() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(LOW_LEVEL_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..100 {
+        avr.next();
+    }
+    // One `inc r17` is executed after SEI and after each RETI.
+    assert_eq!(portb(&avr), 0b01_10_11);
+    // I is cleared on entry and set by RETI.
+    assert_eq!(portd(&avr), 0b1000_0000);
+}
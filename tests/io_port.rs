@@ -0,0 +1,159 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// PD2 is pulled up and PIND is output to PORTB.
+const PULL_UP_HEX: &str = ":0E0000000FE304B904E00BB909B105B9FDCF57
+:00000001FF";
+
+// Same as PULL_UP_HEX, but PUD in MCUCR disables the pull-ups.
+const PULL_UP_DISABLE_HEX: &str = ":1000000000E105BF0FE304B904E00BB909B105B97C
+:02001000FDCF22
+:00000001FF";
+
+// Sets PORTB0 and reads PINB0 twice, right after OUT and after a NOP. The
+// two values are output to PD0 and PD1.
+const SYNCHRONIZER_HEX: &str = ":1000000001E004B90FEF0AB901E005B913B100002E
+:0A00100023B1220F122B1BB9FFCF02
+:00000001FF";
+
+#[test]
+fn pull_up() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PULL_UP_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0b0000_0100);
+
+    // PD2 is pin 4.
+    set_pin(&mut avr, 3, Some(false));
+    assert_eq!(portb(&avr), 0);
+    set_pin(&mut avr, 3, None);
+    assert_eq!(portb(&avr), 0b0000_0100);
+}
+
+#[test]
+fn pull_up_disable() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PULL_UP_DISABLE_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0);
+
+    // A floating pin keeps its last level.
+    set_pin(&mut avr, 3, Some(true));
+    assert_eq!(portb(&avr), 0b0000_0100);
+    set_pin(&mut avr, 3, None);
+    assert_eq!(portb(&avr), 0b0000_0100);
+    set_pin(&mut avr, 3, Some(false));
+    set_pin(&mut avr, 3, None);
+    assert_eq!(portb(&avr), 0);
+}
+
+#[test]
+fn contention() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PULL_UP_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert!(avr.get_contentions().iter().all(|&c| !c));
+
+    // PB2 outputs high and PB0 outputs low.
+    set_pin(&mut avr, 15, Some(true));
+    assert!(avr.get_contentions().iter().all(|&c| !c));
+    set_pin(&mut avr, 13, Some(true));
+    let contentions = avr.get_contentions();
+    assert!(contentions[13]);
+    assert_eq!(contentions.iter().filter(|&&c| c).count(), 1);
+    // The output driver wins.
+    assert_eq!(portb(&avr), 0b0000_0100);
+}
+
+#[test]
+fn synchronizer() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(SYNCHRONIZER_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 0b0000_0010);
+}
+
+// Reads PINB0 by four INs in a row and outputs the values to PD0 ~ PD3.
+const INPUT_LATENCY_HEX: &str = ":100000000FE00AB903B113B123B133B1017011701C
+:1000100021703170110F220F220F330F330F330F66
+:0A002000012B022B032B0BB9FFCFBD
+:00000001FF";
+
+// PORTB = 0x01, then writes 0x06 to PINB and SBI PINB0.
+const TOGGLE_HEX: &str = ":100000000FE304B901E005B906E003B9189AFFCF80
+:00000001FF";
+
+// Toggles PB0 by SBI PINB0 every 4 cycles.
+const BLINK_HEX: &str = ":0800000001E004B9189AFECFDB
+:00000001FF";
+
+#[test]
+fn input_latency() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(INPUT_LATENCY_HEX.to_string()).unwrap();
+    avr.initialize();
+    avr.next();
+    avr.next();
+
+    // PB0 (pin 14) driven before the first IN is read 2 cycles later.
+    let mut states = vec![None; 28];
+    states[13] = Some(true);
+    avr.set_pin_states(states);
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 0b1100);
+}
+
+#[test]
+fn toggle() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(TOGGLE_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0b0000_0110);
+}
+
+#[test]
+fn pin_events() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(BLINK_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+
+    // PB0 is pin 14.
+    let events = avr.take_pin_events();
+    assert!(events.len() > 20);
+    for (n, e) in events.iter().enumerate() {
+        assert_eq!(e.pin, 13);
+        assert_eq!(e.level, n % 2 == 0);
+    }
+    for w in events.windows(2) {
+        assert_eq!(w[1].cycle - w[0].cycle, 4);
+    }
+
+    // PD2 is pin 4.
+    set_pin(&mut avr, 3, Some(true));
+    let events = avr.take_pin_events();
+    assert!(events.iter().any(|e| e.pin == 3 && e.level));
+    assert!(events.iter().all(|e| e.pin == 3 || e.pin == 13));
+}
@@ -0,0 +1,130 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// SMCR is read from PC3 ~ PC0 and PRTIM0 is set if PC4 is high. Timer0
+// runs without prescaling with TOIE0, the ADC is enabled with ADIE and
+// INT0 is enabled on the low level before SLEEP. The ISRs of TIMER0 OVF,
+// ADC and INT0 set PB0, PB1 and PB2.
+const SLEEP_HEX: &str = ":060000000C9434004EC018
+:020040002CC0D2
+:0200540024C0C6
+:080068000FE304B900000000E1
+:100070000000000016B114FF03C000E2009364000A
+:1000800008E800937A0001E00DBB00936E0005BD07
+:100090001F7013BF78948895FFCF289A1895299AD6
+:0A00A000189500E00DBB2A9A189590
+:00000001FF";
+
+// Drive PC4 ~ PC0 (pins 27 ~ 23) by `pinc` and PD2 (pin 4) high, and run
+// until SLEEP has been executed.
+fn start(pinc: u8) -> ATmega328P {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(SLEEP_HEX.to_string()).unwrap();
+    avr.initialize();
+    let mut states = vec![None; 28];
+    for n in 0..5 {
+        states[22 + n] = Some(pinc >> n & 1 == 1);
+    }
+    states[3] = Some(true);
+    avr.set_pin_states(states);
+    for _ in 0..30 {
+        avr.next();
+    }
+    avr
+}
+
+// Cycles at 16 MHz taken by the step which wakes the MCU up
+fn wake_up_cycles(avr: &mut ATmega328P) -> u64 {
+    let mut before = avr.time();
+    while avr.state() != State::Running {
+        before = avr.time();
+        avr.next();
+    }
+    let cycles = ((avr.time() - before).as_nanos() as u64 * 16 + 500) / 1000;
+    // Execute the ISR.
+    for _ in 0..10 {
+        avr.next();
+    }
+    cycles
+}
+
+#[test]
+fn sleep_modes() {
+    // SM2:0 and SE
+    let cases = [
+        (0b0001, State::Sleeping(SleepMode::Idle)),
+        (0b0011, State::Sleeping(SleepMode::ADCNoiseReduction)),
+        (0b0101, State::Sleeping(SleepMode::PowerDown)),
+        (0b0111, State::Sleeping(SleepMode::PowerSave)),
+        (0b1101, State::Sleeping(SleepMode::Standby)),
+        (0b1111, State::Sleeping(SleepMode::ExtendedStandby)),
+        // Reserved
+        (0b1001, State::Running),
+        (0b1011, State::Running),
+        // SE is cleared.
+        (0b0100, State::Running),
+    ];
+    for (pinc, state) in cases.iter() {
+        let avr = start(*pinc);
+        assert_eq!(avr.state(), *state, "SMCR: {:04b}", pinc);
+    }
+}
+
+#[test]
+fn running_peripherals() {
+    // clkI/O runs Timer0 in Idle, clkADC runs the conversion started by
+    // ADC Noise Reduction, and the other modes are woken up by nothing.
+    // Timer0 overflows after the ADC has woken the MCU up.
+    let cases = [
+        (0b0001, 0b001, State::Running),
+        (0b0011, 0b011, State::Running),
+        (0b0101, 0, State::Sleeping(SleepMode::PowerDown)),
+        (0b0111, 0, State::Sleeping(SleepMode::PowerSave)),
+        (0b1101, 0, State::Sleeping(SleepMode::Standby)),
+        (0b1111, 0, State::Sleeping(SleepMode::ExtendedStandby)),
+    ];
+    for (pinc, isr, state) in cases.iter() {
+        let mut avr = start(*pinc);
+        for _ in 0..1_000 {
+            avr.next();
+        }
+        assert_eq!(portb(&avr), *isr, "SMCR: {:04b}", pinc);
+        assert_eq!(avr.state(), *state, "SMCR: {:04b}", pinc);
+    }
+}
+
+#[test]
+fn power_reduction() {
+    // PRTIM0 stops Timer0 in Idle.
+    let mut avr = start(0b1_0001);
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0);
+    assert_eq!(avr.state(), State::Sleeping(SleepMode::Idle));
+}
+
+#[test]
+fn wake_up_latency() {
+    // 1 sleeping cycle, 4 cycles of the interrupt response and 4 cycles to
+    // wake up.
+    let mut avr = start(0b0001);
+    assert_eq!(wake_up_cycles(&mut avr), 9);
+    assert_eq!(portb(&avr), 1);
+
+    let mut avr = start(0b0011);
+    assert_eq!(wake_up_cycles(&mut avr), 9);
+    assert_eq!(portb(&avr), 2);
+
+    // The low level of INT0 wakes the MCU up from Power-down after the
+    // start-up time of 16K CK selected by the default fuses.
+    let mut avr = start(0b0101);
+    let mut states = vec![None; 28];
+    states[3] = Some(false);
+    avr.set_pin_states(states);
+    assert_eq!(wake_up_cycles(&mut avr), 9 + 16_384);
+    assert_eq!(portb(&avr) & 0b100, 0b100);
+}
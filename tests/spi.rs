@@ -0,0 +1,133 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+use avr_emulator::spi::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+mod common;
+use common::*;
+
+// SS, MOSI and SCK are outputs, SPE = MSTR = 1 (clk_io / 4).
+// Pull SS low, send 0x41, send back the received byte, then pull SS high.
+const MASTER_HEX: &str = ":100000000CE204B904E005B900E50CBD2A9801E44E
+:100010000EBD1DB517FFFDCF2EB52EBD1DB517FFAB
+:06002000FDCF2A9AFFCF7C
+:00000001FF";
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Select(bool),
+    Transfer(u8),
+}
+
+// Returns the received byte plus one.
+struct Increment {
+    events: Rc<RefCell<Vec<Event>>>,
+}
+
+impl SpiDevice for Increment {
+    fn select(&mut self, selected: bool) {
+        self.events.borrow_mut().push(Event::Select(selected));
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        self.events.borrow_mut().push(Event::Transfer(mosi));
+        mosi.wrapping_add(1)
+    }
+}
+
+#[test]
+fn exchange_bytes_with_device() {
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(MASTER_HEX.to_string()).unwrap();
+    avr.attach_spi_device(Box::new(Increment {
+        events: Rc::clone(&events),
+    }));
+    avr.initialize();
+
+    // SCK (pin 19) is idle low while SS is high.
+    for _ in 0..8 {
+        avr.next();
+    }
+    assert!(!avr.get_pins()[18]);
+
+    for _ in 0..200 {
+        avr.next();
+    }
+    assert_eq!(
+        *events.borrow(),
+        vec![
+            Event::Select(true),
+            Event::Transfer(0x41),
+            Event::Transfer(0x42),
+            Event::Select(false),
+        ]
+    );
+}
+
+// Slave with the SPI interrupt. MISO is an output and 0xa5 is sent first.
+// The ISR outputs each received byte to PORTD and replies it plus one to
+// the next transfer.
+const SLAVE_HEX: &str = ":0200000033C00B
+:020044001BC0DF
+:080068000FEF0AB900E104B931
+:1000700000EC0CBD05EA0EBD7894FFCF4EB54BB930
+:0600800043954EBD1895EA
+:00000001FF";
+
+// The master sends the bytes pushed to `mosi` and records the replies.
+struct Master {
+    mosi: Rc<RefCell<VecDeque<u8>>>,
+    miso: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SpiDevice for Master {
+    fn transfer(&mut self, _mosi: u8) -> u8 {
+        0xff
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.mosi.borrow_mut().pop_front()
+    }
+
+    fn receive(&mut self, miso: u8) {
+        self.miso.borrow_mut().push(miso);
+    }
+
+    fn sck_cycles(&self) -> u64 {
+        8
+    }
+}
+
+#[test]
+fn slave_with_interrupt() {
+    let mosi = Rc::new(RefCell::new(VecDeque::new()));
+    let miso = Rc::new(RefCell::new(vec![]));
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(SLAVE_HEX.to_string()).unwrap();
+    avr.attach_spi_device(Box::new(Master {
+        mosi: Rc::clone(&mosi),
+        miso: Rc::clone(&miso),
+    }));
+    avr.initialize();
+    for _ in 0..100 {
+        avr.next();
+    }
+
+    // Nothing is exchanged while SS (PB2, pin 16) is high.
+    set_pin(&mut avr, 15, Some(true));
+    mosi.borrow_mut().push_back(0x3c);
+    set_pin(&mut avr, 15, Some(true));
+    assert!(miso.borrow().is_empty());
+
+    set_pin(&mut avr, 15, Some(false));
+    assert_eq!(*miso.borrow(), vec![0xa5]);
+    assert_eq!(portd(&avr), 0x3c);
+
+    mosi.borrow_mut().push_back(0x77);
+    set_pin(&mut avr, 15, Some(false));
+    assert_eq!(*miso.borrow(), vec![0xa5, 0x3d]);
+    assert_eq!(portd(&avr), 0x77);
+}
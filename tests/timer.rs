@@ -0,0 +1,350 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// Timer0 fast PWM, non-inverting OC0A (PD6) with OCR0A = 64 and no
+// prescaling, like `analogWrite(6, 64)`.
+const FAST_PWM_HEX: &str = ":1000000000E40AB900E407BD03E804BD01E005BD52
+:02001000FFCF20
+:00000001FF";
+
+// Timer1 CTC with OCR1A = 99, toggle OC1A (PB1) on compare match.
+const CTC_TOGGLE_HEX: &str = ":1000000002E004B900E00093890003E60093880051
+:0E00100000E40093800009E000938100FFCF20
+:00000001FF";
+
+// Timer1 without prescaling captures a rising edge and a falling edge of
+// PB0 driven by the firmware, waiting for ICF1 each time, and outputs the
+// pulse width to PORTD.
+const PULSE_WIDTH_HEX: &str = ":1000000001E40093810001E004B90FEF0AB9289AD6
+:10001000B59BFECF209186003091870001E00093D0
+:10002000810000E206BB12E31A95F1F72898B59B10
+:10003000FECF4091860050918700421B530B4BB975
+:02004000FFCFF0
+:00000001FF";
+
+// With the noise canceller, a 2-cycle pulse on PB0 is not captured and
+// TIFR1 + 1 is output to PORTD. Then TIFR1 after a rising edge is output.
+const NOISE_CANCELER_HEX: &str = ":1000000001E004B90FEF0AB901EC00938100289ACE
+:1000100028981AE01A95F1F746B343954BB91FEFAC
+:100020001A95F1F7289A1AE01A95F1F746B34BB9E9
+:02003000FFCF00
+:00000001FF";
+
+// Timer0 in CTC mode toggles OC0B (PD5) every 10 cycles, which clocks
+// Timer1 on the rising edges of T1 (PD5). TCNT1 counted for about 600
+// cycles is output to PORTB.
+const EXTERNAL_CLOCK_HEX: &str = ":100000000FE304B900E20AB909E007BD00E008BD4A
+:1000100002E104BD07E00093810001E005BD18EC9A
+:100020001A95F1F700E0009381004091840045B9F2
+:02003000FFCF00
+:00000001FF";
+
+#[test]
+fn fast_pwm() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(FAST_PWM_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // The loop takes 2 cycles, so that the pin is sampled every 2 cycles.
+    let mut high = 0;
+    for _ in 0..25_600 {
+        avr.next();
+        if avr.get_pins()[11] {
+            high += 1;
+        }
+    }
+    // 64 / 256
+    assert!((6_300..6_500).contains(&high), "high: {}", high);
+}
+
+#[test]
+fn ctc_toggle() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(CTC_TOGGLE_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // OC1A toggles every 100 cycles.
+    let mut rising_edges = 0;
+    let mut last = avr.get_pins()[14];
+    for _ in 0..10_000 {
+        avr.next();
+        let pin = avr.get_pins()[14];
+        if pin && !last {
+            rising_edges += 1;
+        }
+        last = pin;
+    }
+    assert!(
+        (99..=101).contains(&rising_edges),
+        "edges: {}",
+        rising_edges
+    );
+}
+
+#[test]
+fn input_capture() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PULSE_WIDTH_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // 166 cycles between the SBI and the CBI
+    assert_eq!(portd(&avr), 166);
+}
+
+#[test]
+fn noise_canceler() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(NOISE_CANCELER_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..200 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 1);
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // ICF1
+    assert_eq!(portd(&avr), 0x20);
+}
+
+// Timer2 clocked by the 32.768 kHz crystal without prescaling. TOV2 wakes
+// the MCU up from Power-save and the interrupts are counted on PORTB. ASSR
+// right after writing TCCR2B is output to PORTD.
+const ASYNC_TIMER2_HEX: &str = ":020000001FC01F
+:06002400439545B9189553
+:100040000FEF04B90AB900E20093B60001E0009393
+:10005000B1001091B6001BB91091B6001F71E1F705
+:1000600001E00093700007E003BF78948895FECF0D
+:00000001FF";
+
+#[test]
+fn async_timer2() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(ASYNC_TIMER2_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // AS2 and TCR2BUB
+    assert_eq!(portd(&avr), 0b0010_0001);
+    assert_eq!(portb(&avr), 0);
+
+    // TCNT2 overflows every 256 / 32.768 kHz = 125,000 cycles at 16 MHz.
+    for _ in 0..400_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 3);
+}
+
+#[test]
+fn external_clock() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(EXTERNAL_CLOCK_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // A rising edge every 20 cycles
+    let count = portb(&avr);
+    assert!((29..=31).contains(&count), "count: {}", count);
+}
+
+// Timer0 sets OCF0A and OCF0B in CTC mode, and Timer2 sets TOV2, OCF2A
+// and OCF2B in normal mode. OCF0A is cleared by writing TIFR0 and TOV2 by
+// SBI before their interrupts are enabled. TIFR0 | TIFR2 << 4 is output
+// to PORTD and the number of interrupts taken to PORTB.
+const CLEAR_FLAGS_HEX: &str = ":040000000C94340028
+:0200240049C0D1
+:020038003FC0C7
+:080068000FEF0AB904B909E029
+:1000700007BD02E004BD01E005BDA99BFECF00E085
+:1000800005BD01E00093B100B89BFECF00E00093F6
+:10009000B10002E005BBB89A02E000936E0001E0F7
+:1000A0000093700078940000000005B317B3129518
+:0C00B000012B0BB945B9FFCF4395189503
+:00000001FF";
+
+#[test]
+fn clear_flags() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(CLEAR_FLAGS_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    assert_eq!(portd(&avr), 0b0110_0100);
+    assert_eq!(portb(&avr), 0);
+}
+
+// Timer1 sets OCF1A, OCF1B and TOV1 from TCNT1 = 0xfff0 with OCR1A = 0xfff5
+// and OCR1B = 0xfff8. After it is stopped, `sbi TIFR1, OCF1A` and
+// `cbi TIFR1, TOV1` are executed and TIFR1 is output to PORTB.
+const CLEAR_TIFR1_HEX: &str = ":100000000FE304B90FEF0093850000EF0093840025
+:100010000FEF0093890005EF009388000FEF009326
+:100020008B0008EF00938A0001E000938100B09BF1
+:10003000FECF00E000938100B19AB09806B305B9F5
+:02004000FFCFF0
+:00000001FF";
+
+#[test]
+fn clear_tifr1() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(CLEAR_TIFR1_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // OCF1B and TOV1 are kept.
+    assert_eq!(portb(&avr), 0b101);
+}
+
+// Timer1 sets TOV1 from TCNT1 = 0xfff8, then PRTIM1 in PRR stops it.
+// TIFR1 is output to PORTD, and to PORTB after 0xff is written to TIFR1.
+const PRR_TIFR1_HEX: &str = ":100000000FEF04B90AB90093850008EF009384004C
+:1000100001E000938100B09BFECF08E000936400F4
+:1000200016B31BB90FEF06BB000016B315B9FFCF0F
+:00000001FF";
+
+#[test]
+fn clear_tifr1_while_stopped_by_prr() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PRR_TIFR1_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..1_000 {
+        avr.next();
+    }
+    // OCF1A, OCF1B (OCR1A = OCR1B = 0) and TOV1 are cleared.
+    assert_eq!(portd(&avr), 0b111);
+    assert_eq!(portb(&avr), 0);
+}
+
+// Count the samples of pin `p` (0-origin) being high in `steps` steps.
+fn count_high(avr: &mut ATmega328P, p: usize, steps: usize) -> usize {
+    (0..steps)
+        .filter(|_| {
+            avr.next();
+            avr.get_pins()[p]
+        })
+        .count()
+}
+
+// Timer0 phase correct PWM without prescaling, non-inverting OC0A (PD6)
+// and inverting OC0B (PD5) with OCR0A = OCR0B = 64.
+const PHASE_CORRECT_PWM_HEX: &str = ":1000000000E60AB900E407BD08BD01EB04BD01E04C
+:0400100005BDFFCF5C
+:00000001FF";
+
+#[test]
+fn phase_correct_pwm() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(PHASE_CORRECT_PWM_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..1_000 {
+        avr.next();
+    }
+
+    // A period is 510 cycles and the output is high for 2 * 64 cycles, the
+    // pins are sampled every 2 cycles.
+    let high = count_high(&mut avr, 11, 25_500);
+    assert!((6_300..6_500).contains(&high), "high: {}", high);
+    let high = count_high(&mut avr, 10, 25_500);
+    assert!((19_000..19_200).contains(&high), "high: {}", high);
+}
+
+// Timer2 fast PWM without prescaling, non-inverting OC2A (PB3) with
+// OCR2A = 192 and inverting OC2B (PD3) with OCR2B = 64.
+const TIMER2_FAST_PWM_HEX: &str = ":1000000008E004B90AB900EC0093B30000E40093DF
+:10001000B40003EB0093B00001E00093B100FFCF08
+:00000001FF";
+
+#[test]
+fn timer2_fast_pwm() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(TIMER2_FAST_PWM_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..1_000 {
+        avr.next();
+    }
+
+    // OC2A is pin 17 and high for 192 / 256, OC2B is pin 5 and low for
+    // 64 / 256.
+    let high = count_high(&mut avr, 16, 25_600);
+    assert!((19_100..19_300).contains(&high), "high: {}", high);
+    let high = count_high(&mut avr, 4, 25_600);
+    assert!((19_100..19_300).contains(&high), "high: {}", high);
+}
+
+// Timer0 in normal mode with OCR0A = 100 sets OC0A (PD6) on compare match.
+// After OCF0A is set, 1 is output to PORTB and OC0A is cleared on the next
+// compare match, then 2 is output.
+const SET_CLEAR_ON_MATCH_HEX: &str = ":100000000FEF04B900E40AB904E607BD00EC04BD33
+:1000100001E005BDA99BFECFA99A01E005B900E862
+:0C00200004BDA99BFECF02E005B9FFCF94
+:00000001FF";
+
+#[test]
+fn set_and_clear_on_compare_match() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(SET_CLEAR_ON_MATCH_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    for _ in 0..20 {
+        avr.next();
+    }
+    assert!(!avr.get_pins()[11]);
+    while portb(&avr) != 1 {
+        avr.next();
+    }
+    assert!(avr.get_pins()[11]);
+    while portb(&avr) != 2 {
+        avr.next();
+    }
+    assert!(!avr.get_pins()[11]);
+}
+
+// OCRnx equal to TOP or BOTTOM. Timer0 in phase correct PWM with
+// non-inverting OC0A (PD6, OCR0A = 0xff) and OC0B (PD5, OCR0B = 0), and
+// Timer2 in fast PWM with non-inverting OC2A (PB3, OCR2A = 0xff) and
+// inverting OC2B (PD3, OCR2B = 0xff).
+const OCR_AT_TOP_HEX: &str = ":1000000008E60AB908E004B90FEF07BD0093B30092
+:100010000093B40000E008BD01EA04BD03EB0093C7
+:0C002000B00001E005BD0093B100FFCF6F
+:00000001FF";
+
+#[test]
+fn ocr_at_top() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(OCR_AT_TOP_HEX.to_string()).unwrap();
+    avr.initialize();
+    for _ in 0..1_000 {
+        avr.next();
+    }
+
+    // The outputs are constant.
+    for _ in 0..2_560 {
+        avr.next();
+        let pins = avr.get_pins();
+        assert_eq!(
+            (pins[11], pins[10], pins[16], pins[4]),
+            (true, false, true, false)
+        );
+    }
+}
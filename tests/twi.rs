@@ -0,0 +1,142 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+use avr_emulator::twi::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+mod common;
+use common::*;
+
+// TWBR = 72 (100 kHz at 16 MHz). Write 0x2a to register 0x10 of the slave
+// 0x50, set the register pointer to 0x10 again and read it after a repeated
+// START. The read byte is output to PORTB.
+const MASTER_HEX: &str = ":100000000FEF04B908E40093B80004EA0093BC00C1
+:1000100057D000EA0093BB0004E80093BC0050D026
+:1000200000E10093BB0004E80093BC0049D00AE261
+:100030000093BB0004E80093BC0042D004EA0093A4
+:10004000BC003ED000EA0093BB0004E80093BC0073
+:1000500037D000E10093BB0004E80093BC0030D02F
+:1000600004EA0093BC002CD001EA0093BB0004E832
+:100070000093BC0025D004E80093BC0021D040913F
+:0C008000BB0045B904E90093BC00FFCFB1
+:0A00C0001091BC0017FFFCCF08955B
+:00000001FF";
+
+// TWAR = 0x40 (slave address 0x20), TWEA = TWEN = 1.
+// A received byte is output to PORTB and transmitted back when read.
+const SLAVE_HEX: &str = ":100000000FEF04B900E40093BA0004EC0093BC00C5
+:100010001091BC0017FFFCCF2091B900287F203839
+:1000200019F44091BB0045B9283A11F44093BB0044
+:0800300004EC0093BC00ECCFCE
+:00000001FF";
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Start(bool),
+    Write(u8),
+    Read(u8),
+    Stop,
+}
+
+// A memory with a register pointer, e.g. an EEPROM or an RTC.
+struct Memory {
+    data: [u8; 256],
+    pointer: Option<u8>,
+    events: Rc<RefCell<Vec<Event>>>,
+}
+
+impl TwiDevice for Memory {
+    fn address(&self) -> u8 {
+        0x50
+    }
+
+    // The first byte written after SLA+W sets the register pointer.
+    fn start(&mut self, read: bool) -> bool {
+        self.events.borrow_mut().push(Event::Start(read));
+        if !read {
+            self.pointer = None;
+        }
+        true
+    }
+
+    fn write(&mut self, data: u8) -> bool {
+        self.events.borrow_mut().push(Event::Write(data));
+        match self.pointer {
+            None => self.pointer = Some(data),
+            Some(p) => {
+                self.data[p as usize] = data;
+                self.pointer = Some(p.wrapping_add(1));
+            }
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let p = self.pointer.unwrap_or(0);
+        self.pointer = Some(p.wrapping_add(1));
+        self.events
+            .borrow_mut()
+            .push(Event::Read(self.data[p as usize]));
+        self.data[p as usize]
+    }
+
+    fn stop(&mut self) {
+        self.events.borrow_mut().push(Event::Stop);
+        self.pointer = None;
+    }
+}
+
+#[test]
+fn master_write_and_read() {
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(MASTER_HEX.to_string()).unwrap();
+    avr.attach_twi_device(Box::new(Memory {
+        data: [0; 256],
+        pointer: None,
+        events: Rc::clone(&events),
+    }));
+    avr.initialize();
+
+    for _ in 0..20_000 {
+        avr.next();
+    }
+    assert_eq!(
+        *events.borrow(),
+        vec![
+            Event::Start(false),
+            Event::Write(0x10),
+            Event::Write(0x2a),
+            Event::Start(false),
+            Event::Write(0x10),
+            Event::Start(true),
+            Event::Read(0x2a),
+            Event::Stop,
+        ]
+    );
+    assert_eq!(portb(&avr), 0x2a);
+}
+
+#[test]
+fn slave_receive_and_transmit() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(SLAVE_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    avr.push_twi_transaction(TwiTransaction::Write(0x20, vec![0x15]));
+    avr.push_twi_transaction(TwiTransaction::Read(0x20, 1));
+    // Not addressed
+    avr.push_twi_transaction(TwiTransaction::Read(0x21, 1));
+    for _ in 0..20_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0x15);
+    assert_eq!(avr.drain_twi_read(), vec![0x15]);
+
+    // The master does not acknowledge the only byte of a read of 0 bytes.
+    avr.push_twi_transaction(TwiTransaction::Read(0x20, 0));
+    for _ in 0..20_000 {
+        avr.next();
+    }
+    assert_eq!(avr.drain_twi_read(), vec![0x15]);
+}
@@ -0,0 +1,73 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+// UBRR0 = 103 (9600 baud at 16 MHz), TXEN0 = 1
+// Send 'H', wait for UDRE0, then send 'i'.
+const TRANSMIT_HEX: &str = ":1000000007E60093C40008E00093C10008E40093F1
+:10001000C6001091C00015FFFCCF09E60093C60092
+:02002000FFCF10
+:00000001FF";
+
+// UBRR0 = 0, RXEN0 = TXEN0 = RXCIE0 = 1, SEI
+// The USART RX interrupt echoes UDR0 back.
+const ECHO_HEX: &str = ":0200000033C00B
+:020048001FC0D7
+:0A00680008E90093C1007894FFCF6F
+:0A0088000091C6000093C600189511
+:00000001FF";
+
+fn run(avr: &mut ATmega328P, steps: usize) {
+    for _ in 0..steps {
+        avr.next();
+    }
+}
+
+#[test]
+fn transmit_at_baud_rate() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(TRANSMIT_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    // A frame of 10 bits takes 16 * 104 cycles per bit. 'i' is written to
+    // the transmit buffer while 'H' is shifted out, then the program stays
+    // in a loop of 2 cycles per instruction.
+    run(&mut avr, 8_000);
+    assert_eq!(avr.drain_usart_tx(), b"");
+    run(&mut avr, 1_000);
+    assert_eq!(avr.drain_usart_tx(), b"H");
+    run(&mut avr, 7_000);
+    assert_eq!(avr.drain_usart_tx(), b"");
+    run(&mut avr, 1_000);
+    assert_eq!(avr.drain_usart_tx(), b"i");
+}
+
+#[test]
+fn echo_received_bytes() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(ECHO_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    avr.push_usart_rx(b"hello");
+    run(&mut avr, 2_000);
+    assert_eq!(avr.drain_usart_tx(), b"hello");
+}
+
+// UBRR0 = 0, 9-bit frames (UCSZ02:0 = 0b111), RXEN0 = TXEN0 = 1
+// Each received frame is sent back with the 9th bit inverted. RXB80 is
+// read before UDR0 and TXB80 is written before UDR0.
+const NINE_BIT_HEX: &str = ":1000000000E00093C5000093C40006E00093C20026
+:100010000CE10093C1001091C00017FFFCCF2091AC
+:10002000C1003091C6000CE121FF01600093C100C6
+:060030003093C600F0CF82
+:00000001FF";
+
+#[test]
+fn nine_bit_frames() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(NINE_BIT_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    avr.push_usart_rx_9bit(&[0x141, 0x042, 0x1ff]);
+    run(&mut avr, 2_000);
+    assert_eq!(avr.drain_usart_tx_9bit(), vec![0x041, 0x142, 0x0ff]);
+}
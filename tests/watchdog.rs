@@ -0,0 +1,104 @@
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::*;
+
+mod common;
+use common::*;
+
+// Output MCUSR to PORTB. Enable the watchdog system reset mode (16 ms) on
+// power-on, then clear MCUSR and stop the watchdog with the timed sequence
+// after the watchdog reset.
+const RESET_HEX: &str = ":100000000FEF04B914B715B913FD04C008E000934D
+:100010006000FFCF00E004BF08E10093600000E053
+:0600200000936000FFCF19
+:00000001FF";
+
+// Enable the watchdog interrupt mode (16 ms) and count the interrupts on PORTB.
+const INTERRUPT_HEX: &str = ":020000000FC02F
+:06001800439545B918955F
+:0E0020000FEF04B900E4009360007894FFCF66
+:00000001FF";
+
+// Enable the watchdog interrupt and system reset mode (16 ms), with SEI
+// only if PC0 is high. The interrupts are counted on PORTB and MCUSR is
+// output to PORTD. The watchdog is stopped after the watchdog reset.
+const INTERRUPT_AND_RESET_HEX: &str = ":020000000FC02F
+:0800180025B1239525B91895C7
+:100020000FEF04B90AB914B71BB913FD06C008E4F1
+:100030000093600030997894FFCF00E004BF08E19E
+:0C0040000093600000E000936000FFCF20
+:00000001FF";
+
+#[test]
+fn system_reset() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(RESET_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    // PORF
+    for _ in 0..100 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0b0001);
+
+    // The watchdog times out after 2K cycles of the 128 kHz oscillator.
+    for _ in 0..150_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0b1001);
+
+    // No more resets
+    for _ in 0..300_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0b1001);
+}
+
+#[test]
+fn interrupt() {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(INTERRUPT_HEX.to_string()).unwrap();
+    avr.initialize();
+
+    // 256,000 cycles at 16 MHz
+    for _ in 0..100_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 0);
+    for _ in 0..200_000 {
+        avr.next();
+    }
+    assert_eq!(portb(&avr), 2);
+}
+
+fn run_interrupt_and_reset(sei: bool) -> (u8, u8, u8) {
+    let mut avr = ATmega328P::new(Package::PDIP28);
+    avr.program(INTERRUPT_AND_RESET_HEX.to_string()).unwrap();
+    // PC0 is pin 23.
+    let mut states = vec![None; 28];
+    states[22] = Some(sei);
+    avr.set_pin_states(states);
+    avr.initialize();
+
+    // 400,000 cycles at 16 MHz, after the first time-out
+    for _ in 0..200_000 {
+        avr.next();
+    }
+    let (interrupts, mcusr) = (portb(&avr), portd(&avr));
+    // After the second time-out
+    for _ in 0..100_000 {
+        avr.next();
+    }
+    (interrupts, mcusr, portd(&avr))
+}
+
+#[test]
+fn interrupt_and_system_reset() {
+    // The interrupt clears WDIE, so that the next time-out resets the MCU.
+    assert_eq!(run_interrupt_and_reset(true), (1, 0b0001, 0b1001));
+}
+
+#[test]
+fn system_reset_with_pending_interrupt() {
+    // A time-out while WDIF is still set resets the MCU.
+    assert_eq!(run_interrupt_and_reset(false), (0, 0b0001, 0b1001));
+}